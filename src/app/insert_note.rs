@@ -1,51 +1,167 @@
-use std::sync::mpsc::{channel, Sender};
-
 use ratatui::{
     buffer::Buffer,
-    crossterm::event::{KeyCode, KeyEvent},
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
     layout::{Alignment, Rect},
     style::Stylize,
     symbols::border,
     text::{Line, Text},
-    widgets::{block::Title, Block, Paragraph, Widget},
+    widgets::{
+        block::{Position, Title},
+        Block, Paragraph, Widget,
+    },
 };
 use ulid::Ulid;
 
-use crate::{gatherer::app_gatherer::ActiveProcessEvent, StateMachine};
+use rarian::{
+    bus::{AddNote, Bus, EditNote},
+    error::{RarianError, Result},
+};
 
 pub enum InputMode {
     Normal,
     Editing,
+    Timeline,
+    Detail,
+    Tasks,
+}
+
+/// A row of the wrapped text, as the index of its first character and its length in characters
+type Row = (usize, usize);
+
+/// Splits `text` into the rows it takes up in an area `width` characters wide, breaking lines at
+/// the last whitespace that fits or in the middle of a word that is too long.
+pub fn wrap_rows(text: &str, width: usize) -> Vec<Row> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut line_start = 0;
+    for line in text.split('\n') {
+        let chars: Vec<char> = line.chars().collect();
+        let mut offset = 0;
+        loop {
+            let remaining = chars.len() - offset;
+            let row_len = if remaining <= width {
+                remaining
+            } else {
+                chars[offset..offset + width]
+                    .iter()
+                    .rposition(|c| c.is_whitespace())
+                    .map_or(width, |space| space + 1)
+            };
+            rows.push((line_start + offset, row_len));
+            offset += row_len;
+            if offset >= chars.len() {
+                break;
+            }
+        }
+        // A full last row leaves no room for the cursor, so it continues on an empty row
+        if rows.last().is_some_and(|(_, len)| *len == width) {
+            rows.push((line_start + chars.len(), 0));
+        }
+        line_start += chars.len() + 1;
+    }
+    rows
+}
+
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    wrap_rows(text, width)
+        .into_iter()
+        .map(|(start, len)| chars[start..start + len].iter().collect())
+        .collect()
 }
 
 pub struct InsertWindow {
-    state_machine_tx: Sender<StateMachine>,
+    bus: Bus,
     pub input: String,
     pub character_index: usize,
     pub editing_note: Option<Ulid>,
+    /// What a new note is linked to
+    pub note_link: Option<String>,
+    width: usize,
+    scroll: usize,
+    cursor_position: Option<(u16, u16)>,
+    undo_stack: Vec<(String, usize)>,
+    redo_stack: Vec<(String, usize)>,
+    typing: bool,
+    /// The error from the last failed save, for the TUI to show
+    pub error: Option<RarianError>,
 }
 
 impl InsertWindow {
-    pub fn new(state_machine_tx: Sender<StateMachine>) -> InsertWindow {
+    pub fn new(bus: Bus) -> InsertWindow {
         InsertWindow {
-            state_machine_tx,
+            bus,
             input: String::new(),
             character_index: 0,
             editing_note: None,
+            note_link: None,
+            width: 80,
+            scroll: 0,
+            cursor_position: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            typing: false,
+            error: None,
         }
     }
 
+    pub fn edit(&mut self, note_id: Ulid, text: &str) {
+        self.editing_note = Some(note_id);
+        self.input = text.to_string();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.scroll = 0;
+        self.move_cursor_to_end();
+    }
+
+    /// Where the cursor was drawn in the last render
+    pub fn cursor_position(&self) -> Option<(u16, u16)> {
+        self.cursor_position
+    }
+
     fn move_cursor_right(&mut self) {
         let cursor_moved_right = self.character_index.saturating_add(1);
         self.character_index = self.clamp_cursor(cursor_moved_right);
     }
 
     fn move_cursor_to_end(&mut self) {
-        let cursor_moved_to_end = self.input.len();
+        let cursor_moved_to_end = self.input.chars().count();
         self.character_index = self.clamp_cursor(cursor_moved_to_end);
     }
 
+    /// Saves the text before it is changed so the change can be undone. Consecutive typing is
+    /// saved once per word.
+    fn save_undo(&mut self, typing: bool) {
+        if !(typing && self.typing) {
+            self.undo_stack
+                .push((self.input.clone(), self.character_index));
+            self.redo_stack.clear();
+        }
+        self.typing = typing;
+    }
+
+    fn undo(&mut self) {
+        if let Some((input, index)) = self.undo_stack.pop() {
+            self.redo_stack
+                .push((self.input.clone(), self.character_index));
+            self.input = input;
+            self.character_index = index;
+        }
+        self.typing = false;
+    }
+
+    fn redo(&mut self) {
+        if let Some((input, index)) = self.redo_stack.pop() {
+            self.undo_stack
+                .push((self.input.clone(), self.character_index));
+            self.input = input;
+            self.character_index = index;
+        }
+        self.typing = false;
+    }
+
     fn enter_char(&mut self, new_char: char) {
+        self.save_undo(!new_char.is_whitespace());
         let index = self.byte_index();
         self.input.insert(index, new_char);
         self.move_cursor_right();
@@ -69,8 +185,82 @@ impl InsertWindow {
     }
 
     fn move_cursor_home(&mut self) {
-        let cursor_moved_home = 0;
-        self.character_index = self.clamp_cursor(cursor_moved_home);
+        let rows = wrap_rows(&self.input, self.width);
+        let (row_index, _) = self.cursor_row_index(&rows);
+        self.character_index = rows[row_index].0;
+    }
+
+    fn move_cursor_to_row_end(&mut self) {
+        let rows = wrap_rows(&self.input, self.width);
+        let (row_index, _) = self.cursor_row_index(&rows);
+        self.character_index = rows[row_index].0 + self.row_max_column(&rows, row_index);
+    }
+
+    fn move_cursor_vertically(&mut self, up: bool) {
+        let rows = wrap_rows(&self.input, self.width);
+        let (row_index, column) = self.cursor_row_index(&rows);
+        let target = match up {
+            true if row_index > 0 => row_index - 1,
+            false if row_index + 1 < rows.len() => row_index + 1,
+            _ => return,
+        };
+        self.character_index = rows[target].0 + column.min(self.row_max_column(&rows, target));
+    }
+
+    /// The last column the cursor can be in on a row. On rows that wrap the column after the last
+    /// character is already the start of the next row.
+    fn row_max_column(&self, rows: &[Row], row_index: usize) -> usize {
+        let (start, len) = rows[row_index];
+        match rows.get(row_index + 1) {
+            Some((next_start, _)) if *next_start == start + len && len > 0 => len - 1,
+            _ => len,
+        }
+    }
+
+    fn cursor_row_index(&self, rows: &[Row]) -> (usize, usize) {
+        let row_index = rows
+            .iter()
+            .rposition(|(start, _)| *start <= self.character_index)
+            .unwrap_or(0);
+        (row_index, self.character_index - rows[row_index].0)
+    }
+
+    /// The index of the start of the word before the cursor
+    fn previous_word_index(&self) -> usize {
+        let chars: Vec<char> = self.input.chars().collect();
+        let mut index = self.character_index;
+        while index > 0 && chars[index - 1].is_whitespace() {
+            index -= 1;
+        }
+        while index > 0 && !chars[index - 1].is_whitespace() {
+            index -= 1;
+        }
+        index
+    }
+
+    /// The index of the start of the word after the cursor
+    fn next_word_index(&self) -> usize {
+        let chars: Vec<char> = self.input.chars().collect();
+        let mut index = self.character_index;
+        while index < chars.len() && !chars[index].is_whitespace() {
+            index += 1;
+        }
+        while index < chars.len() && chars[index].is_whitespace() {
+            index += 1;
+        }
+        index
+    }
+
+    /// Deletes the characters between `from` and the cursor and moves the cursor to `from`
+    fn delete_back_to(&mut self, from: usize) {
+        if from >= self.character_index {
+            return;
+        }
+        self.save_undo(false);
+        let before = self.input.chars().take(from);
+        let after = self.input.chars().skip(self.character_index);
+        self.input = before.chain(after).collect();
+        self.character_index = from;
     }
 
     fn delete_char(&mut self) {
@@ -79,19 +269,7 @@ impl InsertWindow {
             // Method "remove" is not used on the saved text for deleting the selected char.
             // Reason: Using remove on String works on bytes instead of the chars.
             // Using remove would require special care because of char boundaries.
-
-            let current_index = self.character_index;
-            let from_left_to_current_index = current_index - 1;
-
-            // Getting all characters before the selected character.
-            let before_char_to_delete = self.input.chars().take(from_left_to_current_index);
-            // Getting all characters after selected character.
-            let after_char_to_delete = self.input.chars().skip(current_index);
-
-            // Put all characters together except the selected one.
-            // By leaving the selected one out, it is forgotten and therefore deleted.
-            self.input = before_char_to_delete.chain(after_char_to_delete).collect();
-            self.move_cursor_left();
+            self.delete_back_to(self.character_index - 1);
         }
     }
 
@@ -103,59 +281,106 @@ impl InsertWindow {
         }
     }
 
+    fn delete_word(&mut self) {
+        self.delete_back_to(self.previous_word_index());
+    }
+
+    fn delete_to_line_start(&mut self) {
+        let line_start = self
+            .input
+            .chars()
+            .take(self.character_index)
+            .collect::<Vec<char>>()
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |newline| newline + 1);
+        self.delete_back_to(line_start);
+    }
+
     fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
         new_cursor_pos.clamp(0, self.input.chars().count())
     }
 
     fn reset_cursor(&mut self) {
         self.character_index = 0;
+        self.scroll = 0;
     }
 
-    fn submit_message(&mut self) {
+    fn submit_message(&mut self) -> Result<()> {
         match self.editing_note {
-            Some(note_id) => self.edit_note(note_id),
-            None => self.new_note(),
+            Some(note_id) => self.edit_note(note_id)?,
+            None => self.new_note()?,
         }
         self.input.clear();
         self.reset_cursor();
         self.editing_note = None;
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        Ok(())
     }
 
-    fn new_note(&self) {
-        let (tx, rx) = channel::<Option<ActiveProcessEvent>>();
-        self.state_machine_tx
-            .send(StateMachine::CurrentApp(tx))
-            .unwrap();
-        match rx.recv().expect("main thread is alive") {
-            Some(current) => {
-                self.state_machine_tx
-                    .send(StateMachine::NewNote(
-                        self.input.clone().trim().to_string(),
-                        vec![current.get_title().to_string()],
-                    ))
-                    .unwrap();
-            }
-            None => {
-                println!("What do we do when there is no current process?");
+    fn new_note(&self) -> Result<()> {
+        match &self.note_link {
+            Some(link) => {
+                self.bus.request(AddNote {
+                    text: self.input.trim().to_string(),
+                    links: vec![link.clone()],
+                })?;
+                Ok(())
             }
+            None => Ok(()),
         }
     }
 
-    fn edit_note(&self, note_id: Ulid) {
-        self.state_machine_tx
-            .send(StateMachine::EditNote(
-                note_id,
-                self.input.clone().trim().to_string(),
-            ))
-            .unwrap();
+    fn edit_note(&self, note_id: Ulid) -> Result<()> {
+        self.bus.request(EditNote {
+            id: note_id,
+            text: self.input.trim().to_string(),
+        })
+    }
+
+    fn handle_control_key_event(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Left => self.character_index = self.previous_word_index(),
+            KeyCode::Right => self.character_index = self.next_word_index(),
+            KeyCode::Char('w') | KeyCode::Backspace => self.delete_word(),
+            KeyCode::Char('u') => self.delete_to_line_start(),
+            KeyCode::Char('j') => self.enter_char('\n'),
+            KeyCode::Char('z') => self.undo(),
+            KeyCode::Char('y') => self.redo(),
+            KeyCode::Home => self.character_index = 0,
+            KeyCode::End => self.move_cursor_to_end(),
+            _ => {}
+        }
     }
 
     pub(crate) fn handle_key_event(&mut self, key_event: KeyEvent) -> InputMode {
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            // Moving the cursor ends a word, so typing after it is undone on its own
+            self.typing = false;
+            self.handle_control_key_event(key_event);
+            return InputMode::Editing;
+        }
+        if key_event.code != KeyCode::Enter && !matches!(key_event.code, KeyCode::Char(_)) {
+            self.typing = false;
+        }
         match key_event.code {
-            KeyCode::Enter => {
-                self.submit_message();
-                InputMode::Normal
+            KeyCode::Enter
+                if key_event
+                    .modifiers
+                    .intersects(KeyModifiers::ALT | KeyModifiers::SHIFT) =>
+            {
+                self.enter_char('\n');
+                InputMode::Editing
             }
+            KeyCode::Enter => match self.submit_message() {
+                Ok(()) => InputMode::Normal,
+                // Keep the text so it isn't lost while the error is shown
+                Err(err) => {
+                    self.error = Some(err);
+                    InputMode::Editing
+                }
+            },
             KeyCode::Char(to_insert) => {
                 self.enter_char(to_insert);
                 InputMode::Editing
@@ -176,8 +401,16 @@ impl InsertWindow {
                 self.move_cursor_right();
                 InputMode::Editing
             }
+            KeyCode::Up => {
+                self.move_cursor_vertically(true);
+                InputMode::Editing
+            }
+            KeyCode::Down => {
+                self.move_cursor_vertically(false);
+                InputMode::Editing
+            }
             KeyCode::End => {
-                self.move_cursor_to_end();
+                self.move_cursor_to_row_end();
                 InputMode::Editing
             }
             KeyCode::Home => {
@@ -190,14 +423,123 @@ impl InsertWindow {
     }
 }
 
-impl Widget for &InsertWindow {
+impl Widget for &mut InsertWindow {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let title = Title::from(" new note ".bold());
+        let title = match self.editing_note {
+            Some(_) => " edit note ",
+            None => " new note ",
+        };
         let block = Block::bordered()
-            .title(title.alignment(Alignment::Center))
+            .title(Title::from(title.bold()).alignment(Alignment::Center))
+            .title(
+                Title::from(" ENTER save, ALT+ENTER new line, CTRL+Z/Y undo/redo ")
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            )
             .border_set(border::THICK);
-        Paragraph::new(Text::from(Line::from(self.input.as_str())))
+        let inner = block.inner(area);
+        self.width = usize::from(inner.width);
+        let height = usize::from(inner.height).max(1);
+
+        let rows = wrap_rows(&self.input, self.width);
+        let (row_index, column) = self.cursor_row_index(&rows);
+        if row_index < self.scroll {
+            self.scroll = row_index;
+        } else if row_index >= self.scroll + height {
+            self.scroll = row_index + 1 - height;
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            self.cursor_position = Some((
+                inner.x + column as u16,
+                inner.y + (row_index - self.scroll) as u16,
+            ));
+        }
+
+        let chars: Vec<char> = self.input.chars().collect();
+        let lines: Vec<Line> = rows
+            .iter()
+            .skip(self.scroll)
+            .take(height)
+            .map(|(start, len)| Line::from(chars[*start..start + len].iter().collect::<String>()))
+            .collect();
+        Paragraph::new(Text::from(lines))
             .block(block)
             .render(area, buf);
     }
 }
+
+#[cfg(test)]
+mod insert_note_test {
+    use super::*;
+    use ratatui::crossterm::event::{KeyEventKind, KeyEventState};
+    use std::sync::mpsc::channel;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            state: KeyEventState::NONE,
+        }
+    }
+
+    fn type_text(window: &mut InsertWindow, text: &str) {
+        for c in text.chars() {
+            window.handle_key_event(key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn wrap_rows_breaks_at_whitespace_and_newlines() {
+        let text = "hello wide world\nab";
+        assert_eq!(wrap_text(text, 10), vec!["hello ", "wide world", "", "ab"]);
+        assert_eq!(wrap_rows("abcdef", 3), vec![(0, 3), (3, 3), (6, 0)]);
+    }
+
+    #[test]
+    fn cursor_moves_between_wrapped_rows() {
+        let (tx, _rx) = channel();
+        let mut window = InsertWindow::new(Bus::new(tx));
+        window.width = 6;
+        type_text(&mut window, "one two three");
+        window.handle_key_event(key(KeyCode::Up, KeyModifiers::NONE));
+        assert_eq!(window.character_index, 7);
+        window.handle_key_event(key(KeyCode::Home, KeyModifiers::NONE));
+        assert_eq!(window.character_index, 4);
+        window.handle_key_event(key(KeyCode::Up, KeyModifiers::NONE));
+        assert_eq!(window.character_index, 0);
+    }
+
+    #[test]
+    fn word_deletion_can_be_undone_and_redone() {
+        let (tx, _rx) = channel();
+        let mut window = InsertWindow::new(Bus::new(tx));
+        type_text(&mut window, "first line");
+        window.handle_key_event(key(KeyCode::Enter, KeyModifiers::ALT));
+        type_text(&mut window, "second word");
+        window.handle_key_event(key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(window.input, "first line\nsecond ");
+        window.handle_key_event(key(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert_eq!(window.input, "first line\n");
+        window.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        window.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        assert_eq!(window.input, "first line\nsecond word");
+        window.handle_key_event(key(KeyCode::Char('y'), KeyModifiers::CONTROL));
+        assert_eq!(window.input, "first line\nsecond ");
+        window.handle_key_event(key(KeyCode::Left, KeyModifiers::CONTROL));
+        assert_eq!(window.character_index, 11);
+    }
+
+    #[test]
+    fn typing_after_moving_by_word_is_undone_on_its_own() {
+        let (tx, _rx) = channel();
+        let mut window = InsertWindow::new(Bus::new(tx));
+        type_text(&mut window, "word");
+        window.handle_key_event(key(KeyCode::Left, KeyModifiers::CONTROL));
+        type_text(&mut window, "new");
+        assert_eq!(window.input, "newword");
+        window.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        assert_eq!(window.input, "word");
+    }
+}
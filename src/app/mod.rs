@@ -1,2 +1,5 @@
 mod insert_note;
+mod note_detail;
+mod tasks_window;
+mod timeline_window;
 pub mod tui;
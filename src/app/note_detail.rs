@@ -0,0 +1,221 @@
+use chrono::{DateTime, Local};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{palette::tailwind::BLUE, Modifier, Style, Stylize},
+    symbols::border,
+    widgets::{
+        block::{Position, Title},
+        Block, HighlightSpacing, List, ListItem, ListState, Paragraph, StatefulWidget, Widget,
+        Wrap,
+    },
+};
+use ulid::Ulid;
+
+use rarian::{
+    bus::{Bus, GetNoteLinks},
+    error::{RarianError, Result},
+    notes::{Note, NoteLinks, NoteStatus},
+};
+
+use super::insert_note::InputMode;
+
+/// A row of the links list, which only opens a note if `target` is set
+struct LinkRow {
+    label: String,
+    target: Option<Ulid>,
+    dimmed: bool,
+}
+
+fn note_row(prefix: &str, note: &Note) -> LinkRow {
+    let archived = note.status == NoteStatus::Archived;
+    let suffix = if archived { " (archived)" } else { "" };
+    LinkRow {
+        label: format!("{} {}{}", prefix, note.title(), suffix),
+        target: Some(note.id),
+        dimmed: archived,
+    }
+}
+
+fn header_row(label: &str) -> LinkRow {
+    LinkRow {
+        label: label.to_string(),
+        target: None,
+        dimmed: false,
+    }
+}
+
+pub struct NoteDetailWindow {
+    bus: Bus,
+    links: Option<NoteLinks>,
+    rows: Vec<LinkRow>,
+    /// The notes navigated away from, to go back to
+    history: Vec<Ulid>,
+    selected_row: ListState,
+    /// The error from the last note that failed to load, for the TUI to show
+    pub error: Option<RarianError>,
+}
+
+impl NoteDetailWindow {
+    pub fn new(bus: Bus) -> NoteDetailWindow {
+        NoteDetailWindow {
+            bus,
+            links: None,
+            rows: Vec::new(),
+            history: Vec::new(),
+            selected_row: ListState::default(),
+            error: None,
+        }
+    }
+
+    /// Shows a note opened from the notes pane, forgetting the notes navigated through before
+    pub fn open(&mut self, note_id: Ulid) {
+        self.history.clear();
+        self.show(note_id);
+    }
+
+    /// Reloads the shown note, as its text or links may have changed
+    pub fn refresh(&mut self) {
+        if let Some(note_id) = self.links.as_ref().map(|links| links.note.id) {
+            if let Err(err) = self.load(note_id) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn show(&mut self, note_id: Ulid) {
+        match self.load(note_id) {
+            Ok(()) => self.selected_row.select(None),
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    fn load(&mut self, note_id: Ulid) -> Result<()> {
+        let links = self.bus.request(GetNoteLinks { id: note_id })?;
+        let mut rows = vec![header_row("links to:")];
+        for reference in links.outgoing.iter() {
+            rows.push(match &reference.target {
+                Some(note) => note_row("  →", note),
+                None => LinkRow {
+                    label: format!("  → [[{}]] (missing)", reference.reference),
+                    target: None,
+                    dimmed: true,
+                },
+            });
+        }
+        rows.push(header_row("linked from:"));
+        rows.extend(links.backlinks.iter().map(|note| note_row("  ←", note)));
+        self.rows = rows;
+        self.links = Some(links);
+        Ok(())
+    }
+
+    fn follow_selected(&mut self) {
+        let target = self
+            .selected_row
+            .selected()
+            .and_then(|row| self.rows.get(row))
+            .and_then(|row| row.target);
+        let current = self.links.as_ref().map(|links| links.note.id);
+        if let (Some(target), Some(current)) = (target, current) {
+            self.history.push(current);
+            self.show(target);
+        }
+    }
+
+    fn go_back(&mut self) {
+        if let Some(previous) = self.history.pop() {
+            self.show(previous);
+        }
+    }
+
+    fn select_next(&mut self) {
+        match self.selected_row.selected() {
+            Some(row) if row + 1 < self.rows.len() => self.selected_row.select_next(),
+            None if !self.rows.is_empty() => self.selected_row.select_first(),
+            _ => {}
+        }
+    }
+
+    fn select_previous(&mut self) {
+        match self.selected_row.selected() {
+            Some(row) if row > 0 => self.selected_row.select_previous(),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn handle_key_event(&mut self, key_event: KeyEvent) -> InputMode {
+        match key_event.code {
+            KeyCode::Esc | KeyCode::Char('q') => return InputMode::Normal,
+            KeyCode::Char('j') | KeyCode::Down => self.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.select_previous(),
+            KeyCode::Enter | KeyCode::Char('l') | KeyCode::Right => self.follow_selected(),
+            KeyCode::Backspace | KeyCode::Char('h') | KeyCode::Left => self.go_back(),
+            _ => {}
+        }
+        InputMode::Detail
+    }
+}
+
+impl Widget for &mut NoteDetailWindow {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(area);
+        let (title, text) = match &self.links {
+            Some(links) => {
+                let time = links.note.updated_date.unwrap_or(links.note.creation_date);
+                let time = DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M");
+                let text = match &links.note.conflict {
+                    Some(conflict) => format!(
+                        "{}\n\n--- the other version from sync, until edited ---\n{}",
+                        links.note.text, conflict
+                    ),
+                    None => links.note.text.clone(),
+                };
+                (format!(" note {} ", time), text)
+            }
+            None => (" note ".to_string(), String::new()),
+        };
+        Paragraph::new(text)
+            .block(
+                Block::bordered()
+                    .title(Title::from(title.bold()).alignment(Alignment::Center))
+                    .border_set(border::THICK),
+            )
+            .wrap(Wrap { trim: false })
+            .render(layout[0], buf);
+
+        let block = Block::bordered()
+            .title(Title::from(" links ".bold()).alignment(Alignment::Center))
+            .title(
+                Title::from(" ENTER follow, BACKSPACE back, ESC close ")
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            )
+            .border_set(border::THICK);
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let item = ListItem::from(row.label.clone());
+                match (row.target, row.dimmed) {
+                    (_, true) => item.dim(),
+                    (None, false) => item.bold(),
+                    (Some(_), false) => item,
+                }
+            })
+            .collect();
+
+        const SELECTED_STYLE: Style = Style::new().bg(BLUE.c800).add_modifier(Modifier::BOLD);
+        let list = List::new(items)
+            .block(block)
+            .scroll_padding(1)
+            .highlight_style(SELECTED_STYLE)
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+        StatefulWidget::render(list, layout[1], buf, &mut self.selected_row);
+    }
+}
@@ -0,0 +1,258 @@
+use chrono::{Days, Local, NaiveDate};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::{Alignment, Rect},
+    style::{palette::tailwind::BLUE, Color, Modifier, Style, Stylize},
+    symbols::border,
+    widgets::{
+        block::{Position, Title},
+        Block, HighlightSpacing, List, ListItem, ListState, StatefulWidget, Widget,
+    },
+};
+use ulid::Ulid;
+
+use rarian::{
+    bus::{Bus, GetTasks, SetTask, ToggleChecklistItem},
+    error::{RarianError, Result},
+    notes::Note,
+    tasks::{Priority, Task, TaskFilter},
+};
+
+use super::insert_note::InputMode;
+
+/// A task or one of its checklist items
+struct TaskRow {
+    note_id: Ulid,
+    /// The index of the checklist item, `None` for the task itself
+    item: Option<usize>,
+    label: String,
+    done: bool,
+    overdue: bool,
+}
+
+fn checkbox(done: bool) -> &'static str {
+    match done {
+        true => "[x]",
+        false => "[ ]",
+    }
+}
+
+fn task_rows(note: &Note, today: NaiveDate) -> Vec<TaskRow> {
+    let task = note.task.clone().unwrap_or_default();
+    let mut label = format!("{} {}", checkbox(task.done), note.title());
+    if let Some(due) = task.due {
+        label.push_str(&format!("  due {}", due));
+    }
+    if let Some(priority) = task.priority {
+        label.push_str(&format!("  !{}", priority));
+    }
+    let mut rows = vec![TaskRow {
+        note_id: note.id,
+        item: None,
+        label,
+        done: task.done,
+        overdue: task.is_overdue(today),
+    }];
+    rows.extend(
+        note.checklist()
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| TaskRow {
+                note_id: note.id,
+                item: Some(index),
+                label: format!("    {} {}", checkbox(item.done), item.text),
+                done: item.done,
+                overdue: false,
+            }),
+    );
+    rows
+}
+
+/// The open tasks of every app and file, with their checklists
+pub struct TasksWindow {
+    bus: Bus,
+    tasks: Vec<Note>,
+    rows: Vec<TaskRow>,
+    include_done: bool,
+    selected_row: ListState,
+    /// The error from the last request that failed, for the TUI to show
+    pub error: Option<RarianError>,
+}
+
+impl TasksWindow {
+    pub fn new(bus: Bus) -> TasksWindow {
+        TasksWindow {
+            bus,
+            tasks: Vec::new(),
+            rows: Vec::new(),
+            include_done: false,
+            selected_row: ListState::default(),
+            error: None,
+        }
+    }
+
+    pub fn show(&mut self) {
+        self.selected_row.select(None);
+        self.refresh();
+    }
+
+    /// Reloads the tasks, as they may have changed
+    pub fn refresh(&mut self) {
+        if let Err(err) = self.load() {
+            self.error = Some(err);
+        }
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let filter = TaskFilter {
+            include_done: self.include_done,
+            ..TaskFilter::default()
+        };
+        self.tasks = self.bus.request(GetTasks { filter })?;
+        let today = Local::now().date_naive();
+        self.rows = self
+            .tasks
+            .iter()
+            .flat_map(|note| task_rows(note, today))
+            .collect();
+        if let Some(row) = self.selected_row.selected() {
+            if row >= self.rows.len() {
+                self.selected_row.select(self.rows.len().checked_sub(1));
+            }
+        }
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<(&TaskRow, Task)> {
+        let row = self.rows.get(self.selected_row.selected()?)?;
+        let note = self.tasks.iter().find(|note| note.id == row.note_id)?;
+        Some((row, note.task.clone().unwrap_or_default()))
+    }
+
+    fn report(&mut self, result: Result<()>) {
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    /// Checks or unchecks the selected task or checklist item
+    fn toggle_selected(&mut self) {
+        let Some((row, task)) = self.selected() else {
+            return;
+        };
+        let result = match row.item {
+            Some(index) => self.bus.request(ToggleChecklistItem {
+                id: row.note_id,
+                index,
+            }),
+            None => self.bus.request(SetTask {
+                id: row.note_id,
+                task: Some(Task {
+                    done: !task.done,
+                    ..task
+                }),
+            }),
+        };
+        self.report(result);
+    }
+
+    fn change_selected_task(&mut self, change: impl FnOnce(&mut Task)) {
+        let Some((row, mut task)) = self.selected() else {
+            return;
+        };
+        let id = row.note_id;
+        change(&mut task);
+        let result = self.bus.request(SetTask {
+            id,
+            task: Some(task),
+        });
+        self.report(result);
+    }
+
+    /// Moves the due date of the selected task by a day, starting from today if it had none
+    fn shift_due(&mut self, later: bool) {
+        self.change_selected_task(|task| {
+            let due = task.due.unwrap_or_else(|| Local::now().date_naive());
+            task.due = match later {
+                true => due.checked_add_days(Days::new(1)),
+                false => due.checked_sub_days(Days::new(1)),
+            };
+        });
+    }
+
+    fn select_next(&mut self) {
+        match self.selected_row.selected() {
+            Some(row) if row + 1 < self.rows.len() => self.selected_row.select_next(),
+            None if !self.rows.is_empty() => self.selected_row.select_first(),
+            _ => {}
+        }
+    }
+
+    fn select_previous(&mut self) {
+        match self.selected_row.selected() {
+            Some(row) if row > 0 => self.selected_row.select_previous(),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn handle_key_event(&mut self, key_event: KeyEvent) -> InputMode {
+        match key_event.code {
+            KeyCode::Esc | KeyCode::Char('q') => return InputMode::Normal,
+            KeyCode::Char('j') | KeyCode::Down => self.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.select_previous(),
+            KeyCode::Char(' ') | KeyCode::Char('x') | KeyCode::Enter => self.toggle_selected(),
+            KeyCode::Char('!') => {
+                self.change_selected_task(|task| task.priority = Priority::next(task.priority))
+            }
+            KeyCode::Char('>') => self.shift_due(true),
+            KeyCode::Char('<') => self.shift_due(false),
+            KeyCode::Char('D') => self.change_selected_task(|task| task.due = None),
+            KeyCode::Char('a') => {
+                self.include_done = !self.include_done;
+                self.refresh();
+            }
+            _ => {}
+        }
+        InputMode::Tasks
+    }
+}
+
+impl Widget for &mut TasksWindow {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let title = match self.include_done {
+            true => " all tasks ",
+            false => " open tasks ",
+        };
+        let block = Block::bordered()
+            .title(Title::from(title.bold()).alignment(Alignment::Center))
+            .title(
+                Title::from(" SPACE check, ! priority, </> due date, a show done, ESC close ")
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            )
+            .border_set(border::THICK);
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let item = ListItem::from(row.label.clone());
+                match (row.item, row.done, row.overdue) {
+                    (_, true, _) => item.dim(),
+                    (None, false, true) => item.fg(Color::Red).bold(),
+                    (None, false, false) => item.bold(),
+                    (Some(_), false, _) => item,
+                }
+            })
+            .collect();
+
+        const SELECTED_STYLE: Style = Style::new().bg(BLUE.c800).add_modifier(Modifier::BOLD);
+        let list = List::new(items)
+            .block(block)
+            .scroll_padding(1)
+            .highlight_style(SELECTED_STYLE)
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+        StatefulWidget::render(list, area, buf, &mut self.selected_row);
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::{Days, Local, NaiveDate};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::{Alignment, Rect},
+    style::{palette::tailwind::BLUE, Modifier, Style, Stylize},
+    symbols::border,
+    widgets::{
+        block::{Position, Title},
+        Block, HighlightSpacing, List, ListItem, ListState, StatefulWidget, Widget,
+    },
+};
+
+use rarian::{
+    bus::{Bus, GetTimeline},
+    error::{RarianError, Result},
+    timeline::TimelineEntry,
+};
+
+use super::insert_note::InputMode;
+
+pub struct TimelineWindow {
+    bus: Bus,
+    date: NaiveDate,
+    entries: Vec<TimelineEntry>,
+    selected_row: ListState,
+    /// The error from the last day that failed to load, for the TUI to show
+    pub error: Option<RarianError>,
+}
+
+impl TimelineWindow {
+    pub fn new(bus: Bus) -> TimelineWindow {
+        TimelineWindow {
+            bus,
+            date: Local::now().date_naive(),
+            entries: Vec::new(),
+            selected_row: ListState::default(),
+            error: None,
+        }
+    }
+
+    /// Keeps showing the previous day if `date` fails to load
+    pub fn show_day(&mut self, date: NaiveDate) {
+        match self.load_day(date) {
+            Ok(()) => self.selected_row.select(None),
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    fn load_day(&mut self, date: NaiveDate) -> Result<()> {
+        let timeline = self.bus.request(GetTimeline { date })?;
+        self.date = timeline.date;
+        self.entries = timeline.entries;
+        Ok(())
+    }
+
+    pub fn show_today(&mut self) {
+        self.show_day(Local::now().date_naive());
+    }
+
+    fn previous_day(&mut self) {
+        if let Some(date) = self.date.checked_sub_days(Days::new(1)) {
+            self.show_day(date);
+        }
+    }
+
+    fn next_day(&mut self) {
+        if let Some(date) = self.date.checked_add_days(Days::new(1)) {
+            self.show_day(date);
+        }
+    }
+
+    fn select_next(&mut self) {
+        match self.selected_row.selected() {
+            Some(row) if row + 1 < self.entries.len() => self.selected_row.select_next(),
+            None if !self.entries.is_empty() => self.selected_row.select_first(),
+            _ => {}
+        }
+    }
+
+    fn select_previous(&mut self) {
+        match self.selected_row.selected() {
+            Some(row) if row > 0 => self.selected_row.select_previous(),
+            _ => {}
+        }
+    }
+
+    fn select_last(&mut self) {
+        if !self.entries.is_empty() {
+            self.selected_row.select(Some(self.entries.len() - 1));
+        }
+    }
+
+    pub(crate) fn handle_key_event(&mut self, key_event: KeyEvent) -> InputMode {
+        match key_event.code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('t') => return InputMode::Normal,
+            KeyCode::Char('h') | KeyCode::Left => self.previous_day(),
+            KeyCode::Char('l') | KeyCode::Right => self.next_day(),
+            KeyCode::Char('T') => self.show_today(),
+            KeyCode::Char('j') | KeyCode::Down => self.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.select_previous(),
+            KeyCode::Char('g') | KeyCode::Home => self.selected_row.select_first(),
+            KeyCode::Char('G') | KeyCode::End => self.select_last(),
+            _ => {}
+        }
+        InputMode::Timeline
+    }
+}
+
+impl Widget for &mut TimelineWindow {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let title = format!(" timeline {} ", self.date.format("%A %Y-%m-%d"));
+        let block = Block::bordered()
+            .title(Title::from(title.bold()).alignment(Alignment::Center))
+            .title(
+                Title::from(" ←→ change day, T today, ESC back ")
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            )
+            .border_set(border::THICK);
+        let items: Vec<ListItem> = if self.entries.is_empty() {
+            vec![ListItem::from("no activity recorded")]
+        } else {
+            self.entries
+                .iter()
+                .map(|entry| {
+                    let item = ListItem::from(entry.to_string());
+                    if entry.is_app_focus() {
+                        item.bold()
+                    } else {
+                        item
+                    }
+                })
+                .collect()
+        };
+
+        const SELECTED_STYLE: Style = Style::new().bg(BLUE.c800).add_modifier(Modifier::BOLD);
+        let list = List::new(items)
+            .block(block)
+            .scroll_padding(1)
+            .highlight_style(SELECTED_STYLE)
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+        StatefulWidget::render(list, area, buf, &mut self.selected_row);
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
+    fmt::Display,
     io::{self, stdout, Stdout},
-    sync::mpsc::{channel, Sender},
+    panic,
+    sync::mpsc::Receiver,
     time::Duration,
 };
 
@@ -27,11 +29,32 @@ use ratatui::{
 };
 
 use crate::{
-    app::insert_note::InsertWindow, config::Config, gatherer::app_gatherer::ActiveProcessEvent,
-    notes::Note, StateMachine,
+    app::{
+        insert_note::InsertWindow, note_detail::NoteDetailWindow, tasks_window::TasksWindow,
+        timeline_window::TimelineWindow,
+    },
+    external_editor::edit_in_external_editor,
+};
+use chrono::Local;
+use ulid::Ulid;
+
+use rarian::{
+    bus::{
+        AddNote, ArchiveNote, BoostNote, Bus, EditNote, PinNote, RecentApps, RelatedNotes,
+        ReorderNotes, SetReminder, SetTask, SyncNotes,
+    },
+    config::Config,
+    crypto::is_encrypted,
+    error::{RarianError, Result},
+    gatherer::app_gatherer::ActiveProcessEvent,
+    notes::{Note, NoteSort},
+    reminders::{parse_time, Reminder, Trigger},
+    supervisor::is_supervised_thread,
+    tasks::Task,
+    Notification,
 };
 
-use super::insert_note::InputMode;
+use super::insert_note::{wrap_text, InputMode};
 
 pub type Tui = Terminal<CrosstermBackend<Stdout>>;
 
@@ -47,71 +70,281 @@ pub fn restore() -> io::Result<()> {
     Ok(())
 }
 
+/// Restores the terminal before a panic is printed, so it isn't left in raw mode. Panics in
+/// supervised threads are caught and restarted, so the TUI keeps the terminal for those.
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !is_supervised_thread() {
+            let _ = restore();
+        }
+        default_hook(info);
+    }));
+}
+
+#[derive(PartialEq)]
+enum Pane {
+    Apps,
+    Notes,
+}
+
+/// The most recent apps fetched for the apps pane, more than fit on any screen
+const RECENT_APPS: usize = 100;
+
 pub struct TuiApp {
-    state_machine_tx: Sender<StateMachine>,
+    bus: Bus,
+    notifications_rx: Receiver<Notification>,
     exit: bool,
+    needs_redraw: bool,
+    apps_changed: bool,
+    notes_changed: bool,
     input_mode: InputMode,
+    focused_pane: Pane,
     insert_note_window: InsertWindow,
     notes_window: NotesWindow,
     last_apps_window: LastAppsWindow,
     help_window: HelpWindow,
+    timeline_window: TimelineWindow,
+    note_detail_window: NoteDetailWindow,
+    tasks_window: TasksWindow,
+    open_external_editor: bool,
+    /// Whether the notes are encrypted, so they must not be written to a file for `$EDITOR`
+    encrypted: bool,
     sleep_duration: Duration,
+    /// The last error, shown in the status bar until ESC is pressed
+    status: Option<String>,
+    /// The title of the last reminder that became due, shown below any error until ESC is pressed
+    reminder: Option<String>,
 }
 
 impl TuiApp {
-    pub fn new(config: Config, state_machine_tx: Sender<StateMachine>) -> TuiApp {
+    pub fn new(config: Config, bus: Bus, notifications_rx: Receiver<Notification>) -> TuiApp {
         TuiApp {
-            state_machine_tx: state_machine_tx.clone(),
+            bus: bus.clone(),
+            notifications_rx,
             exit: false,
+            needs_redraw: true,
+            apps_changed: true,
+            notes_changed: true,
             input_mode: InputMode::Normal,
-            insert_note_window: InsertWindow::new(state_machine_tx.clone()),
-            notes_window: NotesWindow::new(state_machine_tx.clone()),
-            last_apps_window: LastAppsWindow::new(state_machine_tx.clone()),
+            focused_pane: Pane::Notes,
+            insert_note_window: InsertWindow::new(bus.clone()),
+            notes_window: NotesWindow::new(bus.clone()),
+            last_apps_window: LastAppsWindow::new(bus.clone()),
             help_window: HelpWindow::new(),
+            timeline_window: TimelineWindow::new(bus.clone()),
+            note_detail_window: NoteDetailWindow::new(bus.clone()),
+            tasks_window: TasksWindow::new(bus.clone()),
+            open_external_editor: false,
+            encrypted: is_encrypted(&config.data_path),
             sleep_duration: config.sleep_duration.clone(),
+            status: None,
+            reminder: None,
         }
     }
 
+    /// Redraws only after input or a change notification from the service thread. Input is polled
+    /// rather than read on another thread so nothing competes with `$EDITOR` for it.
     pub fn run(&mut self, terminal: &mut Tui) -> io::Result<()> {
         while !self.exit {
-            self.notes_window.get_current_notes_and_window();
-            terminal.draw(|frame| self.render_frame(frame))?;
+            self.refresh();
+            if self.needs_redraw {
+                terminal.draw(|frame| self.render_frame(frame))?;
+                self.needs_redraw = false;
+            }
             self.handle_events()?;
+            self.handle_notifications();
+            self.take_window_errors();
+            if self.open_external_editor {
+                self.open_external_editor = false;
+                self.edit_in_external_editor(terminal)?;
+                self.needs_redraw = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_notifications(&mut self) {
+        while let Ok(notification) = self.notifications_rx.try_recv() {
+            match notification {
+                Notification::CurrentAppChanged(current) => {
+                    self.notes_window.focused_app =
+                        current.map(|current| current.get_title().to_string());
+                }
+                Notification::RecentAppsChanged => self.apps_changed = true,
+                Notification::NotesChanged => self.notes_changed = true,
+                Notification::ReminderDue(note) => self.reminder = Some(note.title().to_string()),
+                Notification::SyncConflicts(notes) => {
+                    let titles: Vec<&str> = notes.iter().map(|note| note.title()).collect();
+                    self.show_error(format!(
+                        "sync conflict in {}, edit to resolve",
+                        titles.join(", ")
+                    ));
+                }
+                Notification::ConfigChanged(config) => self.sleep_duration = config.sleep_duration,
+                Notification::Error(err) => self.show_error(err),
+            }
+            self.needs_redraw = true;
         }
+    }
+
+    fn take_window_errors(&mut self) {
+        if let Some(err) = self.insert_note_window.error.take() {
+            self.report(Err(err));
+        }
+        if let Some(err) = self.timeline_window.error.take() {
+            self.report(Err(err));
+        }
+        if let Some(err) = self.note_detail_window.error.take() {
+            self.report(Err(err));
+        }
+        if let Some(err) = self.tasks_window.error.take() {
+            self.report(Err(err));
+        }
+    }
+
+    fn show_error(&mut self, err: impl Display) {
+        self.status = Some(err.to_string());
+        self.needs_redraw = true;
+    }
+
+    /// Shows a failed request in the status bar, and exits if the service thread is gone since no
+    /// request can succeed after that
+    fn report(&mut self, result: Result<()>) {
+        match result {
+            Ok(()) => {}
+            Err(RarianError::Disconnected) => self.exit = true,
+            Err(err) => self.show_error(err),
+        }
+    }
+
+    /// Fetches whatever changed since the last refresh from the service thread
+    fn refresh(&mut self) {
+        if self.apps_changed {
+            let result = self.last_apps_window.get_last_apps(RECENT_APPS);
+            self.report(result);
+            self.apps_changed = false;
+        }
+        let selected_app = self.last_apps_window.selected_title.clone();
+        match self
+            .notes_window
+            .get_current_notes_and_window(selected_app, self.notes_changed)
+        {
+            Ok(true) => self.needs_redraw = true,
+            Ok(false) => {}
+            Err(err) => self.report(Err(err)),
+        }
+        if self.notes_changed {
+            match self.input_mode {
+                InputMode::Detail => self.note_detail_window.refresh(),
+                InputMode::Tasks => self.tasks_window.refresh(),
+                _ => {}
+            }
+        }
+        self.notes_changed = false;
+    }
+
+    /// Suspends the TUI while the selected note, or a new one if none is selected, is edited in
+    /// `$VISUAL`/`$EDITOR`. The note is only saved if the editor succeeded and changed the text.
+    fn edit_in_external_editor(&mut self, terminal: &mut Tui) -> io::Result<()> {
+        if self.encrypted {
+            self.show_error("the notes are encrypted, edit them here instead".to_string());
+            return Ok(());
+        }
+        let selected_note = self
+            .notes_window
+            .selected_row
+            .selected()
+            .and_then(|row| self.notes_window.current_notes.get(row))
+            .cloned();
+        let text = selected_note
+            .as_ref()
+            .map_or(String::new(), |note| note.text.clone());
+        restore()?;
+        let edited = edit_in_external_editor(&text);
+        *terminal = init()?;
+        terminal.clear()?;
+        let edited = match edited {
+            Ok(Some(edited)) => edited,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                self.show_error(err);
+                return Ok(());
+            }
+        };
+        let result = match (selected_note, &self.notes_window.current_link) {
+            (Some(note), _) => self.bus.request(EditNote {
+                id: note.id,
+                text: edited,
+            }),
+            (None, Some(link)) => self
+                .bus
+                .request(AddNote {
+                    text: edited,
+                    links: vec![link.clone()],
+                })
+                .map(|_| ()),
+            (None, None) => Ok(()),
+        };
+        self.report(result);
         Ok(())
     }
 
     fn render_frame(&mut self, frame: &mut Frame) {
+        let status_height = match (&self.status, &self.reminder) {
+            (None, None) => 0,
+            _ => 1,
+        };
+        let outer_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(status_height)])
+            .split(frame.size());
+        match (&self.status, &self.reminder) {
+            (Some(status), _) => frame.render_widget(StatusBar(status), outer_layout[1]),
+            (None, Some(reminder)) => frame.render_widget(ReminderBar(reminder), outer_layout[1]),
+            (None, None) => {}
+        }
         let layout = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(vec![Constraint::Percentage(30), Constraint::Percentage(70)])
-            .split(frame.size());
+            .split(outer_layout[0]);
+        let top_percentage = match self.input_mode {
+            InputMode::Editing => 40,
+            _ => 20,
+        };
         let notes_layout = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(vec![Constraint::Percentage(20), Constraint::Percentage(80)])
+            .constraints(vec![
+                Constraint::Percentage(top_percentage),
+                Constraint::Percentage(100 - top_percentage),
+            ])
             .split(layout[1]);
+        self.last_apps_window.focused = self.focused_pane == Pane::Apps;
+        self.notes_window.focused = self.focused_pane == Pane::Notes;
         frame.render_widget(&self.last_apps_window, layout[0]);
-        frame.render_widget(&mut self.notes_window, notes_layout[1]);
         match self.input_mode {
-            InputMode::Normal => frame.render_widget(&self.help_window, notes_layout[0]),
-            InputMode::Editing => frame.render_widget(&self.insert_note_window, notes_layout[0]),
+            InputMode::Normal => {
+                frame.render_widget(&self.help_window, notes_layout[0]);
+                frame.render_widget(&mut self.notes_window, notes_layout[1]);
+            }
+            InputMode::Editing => {
+                frame.render_widget(&mut self.insert_note_window, notes_layout[0]);
+                frame.render_widget(&mut self.notes_window, notes_layout[1]);
+            }
+            InputMode::Timeline => frame.render_widget(&mut self.timeline_window, layout[1]),
+            InputMode::Detail => frame.render_widget(&mut self.note_detail_window, layout[1]),
+            InputMode::Tasks => frame.render_widget(&mut self.tasks_window, layout[1]),
         }
         match self.input_mode {
-            InputMode::Normal =>
+            InputMode::Normal | InputMode::Timeline | InputMode::Detail | InputMode::Tasks =>
                 // Hide the cursor. `Frame` does this by default, so we don't need to do anything here
                 {}
 
             InputMode::Editing => {
-                // Make the cursor visible and ask ratatui to put it at the specified coordinates after
-                // rendering
-                #[allow(clippy::cast_possible_truncation)]
-                frame.set_cursor(
-                    // Draw the cursor at the current position in the input field.
-                    // This position is can be controlled via the left and right arrow key
-                    notes_layout[0].x + self.insert_note_window.character_index as u16 + 1,
-                    // Move one line down, from the border to the input line
-                    notes_layout[0].y + 1,
-                );
+                // Make the cursor visible and ask ratatui to put it where the editor drew it
+                if let Some((x, y)) = self.insert_note_window.cursor_position() {
+                    frame.set_cursor(x, y);
+                }
             }
         }
     }
@@ -122,8 +355,10 @@ impl TuiApp {
                 // it's important to check that the event is a key press event as
                 // crossterm also emits key release and repeat events on Windows.
                 Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                    self.handle_key_event(key_event)
+                    self.handle_key_event(key_event);
+                    self.needs_redraw = true;
                 }
+                Event::Resize(_, _) => self.needs_redraw = true,
                 _ => {}
             };
         }
@@ -133,18 +368,82 @@ impl TuiApp {
     fn handle_normal_mode_key_event(&mut self, key_event: KeyEvent) {
         match key_event.code {
             KeyCode::Char('i') => {
-                self.input_mode = InputMode::Editing;
-                self.notes_window.select_none();
+                if let Some(link) = &self.notes_window.current_link {
+                    self.insert_note_window.note_link = Some(link.clone());
+                    self.input_mode = InputMode::Editing;
+                    self.notes_window.select_none();
+                }
             }
             KeyCode::Char('q') => self.exit(),
             KeyCode::Char('Q') => self.exit(),
-            KeyCode::Esc | KeyCode::Left => self.notes_window.select_none(),
+            KeyCode::Char('E') => self.open_external_editor = true,
+            KeyCode::Char('t') => {
+                self.timeline_window.show_today();
+                self.input_mode = InputMode::Timeline;
+            }
+            KeyCode::Char('T') => {
+                self.tasks_window.show();
+                self.input_mode = InputMode::Tasks;
+            }
+            KeyCode::Char('p') => self.notes_window.toggle_pin(),
+            KeyCode::Char('S') => {
+                let result = self.bus.request(SyncNotes);
+                self.report(result);
+            }
+            KeyCode::Tab | KeyCode::BackTab => match self.focused_pane {
+                Pane::Apps => self.focused_pane = Pane::Notes,
+                Pane::Notes => self.focused_pane = Pane::Apps,
+            },
+            _ => match self.focused_pane {
+                Pane::Apps => self.handle_apps_pane_key_event(key_event),
+                Pane::Notes => self.handle_notes_pane_key_event(key_event),
+            },
+        }
+    }
+
+    fn handle_apps_pane_key_event(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Esc if self.status.is_some() => self.status = None,
+            KeyCode::Esc if self.reminder.is_some() => self.reminder = None,
+            KeyCode::Esc => self.last_apps_window.select_none(),
+            KeyCode::Char('j') | KeyCode::Down => self.last_apps_window.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.last_apps_window.select_previous(),
+            KeyCode::Char('g') | KeyCode::Home => self.last_apps_window.select_first(),
+            KeyCode::Char('G') | KeyCode::End => self.last_apps_window.select_last(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.focused_pane = Pane::Notes,
+            _ => {}
+        }
+    }
+
+    fn handle_notes_pane_key_event(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Esc if self.status.is_some() => self.status = None,
+            KeyCode::Esc if self.reminder.is_some() => self.reminder = None,
+            KeyCode::Esc => self.notes_window.select_none(),
+            KeyCode::Left | KeyCode::Char('h') => {
+                self.notes_window.select_none();
+                self.focused_pane = Pane::Apps;
+            }
             KeyCode::Char('j') | KeyCode::Down => self.notes_window.select_next(),
             KeyCode::Char('k') | KeyCode::Up => self.notes_window.select_previous(),
             KeyCode::Char('g') | KeyCode::Home => self.notes_window.select_first(),
             KeyCode::Char('G') | KeyCode::End => self.notes_window.select_last(),
             KeyCode::Char('a') | KeyCode::Char('d') => self.archive_selected(),
             KeyCode::Char('e') | KeyCode::Enter => self.edit_selected(),
+            KeyCode::Char('o') => self.open_selected(),
+            KeyCode::Char('+') => self.boost_selected(),
+            KeyCode::Char('P') => self.pin_selected(),
+            KeyCode::Char('x') => self.toggle_selected_task(),
+            KeyCode::Char('X') => self.set_selected_task(None),
+            KeyCode::Char('z') => self.snooze_selected(),
+            KeyCode::Char('R') => self.remind_selected_here(),
+            KeyCode::Char('r') => self.set_selected_reminder(None),
+            KeyCode::Char('J') => self.move_selected(true),
+            KeyCode::Char('K') => self.move_selected(false),
+            KeyCode::Char('s') => {
+                self.notes_window.cycle_sort();
+                self.notes_changed = true;
+            }
             _ => {}
         }
     }
@@ -162,120 +461,400 @@ impl TuiApp {
         match self.input_mode {
             InputMode::Normal => self.handle_normal_mode_key_event(key_event),
             InputMode::Editing => self.handle_editing_mode_key_event(key_event),
+            InputMode::Timeline => {
+                self.input_mode = self.timeline_window.handle_key_event(key_event);
+            }
+            InputMode::Detail => {
+                self.input_mode = self.note_detail_window.handle_key_event(key_event);
+            }
+            InputMode::Tasks => {
+                self.input_mode = self.tasks_window.handle_key_event(key_event);
+            }
         }
     }
 
-    fn archive_selected(&self) {
-        match self.notes_window.selected_row.selected() {
-            Some(row) => {
-                let note = self.notes_window.current_notes.get(row).unwrap();
-                self.state_machine_tx
-                    .send(StateMachine::ArchiveNote(note.id))
-                    .unwrap();
-            }
-            None => {}
+    fn archive_selected(&mut self) {
+        let selected_note = self
+            .notes_window
+            .selected_row
+            .selected()
+            .and_then(|row| self.notes_window.current_notes.get(row));
+        if let Some(note) = selected_note {
+            let result = self.bus.request(ArchiveNote { id: note.id });
+            self.report(result);
         }
     }
 
     fn edit_selected(&mut self) {
-        match self.notes_window.selected_row.selected() {
-            Some(row) => {
-                let note = self.notes_window.current_notes.get(row).unwrap();
-                self.insert_note_window.editing_note = Some(note.id);
-                self.insert_note_window.input = note.text.clone();
-                self.input_mode = InputMode::Editing;
-            }
-            None => {}
+        let selected_note = self
+            .notes_window
+            .selected_row
+            .selected()
+            .and_then(|row| self.notes_window.current_notes.get(row));
+        if let Some(note) = selected_note {
+            self.insert_note_window.edit(note.id, &note.text);
+            self.input_mode = InputMode::Editing;
         }
     }
 
+    fn open_selected(&mut self) {
+        let selected_note = self
+            .notes_window
+            .selected_row
+            .selected()
+            .and_then(|row| self.notes_window.current_notes.get(row));
+        if let Some(note) = selected_note {
+            self.note_detail_window.open(note.id);
+            self.input_mode = InputMode::Detail;
+        }
+    }
+
+    /// Ranks the selected note higher for the app shown, linking it to the app if it wasn't yet
+    fn boost_selected(&mut self) {
+        let selected_note = self
+            .notes_window
+            .selected_row
+            .selected()
+            .and_then(|row| self.notes_window.current_notes.get(row));
+        if let (Some(note), Some(link)) = (selected_note, &self.notes_window.current_link) {
+            let result = self.bus.request(BoostNote {
+                id: note.id,
+                link: link.clone(),
+            });
+            self.report(result);
+        }
+    }
+
+    /// Makes the selected note an open task, or checks or unchecks it if it is one
+    fn toggle_selected_task(&mut self) {
+        let task = match self
+            .notes_window
+            .selected_note()
+            .map(|note| note.task.clone())
+        {
+            Some(Some(task)) => Task {
+                done: !task.done,
+                ..task
+            },
+            Some(None) => Task::default(),
+            None => return,
+        };
+        self.set_selected_task(Some(task));
+    }
+
+    fn set_selected_task(&mut self, task: Option<Task>) {
+        if let Some(note) = self.notes_window.selected_note() {
+            let result = self.bus.request(SetTask { id: note.id, task });
+            self.report(result);
+        }
+    }
+
+    fn set_selected_reminder(&mut self, reminder: Option<Reminder>) {
+        if let Some(note) = self.notes_window.selected_note() {
+            let result = self.bus.request(SetReminder {
+                id: note.id,
+                reminder,
+            });
+            self.report(result);
+        }
+    }
+
+    /// Hides the selected note until tomorrow morning
+    fn snooze_selected(&mut self) {
+        let Some(time) = parse_time("tomorrow", Local::now()) else {
+            return;
+        };
+        let trigger = Trigger::Snooze(time.into());
+        self.set_selected_reminder(Some(Reminder::new(trigger)));
+    }
+
+    /// Reminds of the selected note the next time the app shown is focused
+    fn remind_selected_here(&mut self) {
+        let trigger = Trigger::Context {
+            app: self.notes_window.current_link.clone(),
+            project: None,
+        };
+        self.set_selected_reminder(Some(Reminder::new(trigger)));
+    }
+
+    fn pin_selected(&mut self) {
+        let selected_note = self.notes_window.selected_note();
+        if let (Some(note), Some(link)) = (selected_note, &self.notes_window.current_link) {
+            let result = self.bus.request(PinNote {
+                id: note.id,
+                link: link.clone(),
+                pinned: !self.notes_window.is_pinned(note),
+            });
+            self.report(result);
+        }
+    }
+
+    /// Moves the selected note down or up, switching to the manual sort that keeps it there
+    fn move_selected(&mut self, down: bool) {
+        let Some(link) = self.notes_window.current_link.clone() else {
+            return;
+        };
+        let Some((order, row)) = self.notes_window.moved_order(down) else {
+            return;
+        };
+        let result = self.bus.request(ReorderNotes { link, order });
+        if result.is_ok() {
+            self.notes_window.sort = NoteSort::Manual;
+            self.notes_window.update_title();
+            self.notes_window.selected_row.select(Some(row));
+        }
+        self.report(result);
+    }
+
     fn exit(&mut self) {
         self.exit = true;
-        self.state_machine_tx.send(StateMachine::Quit).unwrap();
+        // The service thread may already be gone, which is what quitting wants anyway
+        let _ = self.bus.quit();
     }
 }
 
 struct LastAppsWindow {
-    state_machine_tx: Sender<StateMachine>,
+    bus: Bus,
+    last_apps: Vec<ActiveProcessEvent>,
+    selected_title: Option<String>,
+    focused: bool,
 }
 
 impl LastAppsWindow {
-    pub fn new(state_machine_tx: Sender<StateMachine>) -> LastAppsWindow {
-        LastAppsWindow { state_machine_tx }
+    pub fn new(bus: Bus) -> LastAppsWindow {
+        LastAppsWindow {
+            bus,
+            last_apps: Vec::new(),
+            selected_title: None,
+            focused: false,
+        }
+    }
+
+    fn get_last_apps(&mut self, num: usize) -> Result<()> {
+        let last_processes = self.bus.request(RecentApps { count: num })?;
+        self.last_apps = last_processes.into_iter().take(num).collect();
+        Ok(())
+    }
+
+    /// The apps move around as they get focused, so the selection follows the title rather than
+    /// the row
+    fn selected_row(&self) -> Option<usize> {
+        self.selected_title.as_ref().and_then(|title| {
+            self.last_apps
+                .iter()
+                .position(|app| app.get_title() == title)
+        })
     }
 
-    fn show_last_apps(&self, num: usize) -> Vec<ActiveProcessEvent> {
-        let (tx, rx) = channel::<Vec<ActiveProcessEvent>>();
-        self.state_machine_tx
-            .send(StateMachine::RecentApps(num, tx))
-            .unwrap();
-        let last_processes = rx.recv().expect("main thread is alive");
-        last_processes.into_iter().take(num).collect()
+    fn select_row(&mut self, row: usize) {
+        self.selected_title = self
+            .last_apps
+            .get(row)
+            .map(|app| app.get_title().to_string());
+    }
+
+    fn select_none(&mut self) {
+        self.selected_title = None;
+    }
+
+    fn select_next(&mut self) {
+        match self.selected_row() {
+            Some(row) if row + 1 < self.last_apps.len() => self.select_row(row + 1),
+            Some(_) => {}
+            None => self.select_row(0),
+        }
+    }
+
+    fn select_previous(&mut self) {
+        match self.selected_row() {
+            Some(row) if row > 0 => self.select_row(row - 1),
+            Some(_) => {}
+            None => self.select_last(),
+        }
+    }
+
+    fn select_first(&mut self) {
+        self.select_row(0);
+    }
+
+    fn select_last(&mut self) {
+        if !self.last_apps.is_empty() {
+            self.select_row(self.last_apps.len() - 1);
+        }
     }
 }
 
 impl Widget for &LastAppsWindow {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let title = " latest apps ";
-        let last_apps = self.show_last_apps(area.rows().count() - 2);
-        let list = last_apps
+        let mut selected_row = ListState::default().with_selected(self.selected_row());
+        let list = self
+            .last_apps
             .iter()
             .map(|app_window| app_window.get_title())
             .collect::<List>()
             .block(
                 Block::bordered()
                     .title(Title::from(title.bold()).alignment(Alignment::Center))
-                    .border_set(border::THICK),
+                    .border_set(border::THICK)
+                    .border_style(pane_border_style(self.focused)),
             )
             .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
             .highlight_symbol(">>")
             .repeat_highlight_symbol(true);
 
-        Widget::render(list, area, buf);
+        StatefulWidget::render(list, area, buf, &mut selected_row);
+    }
+}
+
+fn pane_border_style(focused: bool) -> Style {
+    match focused {
+        true => Style::new().fg(BLUE.c400),
+        false => Style::new(),
     }
 }
 
 struct NotesWindow {
-    state_machine_tx: Sender<StateMachine>,
+    bus: Bus,
     current_title: String,
+    /// What new notes are linked to, `None` if there is no app to link them to
+    current_link: Option<String>,
+    pinned_link: Option<String>,
+    /// The title of the focused app, as last notified by the service thread
+    focused_app: Option<String>,
     current_notes: Vec<Note>,
+    sort: NoteSort,
     selected_row: ListState,
+    focused: bool,
 }
 
 impl NotesWindow {
-    pub fn new(state_machine_tx: Sender<StateMachine>) -> NotesWindow {
+    pub fn new(bus: Bus) -> NotesWindow {
         NotesWindow {
-            state_machine_tx,
+            bus,
             current_title: String::new(),
+            current_link: None,
+            pinned_link: None,
+            focused_app: None,
             current_notes: Vec::new(),
+            sort: NoteSort::default(),
             selected_row: ListState::default(),
+            focused: true,
         }
     }
 
-    fn get_current_notes_and_window(&mut self) {
-        let (tx, rx) = channel::<Option<ActiveProcessEvent>>();
-        self.state_machine_tx
-            .send(StateMachine::CurrentApp(tx))
-            .unwrap();
-        match rx.recv().expect("main thread is alive") {
-            Some(current) => {
-                let title = current.get_title();
-                let (tx, rx) = channel::<Vec<Note>>();
-                self.state_machine_tx
-                    .send(StateMachine::GetAppNotes(
-                        current.get_title().to_string(),
-                        tx,
-                    ))
-                    .unwrap();
-                let app_notes = rx.recv().expect("main thread is alive");
-                self.current_title = title.to_owned();
-                self.current_notes = app_notes.into_iter().collect();
+    /// Shows the notes related to the pinned app, otherwise to the app selected in the apps pane,
+    /// and otherwise to the focused app. The notes are only fetched again if they changed or another
+    /// app is shown, and returns whether they were.
+    fn get_current_notes_and_window(
+        &mut self,
+        selected_app: Option<String>,
+        changed: bool,
+    ) -> Result<bool> {
+        let link = self
+            .pinned_link
+            .clone()
+            .or(selected_app)
+            .or(self.focused_app.clone());
+        if link == self.current_link && !changed {
+            return Ok(false);
+        }
+        if link != self.current_link {
+            self.select_none();
+        }
+        match &link {
+            Some(link) => {
+                self.current_notes = self.bus.request(RelatedNotes {
+                    app_title: link.clone(),
+                    sort: self.sort,
+                })?;
             }
             None => {
-                self.current_title = "no app currently detected".to_string();
                 self.current_notes = Vec::new();
             }
         }
+        self.current_link = link;
+        self.update_title();
+        if let Some(row) = self.selected_row.selected() {
+            if row >= self.current_notes.len() {
+                self.select_last();
+            }
+        }
+        Ok(true)
+    }
+
+    fn update_title(&mut self) {
+        self.current_title = match (&self.current_link, &self.pinned_link) {
+            (Some(link), Some(_)) => format!("pinned: {}", link),
+            (Some(link), None) => link.clone(),
+            (None, _) => "no app currently detected".to_string(),
+        };
+        if self.sort != NoteSort::Relevance {
+            self.current_title = format!("{}, sorted by {}", self.current_title, self.sort);
+        }
+    }
+
+    fn selected_note(&self) -> Option<&Note> {
+        self.selected_row
+            .selected()
+            .and_then(|row| self.current_notes.get(row))
+    }
+
+    /// Whether a note is linked to the app shown rather than to a similar context
+    fn is_linked(&self, note: &Note) -> bool {
+        self.current_link
+            .as_ref()
+            .is_some_and(|link| note.link_to(link).is_some())
+    }
+
+    /// Whether a note is pinned to the top of the notes shown
+    fn is_pinned(&self, note: &Note) -> bool {
+        self.current_link
+            .as_ref()
+            .and_then(|link| note.link_to(link))
+            .is_some_and(|link| link.pinned)
+    }
+
+    fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.update_title();
+    }
+
+    /// The notes linked to the app shown, with the selected note swapped with the one `down` or up
+    /// from it, and the row it moves to. Pinned notes only move among each other and so do the
+    /// rest, and notes from similar contexts don't move at all.
+    fn moved_order(&self, down: bool) -> Option<(Vec<Ulid>, usize)> {
+        let row = self.selected_row.selected()?;
+        let other = match down {
+            true => row + 1,
+            false => row.checked_sub(1)?,
+        };
+        let (note, other_note) = (self.current_notes.get(row)?, self.current_notes.get(other)?);
+        if !self.is_linked(note)
+            || !self.is_linked(other_note)
+            || self.is_pinned(note) != self.is_pinned(other_note)
+        {
+            return None;
+        }
+        let mut order: Vec<Ulid> = self
+            .current_notes
+            .iter()
+            .filter(|note| self.is_linked(note))
+            .map(|note| note.id)
+            .collect();
+        let from = order.iter().position(|id| *id == note.id)?;
+        let to = order.iter().position(|id| *id == other_note.id)?;
+        order.swap(from, to);
+        Some((order, other))
+    }
+
+    /// Pins the notes that are shown so they stay when another app is focused
+    fn toggle_pin(&mut self) {
+        self.pinned_link = match self.pinned_link {
+            Some(_) => None,
+            None => self.current_link.clone(),
+        };
+        self.update_title();
     }
 
     fn select_none(&mut self) {
@@ -328,14 +907,39 @@ impl Widget for &mut NotesWindow {
         let title = format!(" {} ", self.current_title);
         let block = Block::bordered()
             .title(Title::from(title.bold()).alignment(Alignment::Center))
-            .border_set(border::THICK);
+            .border_set(border::THICK)
+            .border_style(pane_border_style(self.focused));
+        // Leave room for the borders and the highlight symbol
+        let text_width = usize::from(area.width.saturating_sub(4));
         let list_of_notes: Vec<ListItem> = self
             .current_notes
             .iter()
             .enumerate()
             .map(|(i, note)| {
                 let color = self.alternate_colors(i);
-                ListItem::from(note.text.clone()).bg(color)
+                let mut text = match &note.task {
+                    Some(task) if task.done => format!("[x] {}", note.text),
+                    Some(_) => format!("[ ] {}", note.text),
+                    None => note.text.clone(),
+                };
+                if note.reminder_is_due() {
+                    text = format!("(!) {}", text);
+                }
+                if note.conflict.is_some() {
+                    text = format!("(conflict) {}", text);
+                }
+                let item = ListItem::from(wrap_text(&text, text_width).join("\n")).bg(color);
+                // Notes from similar contexts rather than the app itself are dimmed
+                let item = match (self.is_linked(note), self.is_pinned(note)) {
+                    (true, true) => item.bold(),
+                    (true, false) => item,
+                    (false, _) => item.dim(),
+                };
+                match (note.conflict.is_some(), note.reminder_is_due()) {
+                    (true, _) => item.fg(Color::Red),
+                    (false, true) => item.fg(Color::Yellow),
+                    (false, false) => item,
+                }
             })
             .collect();
 
@@ -364,11 +968,23 @@ impl Widget for &HelpWindow {
             "i = insert note",
             "q = quit",
             "ESC = view mode",
-            "ENTER = add new note",
+            "ENTER = save note, ALT+ENTER = new line",
             "↓↑/jk = select items in list",
+            "TAB/←→ = switch between apps and notes",
+            "p = pin the notes shown",
             "e = edit selected note",
+            "o = open selected note with its [[links]] and backlinks",
+            "E = edit selected or new note in $EDITOR",
             "a/d = archive selected note",
-            "edit the config in %appdata%/Rarian/rarian/data",
+            "+ = rank selected note higher for this app",
+            "P = pin selected note to the top, J/K = move it down/up",
+            "s = change how notes are sorted",
+            "x = make selected note a task or check it, X = make it a note again",
+            "T = open tasks of all apps",
+            "z = snooze selected note until tomorrow, R = remind of it next time here, r = dismiss",
+            "t = timeline, ←→/hl = change day",
+            "S = sync notes with the git remote of the config",
+            "edit the config in %appdata%/Rarian/rarian/data, changes apply right away",
         ];
         let help_message_line = Line::from(help_messages.join("; "));
         let title = Title::from(" help window ".bold());
@@ -382,9 +998,36 @@ impl Widget for &HelpWindow {
     }
 }
 
-pub fn run_app(config: Config, state_machine_tx: Sender<StateMachine>) {
-    let mut terminal = init().expect("crossterm init failed");
-    let mut tui_app = TuiApp::new(config, state_machine_tx);
-    tui_app.run(&mut terminal).expect("app run failed");
-    restore().expect("terminal restore failed");
+/// One line with the last error, cleared with ESC
+struct StatusBar<'a>(&'a str);
+
+impl Widget for StatusBar<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Paragraph::new(format!(" {} (ESC to dismiss)", self.0))
+            .style(Style::new().fg(Color::White).bg(Color::Red))
+            .render(area, buf);
+    }
+}
+
+/// One line with the last reminder that became due, cleared with ESC
+struct ReminderBar<'a>(&'a str);
+
+impl Widget for ReminderBar<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Paragraph::new(format!(" reminder: {} (ESC to dismiss)", self.0))
+            .style(Style::new().fg(Color::Black).bg(Color::Yellow))
+            .render(area, buf);
+    }
+}
+
+pub fn run_app(config: Config, bus: Bus) -> io::Result<()> {
+    let Ok(notifications_rx) = bus.subscribe() else {
+        return Ok(());
+    };
+    install_panic_hook();
+    let mut terminal = init()?;
+    let mut tui_app = TuiApp::new(config, bus, notifications_rx);
+    let result = tui_app.run(&mut terminal);
+    restore()?;
+    result
 }
@@ -0,0 +1,497 @@
+use crate::crypto::{share_key, KEY_FILE, STORES};
+use crate::error::{RarianError, Result};
+use crate::notes::NoteTaker;
+use crate::oplog::{Op, Replica};
+use crate::timeline::local_date;
+use blake2::{Blake2s256, Digest};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::fs::{create_dir_all, read, read_dir, remove_dir_all, remove_file, rename, write, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const BACKUPS_DIR: &str = "backups";
+const MANIFEST: &str = "manifest.json";
+/// Ends a snapshot until every file is in it, and the files written while restoring
+const PARTIAL_SUFFIX: &str = "partial";
+/// The files besides the caches that make up the data, without which the caches can't be read
+/// or the op log can't carry on
+const FILES: [&str; 2] = [KEY_FILE, "device"];
+
+fn default_startup() -> usize {
+    3
+}
+
+fn default_daily() -> usize {
+    7
+}
+
+/// How many snapshots are kept, set in the `[backup]` table of the config. Zero turns a kind of
+/// snapshot off, and snapshots taken by hand or before a restore are kept until removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupConfig {
+    /// Snapshots taken whenever rarian starts
+    #[serde(default = "default_startup")]
+    pub startup: usize,
+    /// Snapshots taken once a day while rarian runs
+    #[serde(default = "default_daily")]
+    pub daily: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            startup: default_startup(),
+            daily: default_daily(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotReason {
+    Startup,
+    Daily,
+    Manual,
+    /// Taken before restoring another snapshot, so the restore can be undone
+    Restore,
+}
+
+impl Display for SnapshotReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            SnapshotReason::Startup => "startup",
+            SnapshotReason::Daily => "daily",
+            SnapshotReason::Manual => "manual",
+            SnapshotReason::Restore => "restore",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub name: String,
+    pub size: u64,
+    /// The BLAKE2s-256 of the file, in hex
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub created: SystemTime,
+    pub reason: SnapshotReason,
+    pub files: Vec<SnapshotFile>,
+}
+
+/// A copy of the caches of a data path in its `backups` folder
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    pub path: PathBuf,
+    pub manifest: Manifest,
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    Blake2s256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn read_error(path: &Path, err: impl Display) -> RarianError {
+    RarianError::ReadFile {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    }
+}
+
+fn write_error(path: &Path, err: impl Display) -> RarianError {
+    RarianError::WriteCache {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    }
+}
+
+/// Reads a file of the data path while no process is appending to it or rewriting it
+fn read_locked(path: &Path) -> Result<Vec<u8>> {
+    let mut file = File::open(path).map_err(|err| read_error(path, err))?;
+    let _ = file.lock_shared();
+    let mut bytes = Vec::new();
+    let read = file.read_to_end(&mut bytes);
+    let _ = file.unlock();
+    read.map_err(|err| read_error(path, err))?;
+    Ok(bytes)
+}
+
+impl Snapshot {
+    fn load(path: &Path) -> Result<Snapshot> {
+        let manifest_path = path.join(MANIFEST);
+        let manifest = read(&manifest_path).map_err(|err| read_error(&manifest_path, err))?;
+        Ok(Snapshot {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            path: path.to_path_buf(),
+            manifest: serde_json::from_slice(&manifest)
+                .map_err(|err| read_error(&manifest_path, err))?,
+        })
+    }
+
+    /// Checks every file against the size and checksum it had when the snapshot was taken,
+    /// returning the first that doesn't match
+    pub fn verify(&self) -> std::result::Result<(), String> {
+        for file in self.manifest.files.iter() {
+            let bytes = read(self.path.join(&file.name))
+                .map_err(|err| format!("{} can't be read: {}", file.name, err))?;
+            if bytes.len() as u64 != file.size || checksum(&bytes) != file.checksum {
+                return Err(format!("{} doesn't match its checksum", file.name));
+            }
+        }
+        Ok(())
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.manifest.files.iter().any(|file| file.name == name)
+    }
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let created: DateTime<Local> = self.manifest.created.into();
+        let size: u64 = self.manifest.files.iter().map(|file| file.size).sum();
+        let status = match self.verify() {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("damaged, {}", err),
+        };
+        writeln!(
+            f,
+            "{}  {}  {}  {} files, {} bytes  {}",
+            self.name,
+            created.format("%Y-%m-%d %H:%M:%S"),
+            self.manifest.reason,
+            self.manifest.files.len(),
+            size,
+            status
+        )
+    }
+}
+
+/// The snapshots of the data path, oldest first. Snapshots that were interrupted before their
+/// manifest was written are left out.
+pub fn snapshots(data_path: &Path) -> Result<Vec<Snapshot>> {
+    let backups = data_path.join(BACKUPS_DIR);
+    let Ok(entries) = read_dir(&backups) else {
+        return Ok(Vec::new());
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_none_or(|ext| ext != PARTIAL_SUFFIX))
+        .filter_map(|path| Snapshot::load(&path).ok())
+        .collect();
+    snapshots.sort_by(|a, b| (a.manifest.created, &a.name).cmp(&(b.manifest.created, &b.name)));
+    Ok(snapshots)
+}
+
+/// Copies the caches of the data path to a new snapshot in its `backups` folder
+pub fn create(data_path: &Path, reason: SnapshotReason) -> Result<Snapshot> {
+    let backups = data_path.join(BACKUPS_DIR);
+    let now = SystemTime::now();
+    let stamp = DateTime::<Local>::from(now).format("%Y%m%d-%H%M%S");
+    let mut name = format!("{}-{}", stamp, reason);
+    let mut count = 1;
+    while backups.join(&name).exists() {
+        count += 1;
+        name = format!("{}-{}-{}", stamp, reason, count);
+    }
+    let partial = backups.join(format!("{}.{}", name, PARTIAL_SUFFIX));
+    create_dir_all(&partial).map_err(|err| write_error(&partial, err))?;
+    let mut files = Vec::new();
+    for file_name in STORES.iter().chain(FILES.iter()) {
+        let source = data_path.join(file_name);
+        if !source.exists() {
+            continue;
+        }
+        let bytes = read_locked(&source)?;
+        let target = partial.join(file_name);
+        write(&target, &bytes).map_err(|err| write_error(&target, err))?;
+        files.push(SnapshotFile {
+            name: file_name.to_string(),
+            size: bytes.len() as u64,
+            checksum: checksum(&bytes),
+        });
+    }
+    let manifest = Manifest {
+        created: now,
+        reason,
+        files,
+    };
+    let manifest_path = partial.join(MANIFEST);
+    write(
+        &manifest_path,
+        serde_json::to_string_pretty(&manifest).unwrap(),
+    )
+    .map_err(|err| write_error(&manifest_path, err))?;
+    let path = backups.join(&name);
+    rename(&partial, &path).map_err(|err| write_error(&path, err))?;
+    Ok(Snapshot {
+        name,
+        path,
+        manifest,
+    })
+}
+
+/// Removes every snapshot of the data path, returning how many there were
+pub fn remove_snapshots(data_path: &Path) -> Result<usize> {
+    let removed = snapshots(data_path)?.len();
+    let backups = data_path.join(BACKUPS_DIR);
+    if backups.exists() {
+        remove_dir_all(&backups).map_err(|err| write_error(&backups, err))?;
+    }
+    Ok(removed)
+}
+
+/// Runs `change` on the caches of every snapshot as if it was a data path, and updates the
+/// checksums of the files it changed, so what is erased from the data path is erased from its
+/// snapshots too. A damaged snapshot keeps the checksums it had. Returns how many snapshots were
+/// changed and how many were left as they were, being encrypted with another passphrase.
+pub fn rewrite_snapshots(
+    data_path: &Path,
+    mut change: impl FnMut(&Path) -> Result<()>,
+) -> Result<(usize, usize)> {
+    let key_file = read(data_path.join(KEY_FILE)).ok();
+    let (mut changed, mut skipped) = (0, 0);
+    for mut snapshot in snapshots(data_path)? {
+        if read(snapshot.path.join(KEY_FILE)).ok() != key_file {
+            skipped += 1;
+            continue;
+        }
+        if key_file.is_some() {
+            share_key(data_path, &snapshot.path)?;
+        }
+        let damaged = snapshot.verify().is_err();
+        change(&snapshot.path)?;
+        // The caches the snapshot didn't have are created empty when they are opened
+        for store in STORES.iter().filter(|store| !snapshot.has(store)) {
+            let path = snapshot.path.join(store);
+            if read(&path).is_ok_and(|bytes| bytes.is_empty()) {
+                remove_file(&path).map_err(|err| write_error(&path, err))?;
+            }
+        }
+        if !damaged {
+            for file in snapshot.manifest.files.iter_mut() {
+                let path = snapshot.path.join(&file.name);
+                let bytes = read(&path).map_err(|err| read_error(&path, err))?;
+                file.size = bytes.len() as u64;
+                file.checksum = checksum(&bytes);
+            }
+            let manifest_path = snapshot.path.join(MANIFEST);
+            write(
+                &manifest_path,
+                serde_json::to_string_pretty(&snapshot.manifest).unwrap(),
+            )
+            .map_err(|err| write_error(&manifest_path, err))?;
+        }
+        changed += 1;
+    }
+    Ok((changed, skipped))
+}
+
+/// Removes the oldest startup and daily snapshots beyond the number the config keeps, returning
+/// how many were removed
+pub fn rotate(data_path: &Path, config: &BackupConfig) -> Result<usize> {
+    let mut removed = 0;
+    for (reason, keep) in [
+        (SnapshotReason::Startup, config.startup),
+        (SnapshotReason::Daily, config.daily),
+    ] {
+        let snapshots: Vec<Snapshot> = snapshots(data_path)?
+            .into_iter()
+            .filter(|snapshot| snapshot.manifest.reason == reason)
+            .collect();
+        for snapshot in snapshots.iter().take(snapshots.len().saturating_sub(keep)) {
+            remove_dir_all(&snapshot.path).map_err(|err| write_error(&snapshot.path, err))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Takes the snapshot of a rarian starting, unless the config keeps none
+pub fn on_startup(data_path: &Path, config: &BackupConfig) -> Result<Option<Snapshot>> {
+    if config.startup == 0 {
+        return Ok(None);
+    }
+    let snapshot = create(data_path, SnapshotReason::Startup)?;
+    rotate(data_path, config)?;
+    Ok(Some(snapshot))
+}
+
+/// Takes the daily snapshot if none was taken on the day of `now`, unless the config keeps none
+pub fn daily(data_path: &Path, config: &BackupConfig, now: SystemTime) -> Result<Option<Snapshot>> {
+    if config.daily == 0 {
+        return Ok(None);
+    }
+    let today = local_date(now);
+    let taken = snapshots(data_path)?.iter().any(|snapshot| {
+        snapshot.manifest.reason == SnapshotReason::Daily
+            && local_date(snapshot.manifest.created) == today
+    });
+    if taken {
+        return Ok(None);
+    }
+    let snapshot = create(data_path, SnapshotReason::Daily)?;
+    rotate(data_path, config)?;
+    Ok(Some(snapshot))
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoreReport {
+    pub snapshot: String,
+    /// The snapshot of the data path from before the restore
+    pub previous: String,
+    /// The notes changed by replaying the op log after the snapshot
+    pub replayed: usize,
+}
+
+impl Display for RestoreReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "restored {}", self.snapshot)?;
+        if self.replayed > 0 {
+            writeln!(f, "replayed the changes to {} notes", self.replayed)?;
+        }
+        writeln!(
+            f,
+            "the data from before is in {}, restore it to undo",
+            self.previous
+        )
+    }
+}
+
+/// Replaces the caches of the data path with the ones in snapshot `name`, after checking their
+/// checksums and taking a snapshot of the current ones. With `until`, the changes to the text,
+/// links and status of the notes made after the snapshot are replayed from the current op log up
+/// to that time. Nothing may be writing to the data path meanwhile.
+pub fn restore(data_path: &Path, name: &str, until: Option<SystemTime>) -> Result<RestoreReport> {
+    let snapshot = snapshots(data_path)?
+        .into_iter()
+        .find(|snapshot| snapshot.name == name)
+        .ok_or_else(|| RarianError::Backup(format!("there is no snapshot named {}", name)))?;
+    snapshot
+        .verify()
+        .map_err(|err| RarianError::Backup(format!("{} is damaged, {}", name, err)))?;
+    let ops: Vec<Op> = match until {
+        None => Vec::new(),
+        Some(until) => {
+            if until < snapshot.manifest.created {
+                return Err(RarianError::Backup(format!(
+                    "{} was taken after that time, restore an earlier snapshot",
+                    name
+                )));
+            }
+            // The op log is read with the current key, which has to read the restored caches too
+            if read(data_path.join(KEY_FILE)).ok() != read(snapshot.path.join(KEY_FILE)).ok() {
+                return Err(RarianError::Backup(format!(
+                    "{} is encrypted with another passphrase, restore it without a time",
+                    name
+                )));
+            }
+            Replica::new(data_path)?
+                .ops()
+                .filter(|op| op.time <= until)
+                .cloned()
+                .collect()
+        }
+    };
+    let previous = create(data_path, SnapshotReason::Restore)?;
+    for file_name in STORES.iter().chain(FILES.iter()) {
+        let target = data_path.join(file_name);
+        if snapshot.has(file_name) {
+            // Each file is complete or untouched, never half copied
+            let partial = data_path.join(format!("{}.{}", file_name, PARTIAL_SUFFIX));
+            let source = snapshot.path.join(file_name);
+            let bytes = read(&source).map_err(|err| read_error(&source, err))?;
+            write(&partial, bytes).map_err(|err| write_error(&partial, err))?;
+            rename(&partial, &target).map_err(|err| write_error(&target, err))?;
+        } else if target.exists() {
+            remove_file(&target).map_err(|err| write_error(&target, err))?;
+        }
+    }
+    let replayed = match ops.is_empty() {
+        true => 0,
+        false => NoteTaker::new(data_path)?.merge_ops(ops)?.len(),
+    };
+    Ok(RestoreReport {
+        snapshot: snapshot.name,
+        previous: previous.name,
+        replayed,
+    })
+}
+
+#[cfg(test)]
+mod backup_test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn snapshots_are_verified_rotated_and_restored_up_to_a_time() {
+        let data_path = PathBuf::from("./testData/backup");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let mut note_taker = NoteTaker::new(&data_path).unwrap();
+        let id = note_taker.add_note("draft", vec![]).unwrap();
+        let config = BackupConfig {
+            startup: 2,
+            daily: 1,
+        };
+        for _ in 0..3 {
+            on_startup(&data_path, &config).unwrap();
+        }
+        let first_daily = daily(&data_path, &config, SystemTime::now()).unwrap();
+        let second_daily = daily(&data_path, &config, SystemTime::now()).unwrap();
+        let base = create(&data_path, SnapshotReason::Manual).unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+        note_taker.edit_note(&id, "second draft").unwrap();
+        let between = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(20));
+        note_taker.edit_note(&id, "final").unwrap();
+        let added = note_taker.add_note("later", vec![]).unwrap();
+        drop(note_taker);
+
+        let listed: Vec<SnapshotReason> = snapshots(&data_path)
+            .unwrap()
+            .iter()
+            .map(|snapshot| snapshot.manifest.reason)
+            .collect();
+        let restored = restore(&data_path, &base.name, Some(between)).unwrap();
+        let note_taker = NoteTaker::new(&data_path).unwrap();
+        let text = note_taker.get_note(&id).unwrap().text.clone();
+        let later = note_taker.get_note(&added).is_some();
+        let too_early = restore(&data_path, &base.name, Some(SystemTime::UNIX_EPOCH));
+        write(base.path.join("notes.json"), "tampered\n").unwrap();
+        let damaged = base.verify();
+        remove_dir_all(&data_path).unwrap();
+
+        assert_eq!(
+            listed,
+            vec![
+                SnapshotReason::Startup,
+                SnapshotReason::Startup,
+                SnapshotReason::Daily,
+                SnapshotReason::Manual
+            ]
+        );
+        assert!(first_daily.is_some());
+        assert!(second_daily.is_none());
+        assert_eq!(restored.replayed, 1);
+        assert_eq!(text, "second draft");
+        assert!(!later);
+        assert!(matches!(too_early, Err(RarianError::Backup(_))));
+        assert!(damaged.unwrap_err().contains("notes.json"));
+    }
+}
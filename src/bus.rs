@@ -0,0 +1,386 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, SystemTime};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::cacher::{Cache, FileCacher};
+use crate::config::Config;
+use crate::error::{RarianError, Result};
+use crate::gatherer::app_gatherer::ActiveProcessEvent;
+use crate::notes::{Note, NoteLinks, NoteSort};
+use crate::reminders::Reminder;
+use crate::sync::SyncOutcome;
+use crate::tasks::{Task, TaskFilter};
+use crate::timeline::Timeline;
+
+/// How long a request waits for its reply by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Reply<T> = Sender<Result<T>>;
+
+/// A command or query for the service thread, answered with a `Response`
+pub trait Request: Debug {
+    type Response: Send + 'static;
+
+    fn into_message(self, reply: Reply<Self::Response>) -> Message;
+}
+
+/// The last `count` distinct apps that were focused, most recent first
+#[derive(Debug, Clone)]
+pub struct RecentApps {
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct CurrentApp;
+
+/// The active notes linked to `link`, newest first
+#[derive(Debug, Clone)]
+pub struct AppNotes {
+    pub link: String,
+}
+
+/// The active notes related to an app in the order of `sort`, pinned notes first. For the focused
+/// app this includes the notes of the file last edited, its project and its git branch.
+#[derive(Debug, Clone)]
+pub struct RelatedNotes {
+    pub app_title: String,
+    pub sort: NoteSort,
+}
+
+/// Adds a note, answered with its id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddNote {
+    pub text: String,
+    pub links: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditNote {
+    pub id: Ulid,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveNote {
+    pub id: Ulid,
+}
+
+/// Makes a note more relevant to `link`, linking it if it wasn't yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoostNote {
+    pub id: Ulid,
+    pub link: String,
+}
+
+/// Pins a note to the top of the notes of `link`, or unpins it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinNote {
+    pub id: Ulid,
+    pub link: String,
+    pub pinned: bool,
+}
+
+/// Saves the order of the notes of `link` for the manual sort
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorderNotes {
+    pub link: String,
+    pub order: Vec<Ulid>,
+}
+
+/// Makes a note a task, changes it, or makes it a plain note again with `None`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetTask {
+    pub id: Ulid,
+    pub task: Option<Task>,
+}
+
+/// Checks or unchecks the `- [ ]` item at `index` of a note
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToggleChecklistItem {
+    pub id: Ulid,
+    pub index: usize,
+}
+
+/// Sets the reminder of a note, or dismisses it with `None`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetReminder {
+    pub id: Ulid,
+    pub reminder: Option<Reminder>,
+}
+
+/// Syncs the notes through the git remote of the config, in the background. The result comes as
+/// `Notification::NotesChanged`, `Notification::SyncConflicts` or `Notification::Error`.
+#[derive(Debug, Clone)]
+pub struct SyncNotes;
+
+/// The task notes of every app and file matching `filter`, open ones first
+#[derive(Debug, Clone)]
+pub struct GetTasks {
+    pub filter: TaskFilter,
+}
+
+/// A note with the notes it references and the notes referencing it
+#[derive(Debug, Clone)]
+pub struct GetNoteLinks {
+    pub id: Ulid,
+}
+
+#[derive(Debug, Clone)]
+pub struct GetTimeline {
+    pub date: NaiveDate,
+}
+
+macro_rules! impl_request {
+    ($request:ident, $response:ty) => {
+        impl Request for $request {
+            type Response = $response;
+
+            fn into_message(self, reply: Reply<Self::Response>) -> Message {
+                Message::$request(self, reply)
+            }
+        }
+    };
+}
+
+impl_request!(RecentApps, Vec<ActiveProcessEvent>);
+impl_request!(CurrentApp, Option<ActiveProcessEvent>);
+impl_request!(AppNotes, Vec<Note>);
+impl_request!(RelatedNotes, Vec<Note>);
+impl_request!(AddNote, Ulid);
+impl_request!(EditNote, ());
+impl_request!(ArchiveNote, ());
+impl_request!(BoostNote, ());
+impl_request!(PinNote, ());
+impl_request!(ReorderNotes, ());
+impl_request!(SetTask, ());
+impl_request!(ToggleChecklistItem, ());
+impl_request!(GetTasks, Vec<Note>);
+impl_request!(SetReminder, ());
+impl_request!(SyncNotes, ());
+impl_request!(GetNoteLinks, NoteLinks);
+impl_request!(GetTimeline, Timeline);
+
+/// Pushed to subscribers when the data they show changes
+#[derive(Debug, Clone)]
+pub enum Notification {
+    CurrentAppChanged(Option<ActiveProcessEvent>),
+    RecentAppsChanged,
+    NotesChanged,
+    /// A reminder became due, after the notes changed to show it
+    ReminderDue(Note),
+    /// A sync merged edits of these notes from two devices, keeping one text as their conflict
+    SyncConflicts(Vec<Note>),
+    /// The config file changed and the running rarian applied it
+    ConfigChanged(Config),
+    Error(RarianError),
+}
+
+/// Everything the service thread handles. Requests are built by `Bus::request`, the rest are
+/// events without a reply.
+#[derive(Debug)]
+pub enum Message {
+    RecentApps(RecentApps, Reply<Vec<ActiveProcessEvent>>),
+    CurrentApp(CurrentApp, Reply<Option<ActiveProcessEvent>>),
+    AppNotes(AppNotes, Reply<Vec<Note>>),
+    RelatedNotes(RelatedNotes, Reply<Vec<Note>>),
+    AddNote(AddNote, Reply<Ulid>),
+    EditNote(EditNote, Reply<()>),
+    ArchiveNote(ArchiveNote, Reply<()>),
+    BoostNote(BoostNote, Reply<()>),
+    PinNote(PinNote, Reply<()>),
+    ReorderNotes(ReorderNotes, Reply<()>),
+    SetTask(SetTask, Reply<()>),
+    ToggleChecklistItem(ToggleChecklistItem, Reply<()>),
+    GetTasks(GetTasks, Reply<Vec<Note>>),
+    SetReminder(SetReminder, Reply<()>),
+    SyncNotes(SyncNotes, Reply<()>),
+    GetNoteLinks(GetNoteLinks, Reply<NoteLinks>),
+    GetTimeline(GetTimeline, Reply<Timeline>),
+    CurrentAppChanged,
+    FileChanged(PathBuf),
+    ConfigChanged,
+    SyncFinished(Result<SyncOutcome>),
+    Subscribe(Sender<Notification>),
+    ReportError(RarianError),
+    Quit,
+}
+
+impl Message {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::RecentApps(..) => "RecentApps",
+            Message::CurrentApp(..) => "CurrentApp",
+            Message::AppNotes(..) => "AppNotes",
+            Message::RelatedNotes(..) => "RelatedNotes",
+            Message::AddNote(..) => "AddNote",
+            Message::EditNote(..) => "EditNote",
+            Message::ArchiveNote(..) => "ArchiveNote",
+            Message::BoostNote(..) => "BoostNote",
+            Message::PinNote(..) => "PinNote",
+            Message::ReorderNotes(..) => "ReorderNotes",
+            Message::SetTask(..) => "SetTask",
+            Message::ToggleChecklistItem(..) => "ToggleChecklistItem",
+            Message::GetTasks(..) => "GetTasks",
+            Message::SetReminder(..) => "SetReminder",
+            Message::SyncNotes(..) => "SyncNotes",
+            Message::GetNoteLinks(..) => "GetNoteLinks",
+            Message::GetTimeline(..) => "GetTimeline",
+            Message::CurrentAppChanged => "CurrentAppChanged",
+            Message::FileChanged(..) => "FileChanged",
+            Message::ConfigChanged => "ConfigChanged",
+            Message::SyncFinished(..) => "SyncFinished",
+            Message::Subscribe(..) => "Subscribe",
+            Message::ReportError(..) => "ReportError",
+            Message::Quit => "Quit",
+        }
+    }
+}
+
+/// A handle to the service thread that any thread can clone and make requests on
+#[derive(Clone)]
+pub struct Bus {
+    tx: Sender<Message>,
+    timeout: Duration,
+}
+
+impl Bus {
+    pub fn new(tx: Sender<Message>) -> Bus {
+        Bus {
+            tx,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The same bus, with requests giving up after `timeout`
+    pub fn with_timeout(self, timeout: Duration) -> Bus {
+        Bus { timeout, ..self }
+    }
+
+    /// Sends `request` and waits for its result
+    pub fn request<R: Request>(&self, request: R) -> Result<R::Response> {
+        let (tx, rx) = channel::<Result<R::Response>>();
+        let message = request.into_message(tx);
+        let name = message.name();
+        self.send(message)?;
+        match rx.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(RarianError::Timeout(name)),
+            Err(RecvTimeoutError::Disconnected) => Err(RarianError::Disconnected),
+        }
+    }
+
+    /// Receives a `Notification` on every change, starting with the currently focused app
+    pub fn subscribe(&self) -> Result<Receiver<Notification>> {
+        let (tx, rx) = channel::<Notification>();
+        self.send(Message::Subscribe(tx))?;
+        Ok(rx)
+    }
+
+    /// Asks the service thread to stop, which only fails if it has stopped already
+    pub fn quit(&self) -> Result<()> {
+        self.send(Message::Quit)
+    }
+
+    pub(crate) fn report_error(&self, err: RarianError) -> Result<()> {
+        self.send(Message::ReportError(err))
+    }
+
+    pub(crate) fn current_app_changed(&self) -> Result<()> {
+        self.send(Message::CurrentAppChanged)
+    }
+
+    pub(crate) fn file_changed(&self, path: PathBuf) -> Result<()> {
+        self.send(Message::FileChanged(path))
+    }
+
+    pub(crate) fn config_changed(&self) -> Result<()> {
+        self.send(Message::ConfigChanged)
+    }
+
+    pub(crate) fn sync_finished(&self, result: Result<SyncOutcome>) -> Result<()> {
+        self.send(Message::SyncFinished(result))
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.tx.send(message).map_err(|_| RarianError::Disconnected)
+    }
+}
+
+/// Hooks run on the service thread around every message, in the order they were added
+pub trait Middleware: Send {
+    fn before(&mut self, _message: &Message) {}
+
+    /// `result` is the error the message failed with, if any
+    fn after(&mut self, _name: &'static str, _result: &Result<()>, _elapsed: Duration) {}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum AuditedChange {
+    Added(AddNote),
+    Edited(EditNote),
+    Archived(ArchiveNote),
+    Boosted(BoostNote),
+    Pinned(PinNote),
+    Reordered(ReorderNotes),
+    TaskChanged(SetTask),
+    Checked(ToggleChecklistItem),
+    Reminded(SetReminder),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AuditRecord {
+    time: SystemTime,
+    change: AuditedChange,
+    error: Option<String>,
+}
+
+/// Appends every change to the notes, and whether it failed, to `audit.json` in the data path
+pub struct AuditLog {
+    cacher: FileCacher,
+    pending: Option<AuditedChange>,
+}
+
+impl AuditLog {
+    pub fn new(data_path: PathBuf) -> Result<AuditLog> {
+        Ok(AuditLog {
+            cacher: FileCacher::new(data_path.join("audit.json"))?,
+            pending: None,
+        })
+    }
+}
+
+impl Middleware for AuditLog {
+    fn before(&mut self, message: &Message) {
+        self.pending = match message {
+            Message::AddNote(request, _) => Some(AuditedChange::Added(request.clone())),
+            Message::EditNote(request, _) => Some(AuditedChange::Edited(request.clone())),
+            Message::ArchiveNote(request, _) => Some(AuditedChange::Archived(request.clone())),
+            Message::BoostNote(request, _) => Some(AuditedChange::Boosted(request.clone())),
+            Message::PinNote(request, _) => Some(AuditedChange::Pinned(request.clone())),
+            Message::ReorderNotes(request, _) => Some(AuditedChange::Reordered(request.clone())),
+            Message::SetTask(request, _) => Some(AuditedChange::TaskChanged(request.clone())),
+            Message::ToggleChecklistItem(request, _) => {
+                Some(AuditedChange::Checked(request.clone()))
+            }
+            Message::SetReminder(request, _) => Some(AuditedChange::Reminded(request.clone())),
+            _ => None,
+        };
+    }
+
+    fn after(&mut self, _name: &'static str, result: &Result<()>, _elapsed: Duration) {
+        if let Some(change) = self.pending.take() {
+            let record = AuditRecord {
+                time: SystemTime::now(),
+                change,
+                error: result.as_ref().err().map(|err| err.to_string()),
+            };
+            // An audit log that can't be written mustn't stop the change it records
+            let _ = self.cacher.cache(&record);
+        }
+    }
+}
@@ -1,8 +1,74 @@
-use anyhow::{Context, Result};
+use crate::crypto::{is_encrypted_record, key_for, Key};
+use crate::error::{RarianError, Result};
+use crate::schema::{Envelope, Migrations};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fs::{File, OpenOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, read_to_string, rename, File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Held while a record is appended or a cache is rewritten, so no record is appended between
+/// reading a cache and rewriting it
+static WRITING: Mutex<()> = Mutex::new(());
+
+fn lock_writes() -> MutexGuard<'static, ()> {
+    WRITING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How often each cache was replaced by a rewrite, so the handles opened before reopen it instead
+/// of appending to the file it replaced
+static REWRITES: Mutex<Option<HashMap<PathBuf, u64>>> = Mutex::new(None);
+
+fn rewrites_of(path: &Path) -> u64 {
+    let rewrites = REWRITES.lock().unwrap_or_else(PoisonError::into_inner);
+    rewrites
+        .as_ref()
+        .and_then(|rewrites| rewrites.get(path).copied())
+        .unwrap_or_default()
+}
+
+fn count_rewrite(path: &Path) {
+    let mut rewrites = REWRITES.lock().unwrap_or_else(PoisonError::into_inner);
+    *rewrites
+        .get_or_insert_with(HashMap::new)
+        .entry(path.to_path_buf())
+        .or_default() += 1;
+}
+
+fn open_cache(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+}
+
+/// A lock on a cache file that other processes respect, shared by readers and exclusive to a
+/// writer, so a read-only command never reads a cache halfway through a rewrite
+struct FileLock<'a> {
+    file: &'a File,
+}
+
+impl<'a> FileLock<'a> {
+    fn shared(file: &'a File) -> FileLock<'a> {
+        // A file system without locks is read and written unlocked, as before
+        let _ = file.lock_shared();
+        FileLock { file }
+    }
+
+    fn exclusive(file: &'a File) -> FileLock<'a> {
+        let _ = file.lock();
+        FileLock { file }
+    }
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
 
 pub trait Cache<T>
 where
@@ -18,21 +84,233 @@ where
     fn load_from_cache(&mut self) -> Vec<T>;
 }
 
+/// A line of a cache that can't be read
+#[derive(Debug, Clone, PartialEq)]
+pub struct BadLine {
+    pub number: usize,
+    pub line: String,
+    pub reason: String,
+}
+
 pub struct FileCacher {
     file: File,
+    path: PathBuf,
+    /// Encrypts the records when the data path is encrypted
+    key: Option<Key>,
+    /// The rewrites of the cache when `file` was opened
+    rewrites: u64,
+    /// The lines the last load couldn't read
+    unreadable: usize,
+    /// Why the last load couldn't read the cache at all
+    load_error: Option<RarianError>,
 }
 
 impl FileCacher {
-    pub fn new(path: PathBuf) -> FileCacher {
-        let try_create_file = OpenOptions::new()
-            .read(true)
+    pub fn new(path: PathBuf) -> Result<FileCacher> {
+        let key = key_for(&path)?;
+        let rewrites = rewrites_of(&path);
+        match open_cache(&path) {
+            Ok(file) => Ok(FileCacher {
+                file,
+                path,
+                key,
+                rewrites,
+                unreadable: 0,
+                load_error: None,
+            }),
+            Err(err) => Err(RarianError::OpenCache {
+                path,
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    /// The name the records are encrypted with
+    fn store(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn encode<T: Serialize>(&self, obj: &T) -> Result<String> {
+        let write_error = |err: serde_json::Error| RarianError::WriteCache {
+            path: self.path.clone(),
+            message: err.to_string(),
+        };
+        let envelope = Envelope {
+            version: Migrations::default().current_version(&self.store()),
+            record: serde_json::to_value(obj).map_err(write_error)?,
+        };
+        let line = serde_json::to_string(&envelope).map_err(write_error)?;
+        match &self.key {
+            Some(key) => key.encrypt(&self.store(), &line),
+            None => Ok(line),
+        }
+    }
+
+    /// Reads a record and upgrades it to the current version, returning the version it was in, or
+    /// why it can't be read
+    fn read_record<T: DeserializeOwned>(
+        &self,
+        line: &str,
+    ) -> std::result::Result<(u32, T), String> {
+        let line = match (is_encrypted_record(line), &self.key) {
+            (true, Some(key)) => key
+                .decrypt(&self.store(), line)
+                .ok_or("can't be decrypted with the key of the data path")?,
+            (true, None) => return Err("is encrypted but the data path isn't".to_string()),
+            (false, _) => line.to_string(),
+        };
+        let value = serde_json::from_str(&line).map_err(|err| format!("invalid JSON: {}", err))?;
+        let envelope = Envelope::from_value(value);
+        let version = envelope.version;
+        let record = Migrations::default().upgrade(&self.store(), envelope)?;
+        let record = serde_json::from_value(record)
+            .map_err(|err| format!("doesn't match the format of version {}: {}", version, err))?;
+        Ok((version, record))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, line: &str) -> Option<T> {
+        self.read_record(line).ok().map(|(_, record)| record)
+    }
+
+    /// Opens the cache again if a rewrite replaced the file since it was opened
+    fn reopen_if_rewritten(&mut self) -> Result<()> {
+        let rewrites = rewrites_of(&self.path);
+        if rewrites == self.rewrites {
+            return Ok(());
+        }
+        self.file = open_cache(&self.path).map_err(|err| RarianError::OpenCache {
+            path: self.path.clone(),
+            message: err.to_string(),
+        })?;
+        self.rewrites = rewrites;
+        Ok(())
+    }
+
+    fn read_lines(&self) -> Result<String> {
+        read_to_string(&self.path).map_err(|err| RarianError::ReadFile {
+            path: self.path.clone(),
+            message: err.to_string(),
+        })
+    }
+
+    /// Replaces the lines of the cache with what `update` returns for each line and its number,
+    /// dropping blank lines and the ones it returns `None` for. The lines are written to a
+    /// temporary file that replaces the cache once it's on disk, so a crash leaves either the old
+    /// or the new cache, and every handle appending to it reopens it before appending again.
+    fn rewrite_lines(
+        &self,
+        mut update: impl FnMut(usize, &str) -> Result<Option<String>>,
+    ) -> Result<()> {
+        let _writing = lock_writes();
+        let _locked = FileLock::exclusive(&self.file);
+        let mut kept = String::new();
+        for (index, line) in self.read_lines()?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(line) = update(index + 1, line)? {
+                kept.push_str(&(line + "\n"));
+            }
+        }
+        let write_error = |err: std::io::Error| RarianError::WriteCache {
+            path: self.path.clone(),
+            message: err.to_string(),
+        };
+        let temporary_path = self.path.with_file_name(format!("{}.tmp", self.store()));
+        let mut temporary = File::create(&temporary_path).map_err(write_error)?;
+        temporary.write_all(kept.as_bytes()).map_err(write_error)?;
+        temporary.sync_all().map_err(write_error)?;
+        rename(&temporary_path, &self.path).map_err(write_error)?;
+        count_rewrite(&self.path);
+        Ok(())
+    }
+
+    /// Replaces every record with what `update` returns for it, dropping the ones it returns
+    /// `None` for, and returns how many were dropped. Records are written in the current version,
+    /// and the ones that can't be read are kept as they are.
+    pub fn rewrite<T>(&mut self, mut update: impl FnMut(T) -> Option<T>) -> Result<usize>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut dropped = 0;
+        self.rewrite_lines(|_, line| match self.decode(line).map(&mut update) {
+            Some(Some(obj)) => self.encode(&obj).map(Some),
+            Some(None) => {
+                dropped += 1;
+                Ok(None)
+            }
+            None => Ok(Some(line.to_string())),
+        })?;
+        Ok(dropped)
+    }
+
+    /// What the last `load_from_cache` left out for not being readable, as an error to report:
+    /// the cache itself when it couldn't be read, otherwise the lines that couldn't
+    pub fn unreadable(&self) -> Option<RarianError> {
+        if let Some(err) = &self.load_error {
+            return Some(err.clone());
+        }
+        (self.unreadable > 0).then(|| RarianError::UnreadableRecords {
+            path: self.path.clone(),
+            count: self.unreadable,
+        })
+    }
+
+    /// Counts the records in each version, and lists the lines that can't be read as a `T`
+    pub fn check<T: DeserializeOwned>(&self) -> (BTreeMap<u32, usize>, Vec<BadLine>) {
+        let mut versions = BTreeMap::new();
+        let mut bad_lines = Vec::new();
+        let text = {
+            let _locked = FileLock::shared(&self.file);
+            self.read_lines().unwrap_or_default()
+        };
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.read_record::<T>(line) {
+                Ok((version, _)) => *versions.entry(version).or_default() += 1,
+                Err(reason) => bad_lines.push(BadLine {
+                    number: index + 1,
+                    line: line.to_string(),
+                    reason,
+                }),
+            }
+        }
+        (versions, bad_lines)
+    }
+
+    /// Moves `bad_lines` out of the cache, appending them as they are to a file named after the
+    /// cache in `dir`, from where they can be fixed and appended back
+    pub fn quarantine(&mut self, bad_lines: &[BadLine], dir: &Path) -> Result<()> {
+        if bad_lines.is_empty() {
+            return Ok(());
+        }
+        let quarantine_path = dir.join(self.store());
+        let write_error = |err: std::io::Error| RarianError::WriteCache {
+            path: quarantine_path.clone(),
+            message: err.to_string(),
+        };
+        create_dir_all(dir).map_err(write_error)?;
+        let mut quarantine = OpenOptions::new()
             .append(true)
             .create(true)
-            .open(path);
-        match try_create_file {
-            Ok(file) => FileCacher { file },
-            Err(err) => panic!("Error creating cache file: {:?}", err),
+            .open(&quarantine_path)
+            .map_err(write_error)?;
+        for bad_line in bad_lines {
+            quarantine
+                .write_all((bad_line.line.clone() + "\n").as_bytes())
+                .map_err(write_error)?;
         }
+        self.rewrite_lines(|number, line| {
+            let quarantined = bad_lines
+                .iter()
+                .any(|bad_line| bad_line.number == number && bad_line.line == line);
+            Ok((!quarantined).then(|| line.to_string()))
+        })
     }
 }
 
@@ -41,10 +319,16 @@ where
     T: Serialize + Sized,
 {
     fn cache(&mut self, obj: &T) -> Result<()> {
-        let line = serde_json::to_string(&obj).expect("Serialization failed");
-        self.file
+        let line = self.encode(obj)?;
+        let _writing = lock_writes();
+        self.reopen_if_rewritten()?;
+        let _locked = FileLock::exclusive(&self.file);
+        (&self.file)
             .write_all((line + "\n").as_bytes())
-            .context("failed to write line to file")
+            .map_err(|err| RarianError::WriteCache {
+                path: self.path.clone(),
+                message: err.to_string(),
+            })
     }
 }
 
@@ -53,20 +337,83 @@ where
     T: for<'a> Deserialize<'a>,
 {
     fn load_from_cache(&mut self) -> Vec<T> {
+        self.load_error = None;
+        if let Err(err) = self.reopen_if_rewritten() {
+            self.load_error = Some(err);
+            return Vec::new();
+        }
         let mut read_buffer = String::new();
-        match self.file.read_to_string(&mut read_buffer) {
-            Ok(_) => read_buffer
-                .lines()
-                .filter_map(|line| {
-                    serde_json::from_str::<T>(&line)
-                        .context("Failed to parse line")
-                        .ok()
-                })
-                .collect(),
-            Err(e) => {
-                println!("Error reading from cache: {}", e);
+        let read = {
+            let _locked = FileLock::shared(&self.file);
+            (&self.file).read_to_string(&mut read_buffer)
+        };
+        match read {
+            Ok(_) => {
+                let mut unreadable = 0;
+                let records = read_buffer
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .filter_map(|line| {
+                        let record = self.decode(line);
+                        unreadable += usize::from(record.is_none());
+                        record
+                    })
+                    .collect();
+                self.unreadable = unreadable;
+                records
+            }
+            Err(err) => {
+                self.load_error = Some(RarianError::ReadFile {
+                    path: self.path.clone(),
+                    message: err.to_string(),
+                });
                 Vec::new()
             }
         }
     }
 }
+
+#[cfg(test)]
+mod cacher_test {
+    use super::*;
+
+    #[test]
+    fn records_appended_after_a_rewrite_end_up_in_the_new_cache() {
+        let data_path = PathBuf::from("./testData/cacher_rewrite");
+        let _ = std::fs::remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let path = data_path.join("words.json");
+        let mut writer = FileCacher::new(path.clone()).unwrap();
+        let mut rewriter = FileCacher::new(path.clone()).unwrap();
+        for word in ["keep", "drop"] {
+            writer.cache(&word.to_string()).unwrap();
+        }
+        let dropped = rewriter
+            .rewrite(|word: String| (word != "drop").then_some(word))
+            .unwrap();
+        writer.cache(&"after".to_string()).unwrap();
+        let words: Vec<String> = FileCacher::new(path).unwrap().load_from_cache();
+        let leftovers = std::fs::read_dir(&data_path).unwrap().count();
+        std::fs::remove_dir_all(&data_path).unwrap();
+
+        assert_eq!(dropped, 1);
+        assert_eq!(words, vec!["keep".to_string(), "after".to_string()]);
+        assert_eq!(leftovers, 1);
+    }
+
+    #[test]
+    fn a_cache_that_cant_be_read_is_reported() {
+        let data_path = PathBuf::from("./testData/cacher_unreadable");
+        let _ = std::fs::remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let path = data_path.join("words.json");
+        std::fs::write(&path, [0xff, 0xfe, b'\n']).unwrap();
+        let mut cacher = FileCacher::new(path).unwrap();
+        let words: Vec<String> = cacher.load_from_cache();
+        let error = cacher.unreadable();
+        std::fs::remove_dir_all(&data_path).unwrap();
+
+        assert!(words.is_empty());
+        assert!(matches!(error, Some(RarianError::ReadFile { .. })));
+    }
+}
@@ -0,0 +1,581 @@
+use anyhow::{bail, Result};
+use chrono::{Local, NaiveDate};
+use clap::{Parser, Subcommand};
+use std::fs::write;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::external_editor::edit_in_external_editor;
+use crate::passphrase::read_new_passphrase;
+use rarian::backup::{create, restore, snapshots, SnapshotReason};
+use rarian::config::Config;
+use rarian::crypto::{is_encrypted, rekey, KdfParams};
+use rarian::digest::{load_template, Digest, DigestPeriod};
+use rarian::export::Vault;
+use rarian::graph::{Graph, GraphFilter, GraphFormat};
+use rarian::import::{import, ImportFormat};
+use rarian::notes::{Note, NoteTaker};
+use rarian::oplog::{FolderTransport, StreamTransport};
+use rarian::reminders::{parse_time, Reminder, Trigger};
+use rarian::retention::{enforce, purge, Purge};
+use rarian::schema::doctor;
+use rarian::sync::{apply, GitSync};
+use rarian::tasks::{Priority, Task, TaskFilter};
+use rarian::timeline::Timeline;
+use ulid::Ulid;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    #[arg(short, long)]
+    pub data_path: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the apps, file changes and notes of a single day in chronological order
+    Timeline {
+        /// The day to show, formatted as YYYY-MM-DD. Defaults to today
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Print a Markdown summary of the apps, projects, files, notes and code tasks of a day or week
+    Digest {
+        /// Summarize a single day, this is the default
+        #[arg(long)]
+        day: bool,
+        /// Summarize the week (Monday to Sunday) of the date
+        #[arg(long, conflicts_with = "day")]
+        week: bool,
+        /// A day in the period to summarize, formatted as YYYY-MM-DD. Defaults to today
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Edit a note in $VISUAL/$EDITOR, or write a new one if no id is given
+    Edit {
+        /// The id of the note to edit
+        id: Option<Ulid>,
+        /// What a new note is linked to, like an app title or a file path. Can be repeated
+        #[arg(short, long = "link")]
+        links: Vec<String>,
+    },
+    /// List the task notes of every app and file, open ones first and then by due date and priority
+    Tasks {
+        /// Include the tasks that are done
+        #[arg(long)]
+        done: bool,
+        /// Only tasks due on or before this day, formatted as YYYY-MM-DD
+        #[arg(long)]
+        due_before: Option<NaiveDate>,
+        /// Only tasks that are past their due date
+        #[arg(long, conflicts_with = "due_before")]
+        overdue: bool,
+        /// Only tasks of this priority: high, medium or low
+        #[arg(short, long)]
+        priority: Option<Priority>,
+        /// Only tasks linked to this app title or file path
+        #[arg(short, long)]
+        link: Option<String>,
+    },
+    /// Make a note a task, or change its due date, priority or whether it is done
+    Task {
+        /// The id of the note
+        id: Ulid,
+        /// When the task is due, formatted as YYYY-MM-DD
+        #[arg(long)]
+        due: Option<NaiveDate>,
+        /// Remove the due date
+        #[arg(long, conflicts_with = "due")]
+        no_due: bool,
+        /// high, medium or low
+        #[arg(short, long)]
+        priority: Option<Priority>,
+        /// Check the task
+        #[arg(long)]
+        done: bool,
+        /// Uncheck the task
+        #[arg(long, conflicts_with = "done")]
+        reopen: bool,
+        /// Make the task a plain note again
+        #[arg(long, conflicts_with_all = ["due", "no_due", "priority", "done", "reopen"])]
+        remove: bool,
+    },
+    /// Remind of a note at a time or the next time an app or project is focused, or snooze it.
+    /// Without any option, prints the reminder of the note
+    Remind {
+        /// The id of the note
+        id: Ulid,
+        /// When to remind, like 2h, 15:00, tomorrow, friday or YYYY-MM-DD [HH:MM]
+        #[arg(long)]
+        at: Option<String>,
+        /// Hide the note until this time, in the same formats as --at
+        #[arg(long, conflicts_with = "at")]
+        snooze: Option<String>,
+        /// Remind the next time an app with this in its title is focused
+        #[arg(long, conflicts_with_all = ["at", "snooze"])]
+        app: Option<String>,
+        /// Remind the next time a file in, or an app titled after, this git repository is focused
+        #[arg(long, conflicts_with_all = ["at", "snooze"])]
+        project: Option<String>,
+        /// Remove the reminder, also dismissing it if it is due
+        #[arg(long, conflicts_with_all = ["at", "snooze", "app", "project"])]
+        clear: bool,
+    },
+    /// Export the notes and the apps, files and projects they link to as a graph
+    Graph {
+        /// dot for Graphviz, graphml for Gephi or yEd, or json
+        #[arg(short, long, default_value = "dot")]
+        format: GraphFormat,
+        /// Only notes created or edited on or after this day, formatted as YYYY-MM-DD
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Only notes created or edited on or before this day, formatted as YYYY-MM-DD
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Only notes with this #tag. Can be repeated to allow any of several tags
+        #[arg(short, long = "tag")]
+        tags: Vec<String>,
+        /// Only notes linked to a file in this git repository
+        #[arg(short, long)]
+        project: Option<String>,
+        /// Include archived notes
+        #[arg(long)]
+        archived: bool,
+        /// Write the graph to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import notes from a folder of Markdown files, a todo.txt file or a JSON file, skipping
+    /// the ones that are already in rarian
+    Import {
+        /// The file or folder to import
+        path: PathBuf,
+        /// markdown, todotxt or json. Guessed from the extension by default, folders being Markdown
+        #[arg(short, long)]
+        format: Option<ImportFormat>,
+        /// What imported notes without links are linked to. Can be repeated
+        #[arg(short, long = "link")]
+        links: Vec<String>,
+        /// Only report what would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Sync the notes with other devices through the git remote in the `[sync]` table of the config
+    Sync,
+    /// Exchange the op log of the notes with other devices, through a shared folder or a TCP
+    /// connection, and apply their changes
+    #[command(group(clap::ArgGroup::new("transport").required(true)))]
+    Replicate {
+        /// A folder every device can read and write, like a network drive
+        #[arg(group = "transport")]
+        folder: Option<PathBuf>,
+        /// Wait for another device to connect on this address, like 0.0.0.0:7878
+        #[arg(long, group = "transport")]
+        listen: Option<String>,
+        /// Connect to a device listening on this address
+        #[arg(long, group = "transport")]
+        connect: Option<String>,
+    },
+    /// Erase app and file events from the activity logs. Without any option, removes the events
+    /// older than the `[retention]` table of the config keeps
+    Purge {
+        /// A regex for the window titles or app names whose events are erased
+        #[arg(long)]
+        app: Option<String>,
+        /// A glob for the paths whose file events are erased, where `**` matches across folders
+        #[arg(long)]
+        path: Option<String>,
+        /// Only erase events before this day, formatted as YYYY-MM-DD. Alone, erases every event
+        /// before it
+        #[arg(long)]
+        before: Option<NaiveDate>,
+    },
+    /// Check the caches in the data path for lines that can't be read and records in old formats
+    Doctor {
+        /// Move the lines that can't be read to the `quarantine` folder of the data path, and
+        /// rewrite the other records in the current format. Close rarian first
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Encrypt the notes and activity logs in the data path with a new passphrase. A data path in
+    /// plaintext is encrypted from then on, and asks for the passphrase whenever rarian starts.
+    /// Close rarian first
+    Rekey,
+    /// Take, list or restore snapshots of the notes and logs in the `backups` folder of the data
+    /// path
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    /// Export the notes to another format
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+}
+
+impl Command {
+    /// Whether the command changes the data path, which needs it to itself. The others read while
+    /// rarian runs.
+    pub fn writes(&self) -> bool {
+        match self {
+            Command::Timeline { .. }
+            | Command::Digest { .. }
+            | Command::Tasks { .. }
+            | Command::Graph { .. }
+            | Command::Export { .. } => false,
+            Command::Import { dry_run, .. } => !dry_run,
+            Command::Doctor { fix } => *fix,
+            Command::Backup { action } => matches!(action, BackupAction::Restore { .. }),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BackupAction {
+    /// List the snapshots, oldest first, and whether their files match their checksums
+    List,
+    /// Take a snapshot now, which is kept until removed
+    Create,
+    /// Replace the notes and logs with a snapshot, after taking a snapshot of the current ones.
+    /// Close rarian first
+    Restore {
+        /// The name of the snapshot, as listed
+        snapshot: String,
+        /// Replay the changes to the text, links and status of the notes made after the snapshot
+        /// up to this time, like `15:00` or `YYYY-MM-DD HH:MM`
+        #[arg(long)]
+        until: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportFormat {
+    /// Write every note as a Markdown page with YAML front-matter, with index pages for the apps,
+    /// files and projects, into an Obsidian or Logseq vault. Only changed pages are rewritten
+    Markdown {
+        /// The vault, or a folder in it
+        dir: PathBuf,
+    },
+}
+
+pub fn run_command(command: Command, config: &Config) -> Result<()> {
+    match command {
+        Command::Timeline { date } => {
+            let date = date.unwrap_or_else(|| Local::now().date_naive());
+            print!("{}", Timeline::load(&config.data_path, date)?);
+        }
+        Command::Digest { week, date, .. } => {
+            let date = date.unwrap_or_else(|| Local::now().date_naive());
+            let period = if week {
+                DigestPeriod::Week
+            } else {
+                DigestPeriod::Day
+            };
+            let template = load_template(&config.data_path, period);
+            let digest = Digest::load(&config.data_path, period, date)?;
+            print!("{}", digest.render(&template));
+        }
+        Command::Edit { id, links } => edit_note(config, id, links)?,
+        Command::Tasks {
+            done,
+            due_before,
+            overdue,
+            priority,
+            link,
+        } => {
+            let today = Local::now().date_naive();
+            let filter = TaskFilter {
+                include_done: done,
+                due_before: match overdue {
+                    true => today.pred_opt(),
+                    false => due_before,
+                },
+                priority,
+                link,
+            };
+            let note_taker = NoteTaker::new(config.data_path.as_path())?;
+            for note in note_taker.tasks(&filter) {
+                print!("{}", format_task(&note, today));
+            }
+        }
+        Command::Task {
+            id,
+            due,
+            no_due,
+            priority,
+            done,
+            reopen,
+            remove,
+        } => {
+            let mut note_taker = NoteTaker::new(config.data_path.as_path())?;
+            let Some(note) = note_taker.get_note(&id) else {
+                bail!("could not find note with id {}", id);
+            };
+            let mut task = note.task.clone().unwrap_or_default();
+            if let Some(due) = due {
+                task.due = Some(due);
+            }
+            if no_due {
+                task.due = None;
+            }
+            if priority.is_some() {
+                task.priority = priority;
+            }
+            task.done = (task.done || done) && !reopen;
+            let task: Option<Task> = if remove { None } else { Some(task) };
+            note_taker.set_task(&id, task)?;
+        }
+        Command::Remind {
+            id,
+            at,
+            snooze,
+            app,
+            project,
+            clear,
+        } => {
+            let mut note_taker = NoteTaker::new(config.data_path.as_path())?;
+            let Some(note) = note_taker.get_note(&id) else {
+                bail!("could not find note with id {}", id);
+            };
+            let parse = |time: &str| match parse_time(time, Local::now()) {
+                Some(time) => Ok(time.into()),
+                None => Err(anyhow::anyhow!("could not understand the time {}", time)),
+            };
+            let trigger = if let Some(at) = at {
+                Trigger::At(parse(&at)?)
+            } else if let Some(snooze) = snooze {
+                Trigger::Snooze(parse(&snooze)?)
+            } else if app.is_some() || project.is_some() {
+                Trigger::Context { app, project }
+            } else if clear {
+                return Ok(note_taker.set_reminder(&id, None)?);
+            } else {
+                match &note.reminder {
+                    Some(reminder) if reminder.is_due() => println!("{} (due)", reminder),
+                    Some(reminder) => println!("{}", reminder),
+                    None => println!("no reminder"),
+                }
+                return Ok(());
+            };
+            note_taker.set_reminder(&id, Some(Reminder::new(trigger)))?;
+        }
+        Command::Graph {
+            format,
+            from,
+            to,
+            tags,
+            project,
+            archived,
+            output,
+        } => {
+            let filter = GraphFilter {
+                from,
+                to,
+                tags,
+                project,
+                include_archived: archived,
+            };
+            let note_taker = NoteTaker::new(config.data_path.as_path())?;
+            let graph = Graph::from_notes(&note_taker, &filter).render(format);
+            match output {
+                Some(path) => write(path, graph)?,
+                None => print!("{}", graph),
+            }
+        }
+        Command::Import {
+            path,
+            format,
+            links,
+            dry_run,
+        } => {
+            let Some(format) = format.or_else(|| ImportFormat::detect(&path)) else {
+                bail!(
+                    "could not tell the format of {}, use --format",
+                    path.display()
+                );
+            };
+            let entries = format.importer().read(&path)?;
+            let mut note_taker = NoteTaker::new(config.data_path.as_path())?;
+            print!("{}", import(&mut note_taker, entries, &links, dry_run)?);
+        }
+        Command::Sync => {
+            let Some(sync_config) = config.sync.clone() else {
+                bail!("no [sync] remote in the config");
+            };
+            let mut note_taker = NoteTaker::new(config.data_path.as_path())?;
+            let notes: Vec<Note> = note_taker.notes().cloned().collect();
+            let outcome = GitSync::new(&config.data_path, sync_config).sync(&notes)?;
+            let changed = apply(&mut note_taker, &outcome)?;
+            println!("{} notes changed", changed.len());
+            for id in outcome.conflicts {
+                if let Some(note) = note_taker.get_note(&id) {
+                    println!("conflict in {} ({}), edit it to resolve", note.title(), id);
+                }
+            }
+        }
+        Command::Replicate {
+            folder,
+            listen,
+            connect,
+        } => {
+            let mut note_taker = NoteTaker::new(config.data_path.as_path())?;
+            let device = note_taker.replica().device().to_string();
+            let changed = match (folder, listen, connect) {
+                (Some(_), _, _) if is_encrypted(&config.data_path) => {
+                    bail!("the notes are encrypted and the folder would hold them in plaintext")
+                }
+                (Some(folder), _, _) => {
+                    note_taker.replicate(&mut FolderTransport::new(folder, &device))?
+                }
+                (_, Some(address), _) => {
+                    let (stream, peer) = TcpListener::bind(address)?.accept()?;
+                    println!("replicating with {}", peer);
+                    note_taker.replicate(&mut StreamTransport::new(stream, false))?
+                }
+                (_, _, Some(address)) => {
+                    let stream = TcpStream::connect(address)?;
+                    note_taker.replicate(&mut StreamTransport::new(stream, true))?
+                }
+                _ => unreachable!("clap requires a transport"),
+            };
+            println!("{} notes changed", changed.len());
+        }
+        Command::Purge { app, path, before } => {
+            let report = match (&app, &path, before) {
+                (None, None, None) => {
+                    if !config.retention.is_enabled() {
+                        bail!("give --app, --path or --before, or set [retention] in the config");
+                    }
+                    enforce(&config.data_path, &config.retention, SystemTime::now())?
+                }
+                _ => purge(
+                    &config.data_path,
+                    &Purge::new(app.as_deref(), path.as_deref(), before)?,
+                )?,
+            };
+            print!("{}", report);
+        }
+        Command::Doctor { fix } => {
+            let reports = doctor(&config.data_path, fix)?;
+            for report in reports.iter() {
+                print!("{}", report);
+            }
+            let bad_lines = reports.iter().any(|report| !report.bad_lines.is_empty());
+            if bad_lines && !fix {
+                println!("run rarian doctor --fix to move the lines that can't be read to the quarantine");
+            }
+        }
+        Command::Backup { action } => match action {
+            BackupAction::List => {
+                for snapshot in snapshots(&config.data_path)? {
+                    print!("{}", snapshot);
+                }
+            }
+            BackupAction::Create => {
+                let snapshot = create(&config.data_path, SnapshotReason::Manual)?;
+                println!("created {}", snapshot.name);
+            }
+            BackupAction::Restore { snapshot, until } => {
+                let until = match until {
+                    Some(until) => match parse_time(&until, Local::now()) {
+                        Some(time) => Some(time.into()),
+                        None => bail!("could not understand the time {}", until),
+                    },
+                    None => None,
+                };
+                print!("{}", restore(&config.data_path, &snapshot, until)?);
+            }
+        },
+        Command::Rekey => {
+            let encrypted = is_encrypted(&config.data_path);
+            let report = rekey(
+                &config.data_path,
+                &read_new_passphrase()?,
+                &KdfParams::default(),
+            )?;
+            match encrypted {
+                true => println!("re-encrypted with the new passphrase"),
+                false => println!("encrypted, rarian will ask for the passphrase when it starts"),
+            }
+            if report.quarantined > 0 {
+                println!(
+                    "{} records couldn't be decrypted and were moved to the quarantine folder",
+                    report.quarantined
+                );
+            }
+            if report.snapshots_removed > 0 {
+                println!(
+                    "{} snapshots the old passphrase opened were removed, rarian backup create takes a new one",
+                    report.snapshots_removed
+                );
+            }
+        }
+        Command::Export {
+            format: ExportFormat::Markdown { dir },
+        } => {
+            let note_taker = NoteTaker::new(config.data_path.as_path())?;
+            let summary = Vault::from_notes(&note_taker).write(&dir)?;
+            println!(
+                "{} pages written, {} unchanged, {} removed",
+                summary.written.len(),
+                summary.unchanged,
+                summary.removed.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A task on one line, followed by its checklist items
+fn format_task(note: &Note, today: NaiveDate) -> String {
+    let task = note.task.clone().unwrap_or_default();
+    let mut line = format!("[{}] {}", if task.done { "x" } else { " " }, note.title());
+    if let Some(due) = task.due {
+        let overdue = if task.is_overdue(today) {
+            ", overdue"
+        } else {
+            ""
+        };
+        line.push_str(&format!(" (due {}{})", due, overdue));
+    }
+    if let Some(priority) = task.priority {
+        line.push_str(&format!(" !{}", priority));
+    }
+    line.push_str(&format!("  {}\n", note.id));
+    for item in note.checklist() {
+        line.push_str(&format!(
+            "    [{}] {}\n",
+            if item.done { "x" } else { " " },
+            item.text
+        ));
+    }
+    line
+}
+
+fn edit_note(config: &Config, id: Option<Ulid>, links: Vec<String>) -> Result<()> {
+    if is_encrypted(&config.data_path) {
+        bail!("the notes are encrypted and the editor would get them in a plaintext file");
+    }
+    let mut note_taker = NoteTaker::new(config.data_path.as_path())?;
+    let text = match id {
+        Some(id) => match note_taker.get_note(&id) {
+            Some(note) => note.text.clone(),
+            None => bail!("could not find note with id {}", id),
+        },
+        None => String::new(),
+    };
+    match (edit_in_external_editor(&text), id) {
+        (Ok(Some(edited)), Some(id)) => note_taker.edit_note(&id, &edited)?,
+        (Ok(Some(edited)), None) => {
+            note_taker.add_note(&edited, links)?;
+        }
+        (Ok(None), _) => println!("note unchanged"),
+        (Err(err), _) => bail!("note unchanged: {}", err),
+    }
+    Ok(())
+}
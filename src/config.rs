@@ -1,5 +1,9 @@
-use anyhow;
-use clap::Parser;
+use crate::backup::BackupConfig;
+use crate::error::{RarianError, Result};
+use crate::reminders::NotifierKind;
+use crate::retention::RetentionConfig;
+use crate::sync::SyncConfig;
+use anyhow::{self, Context};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::io::Read;
@@ -11,53 +15,105 @@ use std::{
 };
 use toml;
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(short, long)]
-    data_path: Option<PathBuf>,
-}
+pub const CONFIG_FILE: &str = "config.toml";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub data_path: PathBuf,
     pub watcher_paths: Vec<PathBuf>,
     pub ignore_paths: Vec<String>,
     pub comment_identifier: String,
     pub sleep_duration: Duration,
+    /// Whether every change to the notes is appended to `audit.json` in the data path
+    #[serde(default)]
+    pub audit_log: bool,
+    /// How due reminders are delivered besides the TUI: desktop, log or off
+    #[serde(default)]
+    pub notifier: NotifierKind,
+    /// The git remote the notes are synced through, if any
+    #[serde(default)]
+    pub sync: Option<SyncConfig>,
+    /// How long the app and file events are kept
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// How many snapshots of the data path are kept
+    #[serde(default)]
+    pub backup: BackupConfig,
 }
 
 impl Config {
-    pub fn new() -> Config {
-        let args = Args::parse();
-        let data_path = args.data_path.unwrap_or_else(|| {
-            let project_dir = ProjectDirs::from("", "Rarian", "rarian").unwrap();
-            project_dir.data_dir().to_path_buf()
-        });
-        create_dir_all(&data_path).expect("Creating the project directories in Roaming failed");
-        let config_path = data_path.join("config.toml");
-        match Self::read_config_from_file(config_path) {
-            Ok(config) => config,
-            Err(err) => {
-                println!("failed to load config file with {}", err);
-                let comment_identifier = vec!["@", "#", "$"].join("");
-                let sleep_duration = Duration::from_millis(16);
-                Config {
-                    data_path: data_path.to_path_buf(),
-                    watcher_paths: vec![],
-                    ignore_paths: vec![],
-                    comment_identifier,
-                    sleep_duration,
-                }
-            }
+    /// Reads the config in the data path, or the default one if there is none. A config that
+    /// can't be read is an error rather than replaced by the default.
+    pub fn new(data_path: Option<PathBuf>) -> anyhow::Result<Config> {
+        let data_path = match data_path {
+            Some(data_path) => data_path,
+            None => ProjectDirs::from("", "Rarian", "rarian")
+                .context("could not find the home directory")?
+                .data_dir()
+                .to_path_buf(),
+        };
+        create_dir_all(&data_path).with_context(|| {
+            format!(
+                "failed to create the data directory {}",
+                data_path.display()
+            )
+        })?;
+        let config_path = data_path.join(CONFIG_FILE);
+        match config_path.exists() {
+            true => Ok(Self::read_config_from_file(&config_path)?),
+            false => Ok(Self::default_config(&data_path)),
         }
     }
 
-    fn read_config_from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Config> {
-        let mut file = File::open(path)?;
-        let mut config_toml = String::new();
-        file.read_to_string(&mut config_toml)?;
-        let config = toml::from_str(&config_toml)?;
+    fn default_config(data_path: &Path) -> Config {
+        let comment_identifier = vec!["@", "#", "$"].join("");
+        let sleep_duration = Duration::from_millis(16);
+        Config {
+            data_path: data_path.to_path_buf(),
+            watcher_paths: vec![],
+            ignore_paths: vec![],
+            comment_identifier,
+            sleep_duration,
+            audit_log: false,
+            notifier: NotifierKind::default(),
+            sync: None,
+            retention: RetentionConfig::default(),
+            backup: BackupConfig::default(),
+        }
+    }
+
+    /// The config as it is now in the data path, for a running rarian to apply. The data path and
+    /// the audit log only change on a restart, so they are kept as they are.
+    pub fn reload(&self) -> Result<Config> {
+        let config_path = self.data_path.join(CONFIG_FILE);
+        let mut config = match config_path.exists() {
+            true => Self::read_config_from_file(&config_path)?,
+            false => Self::default_config(&self.data_path),
+        };
+        config.data_path = self.data_path.clone();
+        config.audit_log = self.audit_log;
         Ok(config)
     }
+
+    fn read_config_from_file(path: &Path) -> Result<Config> {
+        let mut config_toml = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut config_toml))
+            .map_err(|err| RarianError::ReadFile {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            })?;
+        toml::from_str(&config_toml).map_err(|err| {
+            let line = err
+                .span()
+                .map(|span| config_toml[..span.start].matches('\n').count() + 1);
+            RarianError::InvalidConfig {
+                path: path.to_path_buf(),
+                message: match line {
+                    Some(line) => format!("line {}: {}", line, err.message()),
+                    None => err.message().to_string(),
+                },
+            }
+        })
+    }
 }
@@ -0,0 +1,501 @@
+use crate::backup::remove_snapshots;
+use crate::error::{RarianError, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_to_string, remove_file, rename, write, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Holds how the key of an encrypted data path is derived from its passphrase
+pub const KEY_FILE: &str = "key.json";
+/// The caches in the data path that are encrypted
+pub const STORES: [&str; 7] = [
+    "notes.json",
+    "apps.json",
+    "files.json",
+    "ops.json",
+    "reminders.json",
+    "audit.json",
+    "daily.json",
+];
+const RECORD_PREFIX: &str = "enc:";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+/// Ends the files written while rekeying, before they replace the old ones
+const REKEY_SUFFIX: &str = "rekey";
+/// Ends the files written while encrypting plaintext records, before they replace the old ones
+const MIGRATE_SUFFIX: &str = "migrate";
+/// Encrypted with the key to tell a wrong passphrase from a right one
+const CHECK: &str = "rarian";
+
+/// The keys of the data paths unlocked by this process
+static KEYS: Mutex<Vec<(PathBuf, Key)>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    kdf: String,
+    #[serde(flatten)]
+    params: KdfParams,
+    salt: String,
+    check: String,
+}
+
+/// A key derived from a passphrase with Argon2id, that encrypts every record of a cache with
+/// XChaCha20-Poly1305 and the name of the cache as associated data, so records can't be moved
+/// between caches unnoticed
+#[derive(Clone)]
+pub struct Key {
+    cipher: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key")
+    }
+}
+
+impl Key {
+    pub fn derive(passphrase: &str, salt: &[u8], params: &KdfParams) -> Result<Key> {
+        let kdf_error = |err: argon2::Error| RarianError::Crypto(err.to_string());
+        let params = Params::new(
+            params.memory_kib,
+            params.iterations,
+            params.parallelism,
+            Some(32),
+        )
+        .map_err(kdf_error)?;
+        let mut bytes = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut bytes)
+            .map_err(kdf_error)?;
+        let cipher = XChaCha20Poly1305::new(&bytes.into());
+        bytes.fill(0);
+        Ok(Key { cipher })
+    }
+
+    /// Encrypts a record of the cache `store` as a line of text
+    pub fn encrypt(&self, store: &str, record: &str) -> Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: record.as_bytes(),
+            aad: store.as_bytes(),
+        };
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(&nonce, payload)
+                .map_err(|err| RarianError::Crypto(err.to_string()))?,
+        );
+        Ok(RECORD_PREFIX.to_string() + &BASE64.encode(sealed))
+    }
+
+    /// Decrypts a line of the cache `store`, `None` if it was changed or encrypted with another key
+    pub fn decrypt(&self, store: &str, line: &str) -> Option<String> {
+        let sealed = BASE64.decode(line.strip_prefix(RECORD_PREFIX)?).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: store.as_bytes(),
+        };
+        let record = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .ok()?;
+        String::from_utf8(record).ok()
+    }
+}
+
+pub fn is_encrypted_record(line: &str) -> bool {
+    line.starts_with(RECORD_PREFIX)
+}
+
+/// Whether the caches in `data_path` are encrypted
+pub fn is_encrypted(data_path: &Path) -> bool {
+    data_path.join(KEY_FILE).exists()
+}
+
+fn registry_path(data_path: &Path) -> PathBuf {
+    data_path
+        .canonicalize()
+        .unwrap_or_else(|_| data_path.to_path_buf())
+}
+
+fn unlocked_key(data_path: &Path) -> Option<Key> {
+    let path = registry_path(data_path);
+    KEYS.lock()
+        .unwrap()
+        .iter()
+        .find(|(unlocked, _)| *unlocked == path)
+        .map(|(_, key)| key.clone())
+}
+
+fn register(data_path: &Path, key: Key) {
+    let path = registry_path(data_path);
+    let mut keys = KEYS.lock().unwrap();
+    keys.retain(|(unlocked, _)| *unlocked != path);
+    keys.push((path, key));
+}
+
+/// Makes the caches in `copy` use the key of `data_path`, for a copy of its caches that is
+/// encrypted with the same key, like a snapshot
+pub(crate) fn share_key(data_path: &Path, copy: &Path) -> Result<()> {
+    let key = unlocked_key(data_path).ok_or(RarianError::Locked(data_path.to_path_buf()))?;
+    register(copy, key);
+    Ok(())
+}
+
+/// The key the cache at `store` is written with: `None` if its data path isn't encrypted, and an
+/// error if it is but wasn't unlocked, so nothing is ever written to it in plaintext
+pub(crate) fn key_for(store: &Path) -> Result<Option<Key>> {
+    let data_path = match store.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !is_encrypted(data_path) {
+        return Ok(None);
+    }
+    unlocked_key(data_path)
+        .map(Some)
+        .ok_or(RarianError::Locked(data_path.to_path_buf()))
+}
+
+fn read_error(path: &Path, err: impl ToString) -> RarianError {
+    RarianError::ReadFile {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    }
+}
+
+fn write_error(path: &Path, err: impl ToString) -> RarianError {
+    RarianError::WriteCache {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Finishes or undoes a rekey or a migration that was interrupted. The new key file waits until
+/// every new cache is written, so if it is still waiting the caches may be incomplete, and
+/// otherwise they are all there. Migrations leave the old caches until they are replaced.
+fn recover(data_path: &Path) -> Result<()> {
+    let pending_key = with_suffix(&data_path.join(KEY_FILE), REKEY_SUFFIX);
+    let abandoned = pending_key.exists();
+    for store in STORES {
+        let migrating = with_suffix(&data_path.join(store), MIGRATE_SUFFIX);
+        if migrating.exists() {
+            remove_file(&migrating).map_err(|err| write_error(&migrating, err))?;
+        }
+        let pending = with_suffix(&data_path.join(store), REKEY_SUFFIX);
+        if !pending.exists() {
+            continue;
+        }
+        match abandoned {
+            true => remove_file(&pending).map_err(|err| write_error(&pending, err))?,
+            false => {
+                rename(&pending, data_path.join(store)).map_err(|err| write_error(&pending, err))?
+            }
+        }
+    }
+    if abandoned {
+        remove_file(&pending_key).map_err(|err| write_error(&pending_key, err))?;
+    }
+    Ok(())
+}
+
+fn read_key_file(data_path: &Path) -> Result<KeyFile> {
+    let path = data_path.join(KEY_FILE);
+    let text = read_to_string(&path).map_err(|err| read_error(&path, err))?;
+    serde_json::from_str(&text).map_err(|err| read_error(&path, err))
+}
+
+/// Appends the records of `store` that can't be decrypted to the `quarantine` folder of the data
+/// path, as `rarian doctor --fix` does with the lines it can't read
+fn quarantine(data_path: &Path, store: &str, lines: &[&str]) -> Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let dir = data_path.join("quarantine");
+    let path = dir.join(store);
+    create_dir_all(&dir).map_err(|err| write_error(&dir, err))?;
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .map_err(|err| write_error(&path, err))?;
+    for line in lines {
+        writeln!(file, "{}", line).map_err(|err| write_error(&path, err))?;
+    }
+    Ok(())
+}
+
+/// Writes the caches next to themselves with every record encrypted by `new`, reading encrypted
+/// records with `old`. Migrations only rewrite the caches that have plaintext records. Returns the
+/// caches that were rewritten, without replacing them, and how many records couldn't be decrypted
+/// and were quarantined instead.
+fn rewrite_stores(
+    data_path: &Path,
+    old: Option<&Key>,
+    new: &Key,
+    suffix: &str,
+) -> Result<(Vec<PathBuf>, usize)> {
+    let every_store = suffix == REKEY_SUFFIX;
+    let mut rewritten = Vec::new();
+    let mut quarantined = 0;
+    for store in STORES {
+        let path = data_path.join(store);
+        let Ok(text) = read_to_string(&path) else {
+            continue;
+        };
+        if !every_store && text.lines().all(is_encrypted_record) {
+            continue;
+        }
+        let mut lines = Vec::new();
+        let mut undecryptable = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let record = match is_encrypted_record(line) {
+                true => match old.and_then(|old| old.decrypt(store, line)) {
+                    Some(record) => record,
+                    // Records that don't decrypt are kept out of the new cache
+                    None => {
+                        undecryptable.push(line);
+                        continue;
+                    }
+                },
+                false => line.to_string(),
+            };
+            lines.push(new.encrypt(store, &record)? + "\n");
+        }
+        quarantine(data_path, store, &undecryptable)?;
+        quarantined += undecryptable.len();
+        let pending = with_suffix(&path, suffix);
+        write(&pending, lines.concat()).map_err(|err| write_error(&pending, err))?;
+        rewritten.push(path);
+    }
+    Ok((rewritten, quarantined))
+}
+
+fn replace(rewritten: &[PathBuf], suffix: &str) -> Result<()> {
+    for path in rewritten {
+        rename(with_suffix(path, suffix), path).map_err(|err| write_error(path, err))?;
+    }
+    Ok(())
+}
+
+fn derive_key(data_path: &Path, passphrase: &str) -> Result<Key> {
+    let key_file = read_key_file(data_path)?;
+    let path = data_path.join(KEY_FILE);
+    let salt = BASE64
+        .decode(&key_file.salt)
+        .map_err(|err| read_error(&path, err))?;
+    let key = Key::derive(passphrase, &salt, &key_file.params)?;
+    if key.decrypt(KEY_FILE, &key_file.check).as_deref() != Some(CHECK) {
+        return Err(RarianError::WrongPassphrase);
+    }
+    Ok(key)
+}
+
+/// Derives the key of an encrypted data path from its passphrase, encrypts the caches written
+/// in plaintext before it was encrypted, and makes the caches of the data path use the key until
+/// the process exits. Returns how many records of the migrated caches couldn't be decrypted and
+/// were moved to the `quarantine` folder.
+pub fn unlock(data_path: &Path, passphrase: &str) -> Result<usize> {
+    recover(data_path)?;
+    let key = derive_key(data_path, passphrase)?;
+    let (migrated, quarantined) = rewrite_stores(data_path, Some(&key), &key, MIGRATE_SUFFIX)?;
+    replace(&migrated, MIGRATE_SUFFIX)?;
+    register(data_path, key);
+    Ok(quarantined)
+}
+
+/// Like `unlock`, but leaves the caches as they are, for reading a data path that another process
+/// writes to
+pub fn unlock_reading(data_path: &Path, passphrase: &str) -> Result<()> {
+    let key = derive_key(data_path, passphrase)?;
+    register(data_path, key);
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RekeyReport {
+    /// The records that couldn't be decrypted with the old key and were moved to the
+    /// `quarantine` folder
+    pub quarantined: usize,
+    /// The snapshots removed, since the old passphrase, or none, still opened them
+    pub snapshots_removed: usize,
+}
+
+/// Encrypts every cache of the data path with a new key derived from `passphrase`, and removes the
+/// snapshots of the data path. An encrypted data path must be unlocked first, and a plaintext one
+/// is encrypted from now on. Nothing may be writing to the caches meanwhile.
+pub fn rekey(data_path: &Path, passphrase: &str, params: &KdfParams) -> Result<RekeyReport> {
+    recover(data_path)?;
+    let old = match is_encrypted(data_path) {
+        true => Some(unlocked_key(data_path).ok_or(RarianError::Locked(data_path.to_path_buf()))?),
+        false => None,
+    };
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = Key::derive(passphrase, &salt, params)?;
+    let key_file = KeyFile {
+        kdf: "argon2id".to_string(),
+        params: params.clone(),
+        salt: BASE64.encode(salt),
+        check: key.encrypt(KEY_FILE, CHECK)?,
+    };
+    // The new caches are complete once the new key file replaces the old one, see `recover`
+    let path = data_path.join(KEY_FILE);
+    let pending = with_suffix(&path, REKEY_SUFFIX);
+    write(&pending, serde_json::to_string_pretty(&key_file).unwrap())
+        .map_err(|err| write_error(&pending, err))?;
+    let (rewritten, quarantined) = rewrite_stores(data_path, old.as_ref(), &key, REKEY_SUFFIX)?;
+    rename(&pending, &path).map_err(|err| write_error(&path, err))?;
+    replace(&rewritten, REKEY_SUFFIX)?;
+    register(data_path, key);
+    Ok(RekeyReport {
+        quarantined,
+        snapshots_removed: remove_snapshots(data_path)?,
+    })
+}
+
+#[cfg(test)]
+mod crypto_test {
+    use super::*;
+    use crate::notes::NoteTaker;
+    use std::fs::{copy, remove_dir_all};
+
+    const CHEAP: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    /// Keys are unlocked for the whole process, so a copy of the data path starts locked
+    fn locked_copy(data_path: &Path, name: &str) -> PathBuf {
+        let copied = data_path.with_file_name(name);
+        create_dir_all(&copied).unwrap();
+        for file in STORES.iter().chain([&KEY_FILE]) {
+            if data_path.join(file).exists() {
+                copy(data_path.join(file), copied.join(file)).unwrap();
+            }
+        }
+        copied
+    }
+
+    #[test]
+    fn stores_are_encrypted_migrated_and_rekeyed() {
+        let root = PathBuf::from("./testData/crypto");
+        let _ = remove_dir_all(&root);
+        let data_path = root.join("plain");
+        create_dir_all(&data_path).unwrap();
+        let mut note_taker = NoteTaker::new(&data_path).unwrap();
+        let id = note_taker
+            .add_note("my bank pin", vec!["Firefox".to_string()])
+            .unwrap();
+        drop(note_taker);
+
+        rekey(&data_path, "first", &CHEAP).unwrap();
+        NoteTaker::new(&data_path)
+            .unwrap()
+            .add_note("written encrypted", vec![])
+            .unwrap();
+        let notes = read_to_string(data_path.join("notes.json")).unwrap();
+        assert!(notes.lines().all(is_encrypted_record));
+        assert!(!notes.contains("pin") && !notes.contains("Firefox"));
+
+        let ops = read_to_string(data_path.join("ops.json")).unwrap();
+        assert!(ops.lines().all(is_encrypted_record));
+
+        // A plaintext record from before the encryption is encrypted when unlocking
+        let copied = locked_copy(&data_path, "copied");
+        let mut extra = crate::notes::Note::new("from an old version", vec![]);
+        extra.id = ulid::Ulid::new();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(copied.join("notes.json"))
+            .unwrap();
+        writeln!(file, "{}", serde_json::to_string(&extra).unwrap()).unwrap();
+        let locked = NoteTaker::new(&copied).err();
+        let wrong = unlock(&copied, "second").err();
+        let unlocked_quarantine = unlock(&copied, "first").unwrap();
+        let migrated = read_to_string(copied.join("notes.json")).unwrap();
+        let unlocked = NoteTaker::new(&copied).unwrap();
+        let texts: Vec<String> = [id, extra.id]
+            .iter()
+            .filter_map(|id| unlocked.get_note(id).map(|note| note.text.clone()))
+            .collect();
+
+        // Rekeying keeps every record and only the new passphrase unlocks it, quarantining the
+        // records it can't decrypt
+        let damaged = format!("{}{}", RECORD_PREFIX, BASE64.encode([0u8; 64]));
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(copied.join("notes.json"))
+            .unwrap();
+        writeln!(file, "{}", damaged).unwrap();
+        crate::backup::create(&copied, crate::backup::SnapshotReason::Manual).unwrap();
+        let report = rekey(&copied, "second", &CHEAP).unwrap();
+        let snapshots_left = crate::backup::snapshots(&copied).unwrap().len();
+        let quarantine = read_to_string(copied.join("quarantine").join("notes.json")).unwrap();
+        let rekeyed = locked_copy(&copied, "rekeyed");
+        let old_passphrase = unlock(&rekeyed, "first").err();
+        unlock(&rekeyed, "second").unwrap();
+        let count = NoteTaker::new(&rekeyed).unwrap().notes().count();
+        remove_dir_all(&root).unwrap();
+
+        assert!(matches!(locked, Some(RarianError::Locked(_))));
+        assert!(matches!(wrong, Some(RarianError::WrongPassphrase)));
+        assert_eq!(unlocked_quarantine, 0);
+        assert!(migrated.lines().all(is_encrypted_record));
+        assert_eq!(texts, vec!["my bank pin", "from an old version"]);
+        assert!(matches!(old_passphrase, Some(RarianError::WrongPassphrase)));
+        assert_eq!(count, 3);
+        assert_eq!(report.quarantined, 1);
+        assert_eq!(report.snapshots_removed, 1);
+        assert_eq!(snapshots_left, 0);
+        assert_eq!(quarantine, damaged + "\n");
+    }
+
+    #[test]
+    fn records_only_decrypt_in_their_own_store() {
+        let key = Key::derive("passphrase", &[7; SALT_LEN], &CHEAP).unwrap();
+        let line = key.encrypt("notes.json", "{}").unwrap();
+        assert_eq!(key.decrypt("notes.json", &line).as_deref(), Some("{}"));
+        assert_eq!(key.decrypt("apps.json", &line), None);
+        let mut tampered = line.clone();
+        tampered.pop();
+        tampered.push(if line.ends_with('A') { 'B' } else { 'A' });
+        assert_eq!(key.decrypt("notes.json", &tampered), None);
+    }
+}
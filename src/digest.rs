@@ -0,0 +1,406 @@
+use crate::error::Result;
+use crate::retention::daily_totals;
+use crate::timeline::{format_duration, NoteAction, Timeline, TimelineKind};
+use chrono::{NaiveDate, Weekday};
+use itertools::Itertools;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+const DEFAULT_TEMPLATE: &str = include_str!("setup/digest.md");
+static TASK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(TODO|FIXME|XXX)\b").unwrap());
+static PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{(\w+)\}\}").unwrap());
+
+type FocusSpan = (String, Duration, HashSet<String>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DigestPeriod {
+    Day,
+    Week,
+}
+
+impl Display for DigestPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DigestPeriod::Day => write!(f, "day"),
+            DigestPeriod::Week => write!(f, "week"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CodeTask {
+    pub path: PathBuf,
+    pub line_number: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Digest {
+    pub period: DigestPeriod,
+    pub first_day: NaiveDate,
+    pub last_day: NaiveDate,
+    pub apps: Vec<(String, Duration)>,
+    pub projects: Vec<(String, Duration)>,
+    pub files: Vec<(PathBuf, usize)>,
+    pub notes_created: Vec<String>,
+    pub notes_edited: Vec<String>,
+    pub notes_archived: Vec<String>,
+    pub tasks: Vec<CodeTask>,
+}
+
+impl Digest {
+    pub fn load(data_path: &Path, period: DigestPeriod, date: NaiveDate) -> Result<Digest> {
+        let (first_day, last_day) = match period {
+            DigestPeriod::Day => (date, date),
+            DigestPeriod::Week => {
+                let week = date.week(Weekday::Mon);
+                (week.first_day(), week.last_day())
+            }
+        };
+        let dates: Vec<NaiveDate> = first_day
+            .iter_days()
+            .take_while(|day| *day <= last_day)
+            .collect();
+        let timelines = Timeline::load_days(data_path, &dates)?;
+        let mut digest = Digest::from_timelines(period, first_day, last_day, &timelines);
+        // The daily totals hold only the time of the app events that were removed, so they add to
+        // what is left of a day
+        let totals = daily_totals(data_path, &dates)?;
+        if !totals.is_empty() {
+            let mut apps: HashMap<String, Duration> = digest.apps.into_iter().collect();
+            for total in totals {
+                *apps.entry(total.app_title).or_default() += total.duration;
+            }
+            digest.apps = sorted_by_value(apps);
+        }
+        digest.tasks = find_code_tasks(digest.files.iter().map(|(path, _)| path.as_path()));
+        Ok(digest)
+    }
+
+    pub fn from_timelines(
+        period: DigestPeriod,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+        timelines: &[Timeline],
+    ) -> Digest {
+        let mut apps: HashMap<String, Duration> = HashMap::new();
+        let mut projects: HashMap<String, Duration> = HashMap::new();
+        let mut files: HashMap<PathBuf, usize> = HashMap::new();
+        let mut notes_created = Vec::new();
+        let mut notes_edited = Vec::new();
+        let mut notes_archived = Vec::new();
+
+        for timeline in timelines {
+            // The focus span the following entries belong to, with the projects touched in it
+            let mut span: Option<FocusSpan> = None;
+            for entry in timeline.entries.iter() {
+                match &entry.kind {
+                    TimelineKind::AppFocus { duration } => {
+                        add_span_to_projects(&mut projects, span.take());
+                        let title = entry.app_title.clone().unwrap_or_default();
+                        *apps.entry(title.clone()).or_default() += *duration;
+                        span = Some((title, *duration, HashSet::new()));
+                    }
+                    TimelineKind::FileChange { path, .. } => {
+                        *files.entry(path.clone()).or_default() += 1;
+                        if let (Some((title, _, span_projects)), Some(project)) =
+                            (span.as_mut(), project_of(path))
+                        {
+                            if entry.app_title.as_ref() == Some(title) {
+                                span_projects.insert(project);
+                            }
+                        }
+                    }
+                    TimelineKind::Note { action, text, .. } => {
+                        let text = text.lines().join(" ");
+                        match action {
+                            NoteAction::Created => notes_created.push(text),
+                            NoteAction::Edited => notes_edited.push(text),
+                            NoteAction::Archived => notes_archived.push(text),
+                        }
+                    }
+                }
+            }
+            add_span_to_projects(&mut projects, span);
+        }
+
+        Digest {
+            period,
+            first_day,
+            last_day,
+            apps: sorted_by_value(apps),
+            projects: sorted_by_value(projects),
+            files: sorted_by_value(files),
+            notes_created,
+            notes_edited,
+            notes_archived,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self.period {
+            DigestPeriod::Day => format!("Daily digest {}", self.first_day.format("%A %Y-%m-%d")),
+            DigestPeriod::Week => format!(
+                "Weekly digest {} - {}",
+                self.first_day.format("%Y-%m-%d"),
+                self.last_day.format("%Y-%m-%d")
+            ),
+        }
+    }
+
+    /// Replaces the `{{placeholder}}`s in `template` with the Markdown of each digest section, in
+    /// one pass so placeholders inside the sections themselves are left as they are
+    pub fn render(&self, template: &str) -> String {
+        let sections =
+            [
+                ("title", self.title()),
+                (
+                    "apps",
+                    markdown_list(
+                        self.apps.iter().map(|(app, duration)| {
+                            format!("{} ({})", app, format_duration(duration))
+                        }),
+                    ),
+                ),
+                (
+                    "projects",
+                    markdown_list(self.projects.iter().map(|(project, duration)| {
+                        format!("{} ({})", project, format_duration(duration))
+                    })),
+                ),
+                (
+                    "files",
+                    markdown_list(self.files.iter().map(|(path, changes)| {
+                        format!("`{}` ({} changes)", path.display(), changes)
+                    })),
+                ),
+                ("notes_created", markdown_list(self.notes_created.iter())),
+                ("notes_edited", markdown_list(self.notes_edited.iter())),
+                ("notes_archived", markdown_list(self.notes_archived.iter())),
+                (
+                    "tasks",
+                    markdown_list(self.tasks.iter().map(|task| {
+                        format!(
+                            "`{}:{}` {}",
+                            task.path.display(),
+                            task.line_number,
+                            task.text
+                        )
+                    })),
+                ),
+            ];
+        PLACEHOLDER_REGEX
+            .replace_all(template, |captures: &Captures| {
+                sections
+                    .iter()
+                    .find(|(placeholder, _)| *placeholder == &captures[1])
+                    .map_or_else(|| captures[0].to_string(), |(_, section)| section.clone())
+            })
+            .into_owned()
+    }
+}
+
+/// Loads the template for `period` from the `templates` folder in the data path, either
+/// `digest_day.md`/`digest_week.md` or `digest.md`, falling back to the built in template.
+pub fn load_template(data_path: &Path, period: DigestPeriod) -> String {
+    let templates_path = data_path.join("templates");
+    [format!("digest_{}.md", period), "digest.md".to_string()]
+        .iter()
+        .find_map(|file_name| read_to_string(templates_path.join(file_name)).ok())
+        .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string())
+}
+
+/// The name of the git repository a path is in, if any
+pub fn project_of(path: &Path) -> Option<String> {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+        .and_then(|dir| dir.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+fn find_code_tasks<'a>(paths: impl Iterator<Item = &'a Path>) -> Vec<CodeTask> {
+    paths
+        .filter(|path| path.is_file())
+        .filter_map(|path| read_to_string(path).ok().map(|text| (path, text)))
+        .flat_map(|(path, text)| {
+            text.lines()
+                .enumerate()
+                .filter(|(_, line)| TASK_REGEX.is_match(line))
+                .map(|(index, line)| CodeTask {
+                    path: path.to_path_buf(),
+                    line_number: index + 1,
+                    text: line.trim().to_string(),
+                })
+                .collect_vec()
+        })
+        .collect()
+}
+
+fn add_span_to_projects(projects: &mut HashMap<String, Duration>, span: Option<FocusSpan>) {
+    if let Some((_, duration, span_projects)) = span {
+        for project in span_projects {
+            *projects.entry(project).or_default() += duration;
+        }
+    }
+}
+
+fn sorted_by_value<K: Ord, V: Ord + Copy>(map: HashMap<K, V>) -> Vec<(K, V)> {
+    map.into_iter()
+        .sorted_by(|(a_key, a_value), (b_key, b_value)| b_value.cmp(a_value).then(a_key.cmp(b_key)))
+        .collect()
+}
+
+fn markdown_list<T: Display>(items: impl Iterator<Item = T>) -> String {
+    let list = items.map(|item| format!("- {}", item)).join("\n");
+    if list.is_empty() {
+        "_none_".to_string()
+    } else {
+        list
+    }
+}
+
+#[cfg(test)]
+mod digest_test {
+    use super::*;
+    use crate::timeline::TimelineEntry;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::time::SystemTime;
+    use ulid::Ulid;
+
+    fn entry(kind: TimelineKind, app_title: &str) -> TimelineEntry {
+        TimelineEntry {
+            time: SystemTime::now(),
+            kind,
+            app_title: Some(app_title.to_string()),
+        }
+    }
+
+    #[test]
+    fn digest_sums_apps_and_projects_and_renders_sections() {
+        let project_path = PathBuf::from("./testData/digest_project");
+        create_dir_all(project_path.join(".git")).unwrap();
+        let file_path = project_path.join("lib.rs");
+        let date = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let focus = |secs| TimelineKind::AppFocus {
+            duration: Duration::from_secs(secs),
+        };
+        let timeline = Timeline {
+            date,
+            entries: vec![
+                entry(focus(60), "editor"),
+                entry(
+                    TimelineKind::FileChange {
+                        path: file_path.clone(),
+                        change: "modified".to_string(),
+                    },
+                    "editor",
+                ),
+                entry(focus(30), "browser"),
+                entry(
+                    TimelineKind::Note {
+                        id: Ulid::new(),
+                        action: NoteAction::Created,
+                        text: "check\nthe docs".to_string(),
+                    },
+                    "browser",
+                ),
+                entry(focus(120), "editor"),
+            ],
+        };
+
+        let digest = Digest::from_timelines(DigestPeriod::Day, date, date, &[timeline]);
+        remove_dir_all(&project_path).unwrap();
+        assert_eq!(
+            digest.apps,
+            vec![
+                ("editor".to_string(), Duration::from_secs(180)),
+                ("browser".to_string(), Duration::from_secs(30)),
+            ]
+        );
+        assert_eq!(
+            digest.projects,
+            vec![("digest_project".to_string(), Duration::from_secs(60))]
+        );
+        let markdown = digest.render("# {{title}}\n{{notes_created}}\n{{tasks}}");
+        assert_eq!(
+            markdown,
+            "# Daily digest Monday 2024-10-14\n- check the docs\n_none_"
+        );
+    }
+
+    #[test]
+    fn placeholders_in_the_notes_are_not_rendered() {
+        let date = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let timeline = Timeline {
+            date,
+            entries: vec![entry(
+                TimelineKind::Note {
+                    id: Ulid::new(),
+                    action: NoteAction::Created,
+                    text: "{{tasks}}".to_string(),
+                },
+                "editor",
+            )],
+        };
+
+        let digest = Digest::from_timelines(DigestPeriod::Day, date, date, &[timeline]);
+        let markdown = digest.render("{{notes_created}}\n{{tasks}}\n{{unknown}}");
+        assert_eq!(markdown, "- {{tasks}}\n_none_\n{{unknown}}");
+    }
+
+    #[test]
+    fn the_time_removed_from_a_day_still_counts_next_to_what_is_left() {
+        use crate::cacher::{Cache, FileCacher};
+        use crate::gatherer::app_gatherer::ActiveProcessEvent;
+        use crate::retention::{enforce, RetentionConfig};
+        use chrono::{Local, TimeZone};
+
+        let data_path = PathBuf::from("./testData/digest_totals");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let noon: SystemTime = Local
+            .from_local_datetime(&date.and_hms_opt(12, 0, 0).unwrap())
+            .unwrap()
+            .into();
+        let mut apps = FileCacher::new(data_path.join("apps.json")).unwrap();
+        for (start, secs) in [(noon, 60), (noon + Duration::from_secs(7200), 30)] {
+            let app: ActiveProcessEvent = serde_json::from_value(serde_json::json!({
+                "process": {
+                    "title": "editor",
+                    "app_name": "editor",
+                    "window_id": "1",
+                    "process_path": "/bin/editor",
+                    "process_id": 1,
+                },
+                "active_start_time": start,
+                "active_duration": Duration::from_secs(secs),
+            }))
+            .unwrap();
+            apps.cache(&app).unwrap();
+        }
+        // The cutoff falls between the two events of the day
+        let config = RetentionConfig {
+            app_events_days: Some(1),
+            file_events_days: None,
+            keep_daily_totals: true,
+        };
+        let now = noon + Duration::from_secs(3600 + 24 * 60 * 60);
+        let report = enforce(&data_path, &config, now).unwrap();
+        let digest = Digest::load(&data_path, DigestPeriod::Day, date).unwrap();
+        remove_dir_all(&data_path).unwrap();
+
+        assert_eq!(report.app_events, 1);
+        assert_eq!(
+            digest.apps,
+            vec![("editor".to_string(), Duration::from_secs(90))]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use thiserror::Error;
+use ulid::Ulid;
+
+/// Errors are cloned to every subscriber, so they keep the message of their source rather than the
+/// source itself
+#[derive(Debug, Clone, Error)]
+pub enum RarianError {
+    #[error("failed to open {path}: {message}")]
+    OpenCache { path: PathBuf, message: String },
+    #[error("failed to write to {path}: {message}")]
+    WriteCache { path: PathBuf, message: String },
+    #[error("could not find note with id {0}")]
+    NoteNotFound(Ulid),
+    #[error("failed to watch {path}: {message}")]
+    Watch { path: PathBuf, message: String },
+    #[error("failed to read {path}: {message}")]
+    ReadFile { path: PathBuf, message: String },
+    #[error("failed to remove notes from {path}: {message}")]
+    WriteFile { path: PathBuf, message: String },
+    #[error("invalid config {path}: {message}")]
+    InvalidConfig { path: PathBuf, message: String },
+    #[error("failed to export to {path}: {message}")]
+    Export { path: PathBuf, message: String },
+    #[error("{0} is encrypted, unlock it with its passphrase first")]
+    Locked(PathBuf),
+    #[error("{path} is in use by another rarian (process {holder}), close it first")]
+    InUse { path: PathBuf, holder: String },
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("encryption failed: {0}")]
+    Crypto(String),
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+    #[error("backup failed: {0}")]
+    Backup(String),
+    #[error("sync failed: {0}")]
+    Sync(String),
+    #[error("external editor failed: {0}")]
+    Editor(String),
+    #[error("{name} crashed: {message}")]
+    ThreadCrashed { name: String, message: String },
+    #[error("failed to deliver a reminder: {0}")]
+    Notify(String),
+    #[error("the service thread has stopped")]
+    Disconnected,
+    #[error("{0} request timed out")]
+    Timeout(&'static str),
+    #[error(
+        "{count} lines of {path} can't be read and were left out, run rarian doctor to see why"
+    )]
+    UnreadableRecords { path: PathBuf, count: usize },
+}
+
+pub type Result<T> = std::result::Result<T, RarianError>;
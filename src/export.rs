@@ -0,0 +1,448 @@
+use crate::digest::project_of;
+use crate::error::{RarianError, Result};
+use crate::graph::is_file_link;
+use crate::notes::{Note, NoteStatus, NoteTaker, REFERENCE_REGEX};
+use chrono::{DateTime, SecondsFormat, Utc};
+use itertools::Itertools;
+use regex::Captures;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{create_dir_all, read_to_string, remove_file, write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use ulid::Ulid;
+
+/// The pages written by the last export, so pages that are gone can be removed without touching
+/// anything else in the vault
+const MANIFEST: &str = ".rarian-export.json";
+/// Page names are file names, so they are kept well below the limits of file systems
+const MAX_PAGE_NAME: usize = 100;
+
+/// The name of a page, without what file systems or `[[links]]` don't allow
+fn page_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter_map(|c| match c {
+            '[' | ']' => None,
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' => Some('-'),
+            c if c.is_control() => Some(' '),
+            c => Some(c),
+        })
+        .take(MAX_PAGE_NAME)
+        .collect();
+    // Leading dots hide files, trailing ones and spaces are dropped on Windows
+    name.trim_matches(|c: char| c == '.' || c.is_whitespace() || c == '-')
+        .to_string()
+}
+
+/// A double quoted YAML string, which JSON strings are
+fn yaml_string(text: &str) -> String {
+    serde_json::to_string(text).unwrap()
+}
+
+fn yaml_list(key: &str, items: &[String]) -> String {
+    match items.is_empty() {
+        true => format!("{}: []\n", key),
+        false => {
+            let items: String = items
+                .iter()
+                .map(|item| format!("  - {}\n", yaml_string(item)))
+                .collect();
+            format!("{}:\n{}", key, items)
+        }
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// What an index page lists the notes of
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Context {
+    App(String),
+    File(String),
+    Project(String),
+}
+
+impl Context {
+    fn folder(&self) -> &'static str {
+        match self {
+            Context::App(_) => "apps",
+            Context::File(_) => "files",
+            Context::Project(_) => "projects",
+        }
+    }
+
+    fn page_name(&self) -> String {
+        match self {
+            Context::App(title) => page_name(&format!("app - {}", title)),
+            Context::File(path) => page_name(&format!("file - {}", path.replace(['/', '\\'], " "))),
+            Context::Project(name) => page_name(&format!("project - {}", name)),
+        }
+    }
+}
+
+/// Gives every page a name no other page has, ignoring case as some file systems do
+#[derive(Default)]
+struct PageNames {
+    taken: HashSet<String>,
+}
+
+impl PageNames {
+    /// Claims `name`, or if it is taken `name` followed by the `suffix` of the attempt, cutting
+    /// `name` short so the suffix always fits
+    fn claim(&mut self, name: String, suffix: impl Fn(usize) -> String) -> String {
+        let base = name.clone();
+        let mut name = name;
+        let mut attempt = 1;
+        while name.is_empty() || !self.taken.insert(name.to_lowercase()) {
+            attempt += 1;
+            let suffix = page_name(&suffix(attempt));
+            let base: String = base
+                .chars()
+                .take(MAX_PAGE_NAME.saturating_sub(suffix.chars().count() + 1))
+                .collect();
+            name = page_name(&format!("{} {}", base, suffix));
+        }
+        name
+    }
+}
+
+/// The notes as Markdown pages of an Obsidian or Logseq vault: one page per note, named after its
+/// title, and an index page for every app, file and project listing the notes linked to it
+#[derive(Debug, Clone, Default)]
+pub struct Vault {
+    /// The text of every page, by its path in the vault
+    pub pages: BTreeMap<PathBuf, String>,
+}
+
+impl Vault {
+    pub fn from_notes(note_taker: &NoteTaker) -> Vault {
+        // The oldest note keeps the plain name when titles clash, so names don't change later
+        let notes: Vec<&Note> = note_taker
+            .notes()
+            .sorted_by_key(|note| (note.creation_date, note.id))
+            .collect();
+        let mut names = PageNames::default();
+        let note_names: HashMap<_, _> = notes
+            .iter()
+            .map(|note| {
+                let name = names.claim(page_name(note.title()), |attempt| match attempt {
+                    2 => note.id.to_string(),
+                    _ => format!("{} {}", note.id, attempt),
+                });
+                (note.id, name)
+            })
+            .collect();
+
+        let mut contexts: BTreeMap<Context, Vec<&Note>> = BTreeMap::new();
+        for note in notes.iter() {
+            for link in note.links() {
+                if !is_file_link(&link.link) {
+                    contexts
+                        .entry(Context::App(link.link.clone()))
+                        .or_default()
+                        .push(note);
+                    continue;
+                }
+                contexts
+                    .entry(Context::File(link.link.clone()))
+                    .or_default()
+                    .push(note);
+                if let Some(project) = project_of(Path::new(&link.link)) {
+                    contexts
+                        .entry(Context::Project(project))
+                        .or_default()
+                        .push(note);
+                }
+            }
+        }
+        let context_names: BTreeMap<&Context, String> = contexts
+            .keys()
+            .map(|context| {
+                let name = context.page_name();
+                let claimed = names.claim(name, |attempt| attempt.to_string());
+                (context, claimed)
+            })
+            .collect();
+
+        let mut pages = BTreeMap::new();
+        for note in notes.iter() {
+            let name = &note_names[&note.id];
+            let page = note_page(note, name, note_taker, &note_names);
+            pages.insert(Path::new("notes").join(format!("{}.md", name)), page);
+        }
+        for (context, notes) in contexts.iter() {
+            let name = &context_names[context];
+            let files: Vec<&String> = match context {
+                Context::Project(project) => context_names
+                    .iter()
+                    .filter(|(other, _)| match other {
+                        Context::File(path) => {
+                            project_of(Path::new(path)).as_ref() == Some(project)
+                        }
+                        _ => false,
+                    })
+                    .map(|(_, name)| name)
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let page = index_page(context, notes, &files, &note_names);
+            pages.insert(
+                Path::new(context.folder()).join(format!("{}.md", name)),
+                page,
+            );
+        }
+        Vault { pages }
+    }
+
+    /// Writes the pages to `dir`, skipping the ones that didn't change since the last export and
+    /// removing the ones it wrote that are gone
+    pub fn write(&self, dir: &Path) -> Result<ExportSummary> {
+        let export_error = |path: &Path, err: std::io::Error| RarianError::Export {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        };
+        create_dir_all(dir).map_err(|err| export_error(dir, err))?;
+        let mut summary = ExportSummary::default();
+        for (page, text) in self.pages.iter() {
+            let path = dir.join(page);
+            if read_to_string(&path).is_ok_and(|existing| existing == *text) {
+                summary.unchanged += 1;
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).map_err(|err| export_error(parent, err))?;
+            }
+            write(&path, text).map_err(|err| export_error(&path, err))?;
+            summary.written.push(page.clone());
+        }
+
+        let manifest_path = dir.join(MANIFEST);
+        let exported: BTreeSet<PathBuf> = read_to_string(&manifest_path)
+            .ok()
+            .and_then(|manifest| serde_json::from_str(&manifest).ok())
+            .unwrap_or_default();
+        for page in exported.iter() {
+            // The manifest can be edited or synced, so nothing outside the vault is removed
+            if self.pages.contains_key(page) || !is_inside(page) {
+                continue;
+            }
+            let path = dir.join(page);
+            match remove_file(&path) {
+                Ok(()) => summary.removed.push(page.clone()),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(export_error(&path, err)),
+            }
+        }
+        let pages: Vec<&PathBuf> = self.pages.keys().collect();
+        let manifest = serde_json::to_string_pretty(&pages).unwrap();
+        write(&manifest_path, manifest).map_err(|err| export_error(&manifest_path, err))?;
+        Ok(summary)
+    }
+}
+
+/// Whether `page` is a relative path that stays in the folder it is joined to
+fn is_inside(page: &Path) -> bool {
+    page.components().next().is_some()
+        && page
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// The pages an export wrote, left as they were, and removed
+#[derive(Debug, Clone, Default)]
+pub struct ExportSummary {
+    pub written: Vec<PathBuf>,
+    pub unchanged: usize,
+    pub removed: Vec<PathBuf>,
+}
+
+/// The front-matter and the text of a note, with its `[[references]]` pointing to the pages of
+/// the notes they resolve to
+fn note_page(
+    note: &Note,
+    name: &str,
+    note_taker: &NoteTaker,
+    note_names: &HashMap<Ulid, String>,
+) -> String {
+    let mut page = String::from("---\n");
+    page.push_str(&format!("id: {}\n", note.id));
+    page.push_str(&format!("created: {}\n", format_time(note.creation_date)));
+    if let Some(updated) = note.updated_date {
+        page.push_str(&format!("updated: {}\n", format_time(updated)));
+    }
+    let status = match note.status {
+        NoteStatus::Active => "active",
+        NoteStatus::Archived => "archived",
+    };
+    page.push_str(&format!("status: {}\n", status));
+    let links: Vec<String> = note.links().iter().map(|link| link.link.clone()).collect();
+    page.push_str(&yaml_list("links", &links));
+    page.push_str(&yaml_list("tags", &note.tags()));
+    if name != note.title() && !note.title().is_empty() {
+        page.push_str(&yaml_list("aliases", &[note.title().to_string()]));
+    }
+    if let Some(task) = &note.task {
+        page.push_str(&format!("done: {}\n", task.done));
+        if let Some(due) = task.due {
+            page.push_str(&format!("due: {}\n", due));
+        }
+        if let Some(priority) = task.priority {
+            page.push_str(&format!("priority: {}\n", priority));
+        }
+    }
+    page.push_str("---\n");
+
+    let text = REFERENCE_REGEX.replace_all(&note.text, |captures: &Captures| {
+        note_taker
+            .resolve(captures[1].trim())
+            .and_then(|target| note_names.get(&target.id))
+            .map(|name| format!("[[{}]]", name))
+            .unwrap_or_else(|| captures[0].to_string())
+    });
+    page.push_str(&text);
+    if !page.ends_with('\n') {
+        page.push('\n');
+    }
+    page
+}
+
+/// A page listing the notes linked to an app, file or project, and the files of a project
+fn index_page(
+    context: &Context,
+    notes: &[&Note],
+    files: &[&String],
+    note_names: &HashMap<Ulid, String>,
+) -> String {
+    let (kind, link) = match context {
+        Context::App(link) => ("app", link),
+        Context::File(link) => ("file", link),
+        Context::Project(link) => ("project", link),
+    };
+    let mut page = format!(
+        "---\nrarian: {}\nlink: {}\n---\n# {}\n\n",
+        kind,
+        yaml_string(link),
+        link
+    );
+    if !files.is_empty() {
+        page.push_str("## Files\n\n");
+        for file in files {
+            page.push_str(&format!("- [[{}]]\n", file));
+        }
+        page.push_str("\n## Notes\n\n");
+    }
+    for note in notes.iter().unique_by(|note| note.id) {
+        let archived = match note.status {
+            NoteStatus::Archived => " (archived)",
+            NoteStatus::Active => "",
+        };
+        page.push_str(&format!("- [[{}]]{}\n", note_names[&note.id], archived));
+    }
+    page
+}
+
+#[cfg(test)]
+mod export_test {
+    use super::*;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn notes_are_exported_as_pages_and_rewritten_only_when_changed() {
+        let data_path = PathBuf::from("./testData/export");
+        let vault_path = data_path.join("vault");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let mut note_taker = NoteTaker::new(&data_path).unwrap();
+        let design = note_taker
+            .add_note("Parser design\n#rust", vec!["Code".to_string()])
+            .unwrap();
+        let review = note_taker
+            .add_note("Review: see [[parser design]]", vec!["Code".to_string()])
+            .unwrap();
+        let clash = note_taker
+            .add_note("Parser design", vec!["Firefox".to_string()])
+            .unwrap();
+
+        let vault = Vault::from_notes(&note_taker);
+        let design_page = &vault.pages[Path::new("notes/Parser design.md")];
+        assert!(design_page.starts_with(&format!("---\nid: {}\n", design)));
+        assert!(design_page.contains("links:\n  - \"Code\"\ntags:\n  - \"rust\"\n"));
+        // References resolve to the newest note with a title, which has to be told apart
+        let clash_name = format!("Parser design {}", clash);
+        let review_page = &vault.pages[Path::new("notes/Review- see parser design.md")];
+        assert!(review_page.ends_with(&format!("---\nReview: see [[{}]]\n", clash_name)));
+        let clash_page = &vault.pages[&Path::new("notes").join(format!("{}.md", clash_name))];
+        assert!(clash_page.contains("aliases:\n  - \"Parser design\"\n"));
+        let code_page = &vault.pages[Path::new("apps/app - Code.md")];
+        assert!(
+            code_page.ends_with("# Code\n\n- [[Parser design]]\n- [[Review- see parser design]]\n")
+        );
+
+        let first = vault.write(&vault_path).unwrap();
+        assert_eq!(first.written.len(), 5);
+        note_taker.edit_note(&review, "Review done").unwrap();
+        let second = Vault::from_notes(&note_taker).write(&vault_path).unwrap();
+        remove_dir_all(&data_path).unwrap();
+        assert_eq!(
+            second.written,
+            vec![
+                PathBuf::from("apps/app - Code.md"),
+                PathBuf::from("notes/Review done.md")
+            ]
+        );
+        assert_eq!(second.unchanged, 3);
+        assert_eq!(
+            second.removed,
+            vec![PathBuf::from("notes/Review- see parser design.md")]
+        );
+    }
+
+    #[test]
+    fn long_names_that_clash_are_cut_short_to_fit_their_suffix() {
+        let data_path = PathBuf::from("./testData/export_long_names");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let mut note_taker = NoteTaker::new(&data_path).unwrap();
+        let title = "a".repeat(120);
+        let apps = ["b".repeat(120) + " one", "b".repeat(120) + " two"];
+        for _ in 0..3 {
+            note_taker.add_note(&title, apps.to_vec()).unwrap();
+        }
+
+        let vault = Vault::from_notes(&note_taker);
+        remove_dir_all(&data_path).unwrap();
+        let names: HashSet<String> = vault
+            .pages
+            .keys()
+            .map(|page| page.file_stem().unwrap().to_string_lossy().to_lowercase())
+            .collect();
+        assert_eq!(vault.pages.len(), 5);
+        assert_eq!(names.len(), 5);
+        assert!(names
+            .iter()
+            .all(|name| name.chars().count() <= MAX_PAGE_NAME));
+    }
+
+    #[test]
+    fn pages_outside_the_vault_in_the_manifest_are_not_removed() {
+        let data_path = PathBuf::from("./testData/export_manifest");
+        let vault_path = data_path.join("vault");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&vault_path).unwrap();
+        let outside = data_path.join("outside.md");
+        write(&outside, "keep").unwrap();
+        let absolute = outside.canonicalize().unwrap();
+        let manifest = serde_json::json!(["../outside.md", absolute, "notes/gone.md"]);
+        write(vault_path.join(MANIFEST), manifest.to_string()).unwrap();
+        create_dir_all(vault_path.join("notes")).unwrap();
+        write(vault_path.join("notes/gone.md"), "gone").unwrap();
+
+        let summary = Vault::default().write(&vault_path).unwrap();
+        let kept = outside.exists();
+        remove_dir_all(&data_path).unwrap();
+        assert!(kept);
+        assert_eq!(summary.removed, vec![PathBuf::from("notes/gone.md")]);
+    }
+}
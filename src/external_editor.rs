@@ -0,0 +1,62 @@
+use rarian::error::{RarianError, Result};
+use std::env;
+use std::fs::{read_to_string, remove_file, write};
+use std::process::Command;
+use ulid::Ulid;
+
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+/// The editor command from `$VISUAL` or `$EDITOR`, which may include arguments like `code --wait`
+fn editor_command() -> Vec<String> {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string())
+        .split_whitespace()
+        .map(|part| part.to_string())
+        .collect()
+}
+
+/// Writes `text` to a temporary file and opens it in the user's editor. Returns the edited text, or
+/// `None` if it was left unchanged. The terminal must be restored before calling this.
+pub fn edit_in_external_editor(text: &str) -> Result<Option<String>> {
+    let path = env::temp_dir().join(format!("rarian-{}.md", Ulid::new()));
+    write(&path, text).map_err(|err| {
+        RarianError::Editor(format!(
+            "failed to write the note to a temporary file: {}",
+            err
+        ))
+    })?;
+    let edited = run_editor(&path);
+    let _ = remove_file(&path);
+    let edited = edited?;
+    if edited.trim() == text.trim() {
+        Ok(None)
+    } else {
+        Ok(Some(edited.trim().to_string()))
+    }
+}
+
+fn run_editor(path: &std::path::Path) -> Result<String> {
+    let command = editor_command();
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| RarianError::Editor("no editor configured".to_string()))?;
+    let status = Command::new(program)
+        .args(args)
+        .arg(path)
+        .status()
+        .map_err(|err| RarianError::Editor(format!("failed to launch {}: {}", program, err)))?;
+    if !status.success() {
+        return Err(RarianError::Editor(format!(
+            "{} exited with {}",
+            program, status
+        )));
+    }
+    read_to_string(path)
+        .map_err(|err| RarianError::Editor(format!("failed to read the edited note: {}", err)))
+}
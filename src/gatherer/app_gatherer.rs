@@ -1,6 +1,9 @@
 extern crate sysinfo;
+use crate::bus::Bus;
 use crate::cacher::{Cache, FileCacher, LoadFromCache};
 use crate::config::Config;
+use crate::error::{RarianError, Result};
+use crate::supervisor::spawn_supervised;
 use active_win_pos_rs::{get_active_window, ActiveWindow};
 use itertools::Itertools;
 use regex::Regex;
@@ -9,8 +12,8 @@ use serde_with::skip_serializing_none;
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
-use std::thread::{sleep, spawn, JoinHandle};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{sleep, JoinHandle};
 use std::time::{Duration, SystemTime};
 use sysinfo::{
     set_open_files_limit, Pid, Process, ProcessExt, ProcessRefreshKind, System, SystemExt,
@@ -84,6 +87,18 @@ impl ActiveProcessEvent {
     pub fn get_title(&self) -> &str {
         &self.process.title
     }
+
+    pub fn get_app_name(&self) -> &str {
+        &self.process.app_name
+    }
+
+    pub fn get_start_time(&self) -> SystemTime {
+        self.active_start_time
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.active_duration
+    }
 }
 
 impl Display for ActiveProcessEvent {
@@ -92,17 +107,23 @@ impl Display for ActiveProcessEvent {
     }
 }
 
-struct ActiveProcessGatherer {
+/// Locks the shared process state even if the gatherer thread panicked while holding it, as the
+/// thread is restarted and carries on with the same state
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct ActiveProcessGatherer<'a> {
     current: Arc<Mutex<Option<ActiveProcessEvent>>>,
     process_events: Arc<Mutex<Vec<ActiveProcessEvent>>>,
-    cacher: FileCacher,
+    cacher: &'a mut FileCacher,
 }
 
-impl ActiveProcessGatherer {
+impl<'a> ActiveProcessGatherer<'a> {
     pub fn new(
         current: Arc<Mutex<Option<ActiveProcessEvent>>>,
         process_events: Arc<Mutex<Vec<ActiveProcessEvent>>>,
-        cacher: FileCacher,
+        cacher: &'a mut FileCacher,
     ) -> Self {
         Self {
             current,
@@ -112,7 +133,7 @@ impl ActiveProcessGatherer {
     }
 
     pub fn is_current_process(&self, active_process: &ActiveProcess) -> bool {
-        let current = &*self.current.lock().unwrap();
+        let current = &*lock(&self.current);
         match current {
             Some(current) => current.process == *active_process,
             None => false,
@@ -120,22 +141,32 @@ impl ActiveProcessGatherer {
     }
 
     pub fn update_active_duration(&mut self) {
-        let mut current_process = self.current.lock().unwrap();
+        let mut current_process = lock(&self.current);
         if let Some(ref mut current_process) = *current_process {
-            current_process.active_duration = SystemTime::now()
-                .duration_since(current_process.active_start_time)
-                .expect("now is after this process has started");
+            // The clock can move backwards, in which case the duration is kept as it was
+            if let Ok(duration) =
+                SystemTime::now().duration_since(current_process.active_start_time)
+            {
+                current_process.active_duration = duration;
+            }
         }
     }
 
-    pub fn update_current_and_cache(&mut self, new_process: Option<ActiveProcessEvent>) {
-        let mut current_process = self.current.lock().unwrap();
+    /// Replaces the current process, caching the one it replaces. The current process is replaced
+    /// even if caching fails.
+    pub fn update_current_and_cache(
+        &mut self,
+        new_process: Option<ActiveProcessEvent>,
+    ) -> Result<()> {
+        let mut current_process = lock(&self.current);
+        let mut cached = Ok(());
         if let Some(ref mut current) = *current_process {
-            self.cacher.cache(current).expect("cache event failed");
-            let mut process_events = self.process_events.lock().unwrap();
+            cached = self.cacher.cache(current);
+            let mut process_events = lock(&self.process_events);
             process_events.push(current.clone());
         }
         *current_process = new_process;
+        cached
     }
 }
 
@@ -163,9 +194,10 @@ fn get_active_process(sys: &System) -> Option<ActiveProcess> {
 const IGNORE_APPS_REGEXES: [&str; 3] = ["Rarian app$", "^Task Switching$", "^$"];
 
 fn monitor_processes(
-    cacher: FileCacher,
-    sleep_duration: Duration,
-    gatherer_rx: Receiver<bool>,
+    bus: &Bus,
+    cacher: &mut FileCacher,
+    sleep_duration: Arc<Mutex<Duration>>,
+    gatherer_rx: &Receiver<bool>,
     current: Arc<Mutex<Option<ActiveProcessEvent>>>,
     process_events: Arc<Mutex<Vec<ActiveProcessEvent>>>,
 ) {
@@ -174,7 +206,8 @@ fn monitor_processes(
 
     let ignore_apps = Regex::new(IGNORE_APPS_REGEXES.join("|").as_str()).unwrap();
     while let Err(_) = gatherer_rx.try_recv() {
-        sleep(sleep_duration);
+        let duration = *lock(&sleep_duration);
+        sleep(duration);
         sys.refresh_processes_specifics(ProcessRefreshKind::new());
 
         active_process_gatherer.update_active_duration();
@@ -185,60 +218,90 @@ fn monitor_processes(
                         continue;
                     }
                     let new_process = ActiveProcessEvent::new(active_process);
-                    active_process_gatherer.update_current_and_cache(Some(new_process));
+                    let cached =
+                        active_process_gatherer.update_current_and_cache(Some(new_process));
+                    report_app_change(bus, cached);
                 }
             }
             None => {
-                active_process_gatherer.update_current_and_cache(None);
+                if lock(&active_process_gatherer.current).is_some() {
+                    let cached = active_process_gatherer.update_current_and_cache(None);
+                    report_app_change(bus, cached);
+                }
             }
         }
     }
     println!("process monitor stopping gracefully");
 }
 
+fn report_app_change(bus: &Bus, cached: Result<()>) {
+    if let Err(err) = cached {
+        let _ = bus.report_error(err);
+    }
+    let _ = bus.current_app_changed();
+}
+
 pub struct AppGatherer {
     thread_ctrl_tx: Sender<bool>,
     gatherer_thread: JoinHandle<()>,
     current: Arc<Mutex<Option<ActiveProcessEvent>>>,
     process_events: Arc<Mutex<Vec<ActiveProcessEvent>>>,
+    sleep_duration: Arc<Mutex<Duration>>,
+    /// The lines of the app log that couldn't be read when it was loaded
+    unreadable: Option<RarianError>,
 }
 
 impl AppGatherer {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(bus: Bus, config: &Config) -> Result<Self> {
         let data_path: PathBuf = PathBuf::from(config.data_path.clone()).join("apps.json");
-        let mut cacher = FileCacher::new(data_path);
-        let sleep_duration = config.sleep_duration;
+        let mut cacher = FileCacher::new(data_path)?;
+        let sleep_duration = Arc::new(Mutex::new(config.sleep_duration));
 
         let (thread_ctrl_tx, thread_ctrl_rx) = channel::<bool>();
         let current = Arc::new(Mutex::new(None));
         let process_events = Arc::new(Mutex::new(cacher.load_from_cache()));
+        let unreadable = cacher.unreadable();
         let current_clone = Arc::clone(&current);
         let process_events_clone = Arc::clone(&process_events);
+        let sleep_duration_clone = Arc::clone(&sleep_duration);
 
-        let gatherer_thread = spawn(move || {
+        let supervisor_bus = bus.clone();
+        let gatherer_thread = spawn_supervised("app gatherer", supervisor_bus, move || {
             monitor_processes(
-                cacher,
-                sleep_duration,
-                thread_ctrl_rx,
-                current_clone,
-                process_events_clone,
+                &bus,
+                &mut cacher,
+                sleep_duration_clone.clone(),
+                &thread_ctrl_rx,
+                current_clone.clone(),
+                process_events_clone.clone(),
             )
         });
-        Self {
+        Ok(Self {
             thread_ctrl_tx,
             gatherer_thread,
             current,
             process_events,
-        }
+            sleep_duration,
+            unreadable,
+        })
+    }
+
+    pub fn unreadable(&self) -> Option<RarianError> {
+        self.unreadable.clone()
+    }
+
+    /// How long the gatherer sleeps between checking the focused app, from its next check on
+    pub fn set_sleep_duration(&self, sleep_duration: Duration) {
+        *lock(&self.sleep_duration) = sleep_duration;
     }
 
     pub fn get_current(&self) -> Option<ActiveProcessEvent> {
-        let current = &*self.current.lock().unwrap();
+        let current = &*lock(&self.current);
         return current.clone();
     }
 
     pub fn get_last_processes(&self, n: usize) -> Vec<ActiveProcessEvent> {
-        let process_events = &*self.process_events.lock().unwrap();
+        let process_events = &*lock(&self.process_events);
         let num = std::cmp::min(n, process_events.len());
         let last_processes: Vec<ActiveProcessEvent> = process_events
             .iter()
@@ -251,7 +314,8 @@ impl AppGatherer {
     }
 
     pub fn close(self) {
-        self.thread_ctrl_tx.send(true).expect("send failed");
-        self.gatherer_thread.join().unwrap();
+        // The thread is gone already if it crashed too often
+        let _ = self.thread_ctrl_tx.send(true);
+        let _ = self.gatherer_thread.join();
     }
 }
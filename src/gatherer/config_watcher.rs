@@ -0,0 +1,79 @@
+use notify::EventKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{sleep, JoinHandle};
+use std::time::Duration;
+
+use crate::bus::Bus;
+use crate::config::{Config, CONFIG_FILE};
+use crate::error::{RarianError, Result};
+use crate::gatherer::file_watcher::watch_file_thread;
+use crate::supervisor::spawn_supervised;
+
+/// Editors write the config in a few steps, so it's reloaded once it was left alone this long
+const SETTLE_DURATION: Duration = Duration::from_millis(200);
+
+fn is_config_event(event: &notify::Event, config_path: &Path) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event
+            .paths
+            .iter()
+            .any(|path| path.file_name() == config_path.file_name())
+}
+
+fn forward_config_changes(
+    bus: &Bus,
+    notify_rx: &Receiver<notify::Result<notify::Event>>,
+    config_path: &Path,
+) {
+    loop {
+        match notify_rx.recv() {
+            Ok(Ok(event)) => {
+                if !is_config_event(&event, config_path) {
+                    continue;
+                }
+                sleep(SETTLE_DURATION);
+                while notify_rx.try_recv().is_ok() {}
+                if bus.config_changed().is_err() {
+                    break;
+                }
+            }
+            Ok(Err(e)) => {
+                let _ = bus.report_error(RarianError::Watch {
+                    path: config_path.to_path_buf(),
+                    message: e.to_string(),
+                });
+            }
+            // The watcher stopped
+            Err(_) => break,
+        }
+    }
+}
+
+/// Tells the service thread whenever the config file in the data path changes
+pub struct ConfigWatcher {
+    thread_ctrl: Sender<bool>,
+    watcher_thread: JoinHandle<()>,
+}
+
+impl ConfigWatcher {
+    pub fn new(bus: Bus, config: &Config) -> Result<Self> {
+        let config_path: PathBuf = config.data_path.join(CONFIG_FILE);
+        let (notify_tx, notify_rx) = channel();
+        let (thread_ctrl, thread_ctrl_rx) = channel();
+        let watcher_thread = watch_file_thread(&config_path, notify_tx, thread_ctrl_rx)?;
+        let supervisor_bus = bus.clone();
+        spawn_supervised("config watcher", supervisor_bus, move || {
+            forward_config_changes(&bus, &notify_rx, &config_path)
+        });
+        Ok(Self {
+            thread_ctrl,
+            watcher_thread,
+        })
+    }
+
+    pub fn close(self) {
+        let _ = self.thread_ctrl.send(true);
+        let _ = self.watcher_thread.join();
+    }
+}
@@ -1,134 +1,142 @@
 use itertools::Itertools;
 use notify::event::ModifyKind;
 use notify::EventKind;
-use serde_json::{self, Value};
+use serde::{Deserialize, Serialize};
 extern crate notify;
-use anyhow::Result;
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread::{spawn, JoinHandle};
+use std::thread::JoinHandle;
 use std::time::SystemTime;
 
+use crate::bus::{AddNote, Bus, CurrentApp};
 use crate::cacher::{Cache, FileCacher};
 use crate::config::Config;
-use crate::gatherer::app_gatherer::ActiveProcessEvent;
+use crate::error::{RarianError, Result};
 use crate::gatherer::file_watcher::watch_dir_thread;
-use crate::StateMachine;
+use crate::supervisor::spawn_supervised;
 
-fn cache_event(event: &notify::Event) -> Value {
-    let timestamp = SystemTime::now();
-    serde_json::json!({
-        "event": event,
-        "timestamp": timestamp
-    })
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEvent {
+    pub event: notify::Event,
+    pub timestamp: SystemTime,
+}
+
+fn cache_event(event: &notify::Event) -> FileEvent {
+    FileEvent {
+        event: event.clone(),
+        timestamp: SystemTime::now(),
+    }
 }
 
 fn create_notify_channel() -> (
-    Sender<Result<notify::Event, notify::Error>>,
-    Receiver<Result<notify::Event, notify::Error>>,
+    Sender<notify::Result<notify::Event>>,
+    Receiver<notify::Result<notify::Event>>,
 ) {
     return channel();
 }
 
+/// Starts a watcher for every path, reporting the paths that can't be watched
 fn create_file_watchers(
     watcher_paths: Vec<PathBuf>,
-    notify_tx: Sender<Result<notify::Event, notify::Error>>,
+    notify_tx: Sender<notify::Result<notify::Event>>,
+    bus: &Bus,
 ) -> Vec<(Sender<bool>, JoinHandle<()>)> {
     let file_watcher_threads: Vec<(Sender<bool>, JoinHandle<()>)> = watcher_paths
         .into_iter()
-        .map(|file_path| {
+        .filter_map(|file_path| {
             let (notify_ctrl_tx, notify_ctrl_rx) = channel();
             let path: PathBuf = PathBuf::from(file_path);
-            let file_watcher_thread =
-                watch_dir_thread(path.as_path(), notify_tx.clone(), notify_ctrl_rx);
-            return (notify_ctrl_tx, file_watcher_thread);
+            match watch_dir_thread(path.as_path(), notify_tx.clone(), notify_ctrl_rx) {
+                Ok(file_watcher_thread) => Some((notify_ctrl_tx, file_watcher_thread)),
+                Err(err) => {
+                    let _ = bus.report_error(err);
+                    None
+                }
+            }
         })
         .collect();
     return file_watcher_threads;
 }
 
-fn check_for_notes(
-    comment_identifier: &str,
-    state_machine_tx: Sender<StateMachine>,
-    file_event: notify::Event,
-) {
-    file_event.paths.iter().for_each(|path| {
-        if path.is_file() {
-            if let Ok(mut file) = OpenOptions::new().read(true).open(path) {
-                let mut read_buffer = String::new();
-                match file.read_to_string(&mut read_buffer) {
-                    Ok(_) => {
-                        let split_file: Vec<&str> =
-                            read_buffer.trim().split(comment_identifier).collect();
-                        if split_file.len() > 1 && split_file.len() % 2 == 1 {
-                            let mut file_text = Vec::<&str>::new();
-                            let mut notes = Vec::<&str>::new();
-                            split_file
-                                .into_iter()
-                                .enumerate()
-                                .for_each(|(index, text)| {
-                                    if index % 2 == 0 {
-                                        file_text.push(text);
-                                    } else {
-                                        notes.push(text);
-                                    }
-                                });
-                            let (tx, rx) = channel::<Option<ActiveProcessEvent>>();
-                            state_machine_tx.send(StateMachine::CurrentApp(tx)).unwrap();
-                            let process = rx
-                                .recv()
-                                .expect("main thread is alive")
-                                .expect("there must be a process for file changes");
-                            let mut new_file_text = file_text.into_iter().map(|text| text).join("");
-                            new_file_text.push_str("\n");
-                            if let Ok(mut write_file) =
-                                OpenOptions::new().truncate(true).write(true).open(path)
-                            {
-                                if let Ok(_) = write_file.write_all(new_file_text.as_bytes()) {
-                                    notes.into_iter().for_each(|note| {
-                                        let mut links: Vec<String> = file_event
-                                            .paths
-                                            .iter()
-                                            .map(|p| p.to_string_lossy().to_string())
-                                            .collect();
-                                        links.push(process.get_title().to_string());
-                                        state_machine_tx
-                                            .send(StateMachine::NewNote(
-                                                note.trim().to_string(),
-                                                links,
-                                            ))
-                                            .unwrap();
-                                    })
-                                } else {
-                                    println!("failed to write to file will try again next time");
-                                }
-                            } else {
-                                println!("Error opening file for writing: {:?}", path)
-                            }
-                        }
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {}
-                    Err(err) => {
-                        panic!("failed to parse file: {}", err)
-                    }
+/// Moves the notes written between comment identifiers in a file into the notes store
+fn check_for_notes(comment_identifier: &str, bus: &Bus, file_event: notify::Event) -> Result<()> {
+    for path in file_event.paths.iter().filter(|path| path.is_file()) {
+        let Ok(mut file) = OpenOptions::new().read(true).open(path) else {
+            continue;
+        };
+        let mut read_buffer = String::new();
+        match file.read_to_string(&mut read_buffer) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => continue,
+            Err(err) => {
+                return Err(RarianError::ReadFile {
+                    path: path.clone(),
+                    message: err.to_string(),
+                })
+            }
+        }
+        let split_file: Vec<&str> = read_buffer.trim().split(comment_identifier).collect();
+        if split_file.len() > 1 && split_file.len() % 2 == 1 {
+            let notes: Vec<&str> = split_file.iter().skip(1).step_by(2).copied().collect();
+            // The text of the file with the notes before `added` removed from it
+            let file_text = |added: usize| {
+                let mut text = split_file
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| index % 2 == 0 || index / 2 >= added)
+                    .map(|(index, text)| match index % 2 {
+                        0 => text.to_string(),
+                        _ => format!("{comment_identifier}{text}{comment_identifier}"),
+                    })
+                    .join("");
+                text.push('\n');
+                text
+            };
+            let write_file = |text: String| {
+                OpenOptions::new()
+                    .truncate(true)
+                    .write(true)
+                    .open(path)
+                    .and_then(|mut write_file| write_file.write_all(text.as_bytes()))
+                    .map_err(|err| RarianError::WriteFile {
+                        path: path.clone(),
+                        message: err.to_string(),
+                    })
+            };
+            let process = bus.request(CurrentApp)?;
+            // The notes are only added once they are removed from the file, otherwise they would be
+            // added again the next time it changes. The ones that fail to be added are put back.
+            write_file(file_text(notes.len()))?;
+            for (added, note) in notes.into_iter().enumerate() {
+                let mut links: Vec<String> = file_event
+                    .paths
+                    .iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect();
+                if let Some(process) = &process {
+                    links.push(process.get_title().to_string());
+                }
+                if let Err(err) = bus.request(AddNote {
+                    text: note.trim().to_string(),
+                    links,
+                }) {
+                    write_file(file_text(added))?;
+                    return Err(err);
                 }
             }
         }
-    });
+    }
+    Ok(())
 }
 
-fn act_on_event(
-    comment_identifier: &str,
-    state_machine_tx: Sender<StateMachine>,
-    file_event: notify::Event,
-) {
+fn act_on_event(comment_identifier: &str, bus: &Bus, file_event: notify::Event) -> Result<()> {
     match file_event.kind {
         EventKind::Modify(ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Other) => {
-            check_for_notes(comment_identifier, state_machine_tx, file_event)
+            check_for_notes(comment_identifier, bus, file_event)
         }
-        _ => {}
+        _ => Ok(()),
     }
 }
 
@@ -148,36 +156,71 @@ fn path_includes_ignored_path(path: &PathBuf, ignore_paths: &Vec<String>) -> boo
     })
 }
 
-fn create_caching_thread(
-    state_machine_tx: Sender<StateMachine>,
-    notify_rx: Receiver<Result<notify::Event, notify::Error>>,
-    data_path: PathBuf,
-    comment_identifier: String,
-    ignore_paths: Vec<String>,
+fn cache_file_events(
+    bus: &Bus,
+    notify_rx: &Receiver<notify::Result<notify::Event>>,
+    cacher: &mut FileCacher,
+    data_path: &PathBuf,
+    comment_identifier: &str,
+    ignore_paths: &Vec<String>,
 ) {
-    let mut cacher = FileCacher::new(data_path.clone());
-    spawn(move || loop {
+    let report_error = |err: RarianError| {
+        let _ = bus.report_error(err);
+    };
+    loop {
         let cache_path = data_path.as_path();
         match notify_rx.recv() {
             Ok(Ok(file_event)) => {
                 if file_event.paths.clone().iter().all(|path| {
                     !path.ends_with(cache_path)
                         && !path_is_hidden(path)
-                        && !path_includes_ignored_path(path, &ignore_paths)
+                        && !path_includes_ignored_path(path, ignore_paths)
                 }) {
-                    cacher
-                        .cache(&cache_event(&file_event))
-                        .expect("cache event failed");
-                    act_on_event(&comment_identifier, state_machine_tx.clone(), file_event);
+                    if let Err(err) = cacher.cache(&cache_event(&file_event)) {
+                        report_error(err);
+                    }
+                    if let (EventKind::Modify(_) | EventKind::Create(_), Some(path)) =
+                        (file_event.kind, file_event.paths.first())
+                    {
+                        let _ = bus.file_changed(path.clone());
+                    }
+                    match act_on_event(comment_identifier, bus, file_event) {
+                        Err(RarianError::Disconnected) => break,
+                        Err(err) => report_error(err),
+                        Ok(()) => {}
+                    }
                 }
             }
-            Ok(Err(e)) => println!("notify error: {:?}!", e),
-            Err(e) => {
-                println!("rx error: {:?}!", e);
-                break;
-            }
+            Ok(Err(e)) => report_error(RarianError::Watch {
+                path: e.paths.first().cloned().unwrap_or_default(),
+                message: e.to_string(),
+            }),
+            // All the watchers stopped
+            Err(_) => break,
         }
+    }
+}
+
+fn create_caching_thread(
+    bus: Bus,
+    notify_rx: Receiver<notify::Result<notify::Event>>,
+    data_path: PathBuf,
+    comment_identifier: String,
+    ignore_paths: Vec<String>,
+) -> Result<()> {
+    let mut cacher = FileCacher::new(data_path.clone())?;
+    let supervisor_bus = bus.clone();
+    spawn_supervised("file gatherer", supervisor_bus, move || {
+        cache_file_events(
+            &bus,
+            &notify_rx,
+            &mut cacher,
+            &data_path,
+            &comment_identifier,
+            &ignore_paths,
+        )
     });
+    Ok(())
 }
 
 pub struct FileGatherer {
@@ -185,27 +228,77 @@ pub struct FileGatherer {
 }
 
 impl FileGatherer {
-    pub fn new(state_machine_tx: Sender<StateMachine>, config: &Config) -> Self {
+    pub fn new(bus: Bus, config: &Config) -> Result<Self> {
         let (notify_tx, notify_rx) = create_notify_channel();
-        let file_watcher_threads = create_file_watchers(config.watcher_paths.clone(), notify_tx);
+        let file_watcher_threads =
+            create_file_watchers(config.watcher_paths.clone(), notify_tx, &bus);
 
         let files_data_path = PathBuf::from(config.data_path.clone()).join("files.json");
         create_caching_thread(
-            state_machine_tx,
+            bus,
             notify_rx,
             files_data_path,
             config.comment_identifier.clone(),
             config.ignore_paths.clone(),
-        );
-        Self {
+        )?;
+        Ok(Self {
             file_watcher_threads,
-        }
+        })
     }
 
     pub fn close(self) {
         for (thread_ctrl, watcher_thread) in self.file_watcher_threads.into_iter() {
-            thread_ctrl.send(true).expect("send failed");
-            watcher_thread.join().unwrap();
+            let _ = thread_ctrl.send(true);
+            let _ = watcher_thread.join();
         }
     }
 }
+
+#[cfg(test)]
+mod file_gatherer_test {
+    use super::*;
+    use crate::bus::Message;
+    use notify::event::DataChange;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use ulid::Ulid;
+
+    #[test]
+    fn notes_that_fail_to_be_added_are_put_back_in_the_file() {
+        let data_path = PathBuf::from("./testData/file_gatherer_notes");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let path = data_path.join("lib.rs");
+        write(&path, "fn main() {}\n%%first%%\n%%second%%\n").unwrap();
+        let (tx, rx) = channel();
+        let service = std::thread::spawn(move || {
+            let mut added = Vec::new();
+            for message in rx {
+                match message {
+                    Message::CurrentApp(_, reply) => {
+                        let _ = reply.send(Ok(None));
+                    }
+                    Message::AddNote(AddNote { text, .. }, reply) if added.is_empty() => {
+                        added.push(text);
+                        let _ = reply.send(Ok(Ulid::new()));
+                    }
+                    Message::AddNote(_, reply) => {
+                        let _ = reply.send(Err(RarianError::Disconnected));
+                    }
+                    _ => {}
+                }
+            }
+            added
+        });
+        let event = notify::Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any)))
+            .add_path(path.clone());
+
+        let result = check_for_notes("%%", &Bus::new(tx), event);
+        let added = service.join().unwrap();
+        let file_text = read_to_string(&path).unwrap();
+        remove_dir_all(&data_path).unwrap();
+
+        assert!(matches!(result, Err(RarianError::Disconnected)));
+        assert_eq!(added, vec!["first".to_string()]);
+        assert_eq!(file_text, "fn main() {}\n\n%%second%%\n");
+    }
+}
@@ -1,29 +1,40 @@
 extern crate notify;
+use crate::error::{RarianError, Result};
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread::{spawn, JoinHandle};
 
-fn watch_dir(
+fn watch(
     full_path: PathBuf,
-    tx: Sender<Result<notify::Event, notify::Error>>,
-) -> notify::RecommendedWatcher {
-    let mut watcher =
-        RecommendedWatcher::new(tx, Config::default()).expect("watcher creation failed");
+    mode: RecursiveMode,
+    tx: Sender<notify::Result<notify::Event>>,
+) -> Result<notify::RecommendedWatcher> {
+    let watch_error = |err: notify::Error| RarianError::Watch {
+        path: full_path.clone(),
+        message: err.to_string(),
+    };
+    let mut watcher = RecommendedWatcher::new(tx, Config::default()).map_err(watch_error)?;
     watcher
-        .watch(full_path.as_ref(), RecursiveMode::Recursive)
-        .expect("watcher watching failed");
-    return watcher;
+        .watch(full_path.as_ref(), mode)
+        .map_err(watch_error)?;
+    return Ok(watcher);
 }
 
-pub fn watch_dir_thread(
-    path: &Path,
-    tx: Sender<Result<notify::Event, notify::Error>>,
+fn watch_dir(
+    full_path: PathBuf,
+    tx: Sender<notify::Result<notify::Event>>,
+) -> Result<notify::RecommendedWatcher> {
+    watch(full_path, RecursiveMode::Recursive, tx)
+}
+
+/// Keeps `watcher` watching until `thread_ctrl` says to stop
+fn watcher_thread(
+    watcher: notify::RecommendedWatcher,
     thread_ctrl: Receiver<bool>,
 ) -> JoinHandle<()> {
-    let full_path = path.canonicalize().unwrap();
-    let watcher_handle = spawn(move || {
-        let _watcher = watch_dir(full_path, tx);
+    spawn(move || {
+        let _watcher = watcher;
         match thread_ctrl.recv() {
             Ok(event) => match &event {
                 true => {
@@ -38,8 +49,40 @@ pub fn watch_dir_thread(
             }
         }
         return;
-    });
-    return watcher_handle;
+    })
+}
+
+pub fn watch_dir_thread(
+    path: &Path,
+    tx: Sender<notify::Result<notify::Event>>,
+    thread_ctrl: Receiver<bool>,
+) -> Result<JoinHandle<()>> {
+    let full_path = path.canonicalize().map_err(|err| RarianError::Watch {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })?;
+    let watcher = watch_dir(full_path, tx)?;
+    return Ok(watcher_thread(watcher, thread_ctrl));
+}
+
+/// Watches the folder of a file without its sub-folders, so the file is still watched after an
+/// editor replaces it. The events are of every file in the folder.
+pub fn watch_file_thread(
+    path: &Path,
+    tx: Sender<notify::Result<notify::Event>>,
+    thread_ctrl: Receiver<bool>,
+) -> Result<JoinHandle<()>> {
+    let watch_error = |message: String| RarianError::Watch {
+        path: path.to_path_buf(),
+        message,
+    };
+    let folder = path
+        .parent()
+        .ok_or_else(|| watch_error("it has no folder".to_string()))?
+        .canonicalize()
+        .map_err(|err| watch_error(err.to_string()))?;
+    let watcher = watch(folder, RecursiveMode::NonRecursive, tx)?;
+    Ok(watcher_thread(watcher, thread_ctrl))
 }
 
 #[cfg(test)]
@@ -53,13 +96,13 @@ mod file_dir_test {
     fn create_dir_watcher_and_co(
         test_path: &PathBuf,
     ) -> (
-        Receiver<Result<notify::Event, notify::Error>>,
+        Receiver<notify::Result<notify::Event>>,
         Sender<bool>,
         JoinHandle<()>,
     ) {
         let (tx, rx) = create_channel();
         let (thread_ctrl, thread_rx) = channel();
-        let watcher_thread = watch_dir_thread(test_path.as_path(), tx, thread_rx);
+        let watcher_thread = watch_dir_thread(test_path.as_path(), tx, thread_rx).unwrap();
         sleep(duration());
         return (rx, thread_ctrl, watcher_thread);
     }
@@ -75,7 +118,8 @@ mod file_dir_test {
         let _watcher = watch_dir(
             test_path.as_path().canonicalize().unwrap(),
             create_channel().0,
-        );
+        )
+        .unwrap();
         rmdir_thread.join().unwrap();
     }
 
@@ -84,7 +128,7 @@ mod file_dir_test {
         let (test_path, rmdir_thread) =
             create_test_dir("watcher_should_return_correct_event_for_file_in_watched_dir");
         let (tx, rx) = create_channel();
-        let _watcher = watch_dir(test_path.as_path().canonicalize().unwrap(), tx);
+        let _watcher = watch_dir(test_path.as_path().canonicalize().unwrap(), tx).unwrap();
         sleep(duration());
 
         let file_path = create_file_in_dir(&test_path, "tmp.txt", "temp");
@@ -100,7 +144,7 @@ mod file_dir_test {
     fn watch_sub_folder_recursive() {
         let (test_path, rmdir_thread) = create_test_dir("watch_sub_folder_recursive");
         let (tx, rx) = create_channel();
-        let _watcher = watch_dir(test_path.as_path().canonicalize().unwrap(), tx);
+        let _watcher = watch_dir(test_path.as_path().canonicalize().unwrap(), tx).unwrap();
 
         let sub_dir_path = create_test_path("watch_sub_folder_recursive/tmp_dir");
         create_dir_all(&sub_dir_path).expect("create dir failed");
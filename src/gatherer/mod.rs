@@ -1,4 +1,5 @@
 pub mod app_gatherer;
+pub mod config_watcher;
 pub mod file_gatherer;
 pub mod file_watcher;
 mod test_utils;
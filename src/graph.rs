@@ -0,0 +1,406 @@
+use crate::digest::project_of;
+use crate::notes::{Note, NoteStatus, NoteTaker};
+use crate::timeline::local_date;
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Note,
+    App,
+    File,
+    Project,
+}
+
+impl Display for NodeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeKind::Note => write!(f, "note"),
+            NodeKind::App => write!(f, "app"),
+            NodeKind::File => write!(f, "file"),
+            NodeKind::Project => write!(f, "project"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// A note linked to an app or a file
+    Link,
+    /// A `[[reference]]` from one note to another
+    Reference,
+    /// A file in the project it belongs to
+    Project,
+}
+
+impl Display for EdgeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EdgeKind::Link => write!(f, "link"),
+            EdgeKind::Reference => write!(f, "reference"),
+            EdgeKind::Project => write!(f, "project"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphNode {
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub kind: EdgeKind,
+    pub weight: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    GraphMl,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!(
+                "unknown format {}, expected dot, graphml or json",
+                format
+            )),
+        }
+    }
+}
+
+/// Which notes go into the graph. Every filter that is set has to match.
+#[derive(Debug, Clone, Default)]
+pub struct GraphFilter {
+    /// The first day a note was created or edited on
+    pub from: Option<NaiveDate>,
+    /// The last day a note was created or edited on
+    pub to: Option<NaiveDate>,
+    /// A note has to have at least one of these `#tags`
+    pub tags: Vec<String>,
+    /// A note has to be linked to a file in this project
+    pub project: Option<String>,
+    pub include_archived: bool,
+}
+
+impl GraphFilter {
+    fn matches(&self, note: &Note) -> bool {
+        let date = local_date(note.updated_date.unwrap_or(note.creation_date));
+        let tags = note.tags();
+        (self.include_archived || note.status == NoteStatus::Active)
+            && self.from.is_none_or(|from| date >= from)
+            && self.to.is_none_or(|to| date <= to)
+            && (self.tags.is_empty()
+                || self
+                    .tags
+                    .iter()
+                    .any(|tag| tags.contains(&tag.trim_start_matches('#').to_lowercase())))
+            && self.project.as_ref().is_none_or(|project| {
+                note.links()
+                    .iter()
+                    .any(|link| project_of(Path::new(&link.link)).as_ref() == Some(project))
+            })
+    }
+}
+
+/// Whether a link is a file path rather than an app title
+pub(crate) fn is_file_link(link: &str) -> bool {
+    Path::new(link).is_absolute() || link.starts_with("./") || link.starts_with(".\\")
+}
+
+/// Notes and what they are linked to: apps, files, the projects of the files, and other notes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl Graph {
+    pub fn from_notes(note_taker: &NoteTaker, filter: &GraphFilter) -> Graph {
+        let notes: Vec<&Note> = note_taker
+            .notes()
+            .filter(|note| filter.matches(note))
+            .sorted_by_key(|note| note.id)
+            .collect();
+        let note_ids: HashSet<_> = notes.iter().map(|note| note.id).collect();
+        // Ordered by id so the export is the same every time
+        let mut nodes: BTreeMap<String, GraphNode> = BTreeMap::new();
+        let mut edges: BTreeMap<(String, String), GraphEdge> = BTreeMap::new();
+        for note in notes.iter() {
+            let note_id = format!("note:{}", note.id);
+            nodes.insert(
+                note_id.clone(),
+                GraphNode {
+                    id: note_id.clone(),
+                    kind: NodeKind::Note,
+                    label: note.title().to_string(),
+                },
+            );
+            for link in note.links() {
+                let (kind, target_id) = match is_file_link(&link.link) {
+                    true => (NodeKind::File, format!("file:{}", link.link)),
+                    false => (NodeKind::App, format!("app:{}", link.link)),
+                };
+                nodes.entry(target_id.clone()).or_insert(GraphNode {
+                    id: target_id.clone(),
+                    kind,
+                    label: link.link.clone(),
+                });
+                add_edge(
+                    &mut edges,
+                    &note_id,
+                    &target_id,
+                    EdgeKind::Link,
+                    link.weight,
+                );
+                if kind != NodeKind::File {
+                    continue;
+                }
+                if let Some(project) = project_of(Path::new(&link.link)) {
+                    let project_id = format!("project:{}", project);
+                    nodes.entry(project_id.clone()).or_insert(GraphNode {
+                        id: project_id.clone(),
+                        kind: NodeKind::Project,
+                        label: project,
+                    });
+                    edges
+                        .entry((target_id.clone(), project_id.clone()))
+                        .or_insert(GraphEdge {
+                            source: target_id.clone(),
+                            target: project_id,
+                            kind: EdgeKind::Project,
+                            weight: 1,
+                        });
+                }
+            }
+            for reference in note.references() {
+                let Some(target) = note_taker.resolve(&reference) else {
+                    continue;
+                };
+                if target.id != note.id && note_ids.contains(&target.id) {
+                    add_edge(
+                        &mut edges,
+                        &note_id,
+                        &format!("note:{}", target.id),
+                        EdgeKind::Reference,
+                        1,
+                    );
+                }
+            }
+        }
+        Graph {
+            nodes: nodes.into_values().collect(),
+            edges: edges.into_values().collect(),
+        }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::GraphMl => self.to_graphml(),
+            GraphFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+        }
+    }
+
+    /// A Graphviz digraph, with thicker edges for heavier links
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rarian {\n");
+        for node in self.nodes.iter() {
+            let shape = match node.kind {
+                NodeKind::Note => "note",
+                NodeKind::App => "box",
+                NodeKind::File => "component",
+                NodeKind::Project => "folder",
+            };
+            dot.push_str(&format!(
+                "  \"{}\" [label=\"{}\", kind=\"{}\", shape={}];\n",
+                escape_dot(&node.id),
+                escape_dot(&node.label),
+                node.kind,
+                shape
+            ));
+        }
+        for edge in self.edges.iter() {
+            dot.push_str(&format!(
+                "  \"{}\" -> \"{}\" [kind=\"{}\", weight={}, penwidth={}];\n",
+                escape_dot(&edge.source),
+                escape_dot(&edge.target),
+                edge.kind,
+                edge.weight,
+                edge.weight
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// GraphML, which Gephi and yEd import with the node kind and the edge weight as attributes
+    pub fn to_graphml(&self) -> String {
+        let mut graphml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+            "  <key id=\"kind\" for=\"all\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>\n",
+            "  <graph id=\"rarian\" edgedefault=\"directed\">\n",
+        ));
+        for node in self.nodes.iter() {
+            graphml.push_str(&format!(
+                concat!(
+                    "    <node id=\"{}\">\n",
+                    "      <data key=\"label\">{}</data>\n",
+                    "      <data key=\"kind\">{}</data>\n",
+                    "    </node>\n",
+                ),
+                escape_xml(&node.id),
+                escape_xml(&node.label),
+                node.kind
+            ));
+        }
+        for edge in self.edges.iter() {
+            graphml.push_str(&format!(
+                concat!(
+                    "    <edge source=\"{}\" target=\"{}\">\n",
+                    "      <data key=\"kind\">{}</data>\n",
+                    "      <data key=\"weight\">{}</data>\n",
+                    "    </edge>\n",
+                ),
+                escape_xml(&edge.source),
+                escape_xml(&edge.target),
+                edge.kind,
+                edge.weight
+            ));
+        }
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+}
+
+/// Adds an edge, or adds to the weight of the edge between the same nodes
+fn add_edge(
+    edges: &mut BTreeMap<(String, String), GraphEdge>,
+    source: &str,
+    target: &str,
+    kind: EdgeKind,
+    weight: usize,
+) {
+    edges
+        .entry((source.to_string(), target.to_string()))
+        .and_modify(|edge| edge.weight += weight)
+        .or_insert(GraphEdge {
+            source: source.to_string(),
+            target: target.to_string(),
+            kind,
+            weight,
+        });
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod graph_test {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::PathBuf;
+
+    #[test]
+    fn notes_apps_files_and_projects_are_exported() {
+        let path = PathBuf::from("./testData/graph_notes");
+        let project_path = path.join("graph_project");
+        create_dir_all(project_path.join(".git")).unwrap();
+        let file = std::env::current_dir()
+            .unwrap()
+            .join("testData/graph_notes/graph_project/main.rs")
+            .to_string_lossy()
+            .to_string();
+        let mut note_taker = NoteTaker::new(&path).unwrap();
+        let target = note_taker
+            .add_note(
+                "Parser design\n#rust",
+                vec!["editor".to_string(), file.clone()],
+            )
+            .unwrap();
+        note_taker
+            .add_note("see [[parser design]] #Rust", vec!["editor".to_string()])
+            .unwrap();
+        note_taker
+            .add_note("unrelated \"quoted\" <note>", vec!["browser".to_string()])
+            .unwrap();
+
+        let filter = GraphFilter {
+            tags: vec!["#rust".to_string()],
+            ..GraphFilter::default()
+        };
+        let graph = Graph::from_notes(&note_taker, &filter);
+        let kinds: Vec<NodeKind> = graph.nodes.iter().map(|node| node.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NodeKind::App,
+                NodeKind::File,
+                NodeKind::Note,
+                NodeKind::Note,
+                NodeKind::Project
+            ]
+        );
+        let reference = graph
+            .edges
+            .iter()
+            .find(|edge| edge.kind == EdgeKind::Reference)
+            .unwrap();
+        assert_eq!(reference.target, format!("note:{}", target));
+
+        let project_filter = GraphFilter {
+            project: Some("graph_project".to_string()),
+            ..GraphFilter::default()
+        };
+        let project_graph = Graph::from_notes(&note_taker, &project_filter);
+        let everything = Graph::from_notes(&note_taker, &GraphFilter::default());
+        remove_dir_all(&path).unwrap();
+        assert_eq!(
+            project_graph
+                .nodes
+                .iter()
+                .filter(|node| node.kind == NodeKind::Note)
+                .count(),
+            1
+        );
+        assert!(everything
+            .to_dot()
+            .contains("[label=\"unrelated \\\"quoted\\\" <note>\", kind=\"note\", shape=note]"));
+        assert!(everything
+            .to_graphml()
+            .contains("<data key=\"label\">unrelated &quot;quoted&quot; &lt;note&gt;</data>"));
+        assert!(everything
+            .render(GraphFormat::Json)
+            .contains("\"kind\": \"project\""));
+    }
+}
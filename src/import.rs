@@ -0,0 +1,843 @@
+use crate::error::{RarianError, Result};
+use crate::notes::{Link, Note, NoteStatus, NoteTaker, REFERENCE_REGEX};
+use crate::schema::Envelope;
+use crate::tasks::{Priority, Task};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use regex::Captures;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use ulid::Ulid;
+
+/// A note read by an importer, or why it couldn't be, with where it came from
+#[derive(Debug, Clone)]
+pub struct ImportEntry {
+    /// The file, and the line for formats with a note per line
+    pub source: String,
+    pub note: std::result::Result<Note, String>,
+}
+
+/// Reads notes from another format. Entries that can't be read are reported rather than failing
+/// the whole import.
+pub trait Importer {
+    fn read(&self, path: &Path) -> Result<Vec<ImportEntry>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// A folder of Markdown files, with or without front-matter, like the ones `Vault` writes
+    Markdown,
+    TodoTxt,
+    /// A JSON array of notes, or one note per line
+    Json,
+}
+
+impl ImportFormat {
+    /// The format of a file or folder by its extension, folders being Markdown
+    pub fn detect(path: &Path) -> Option<ImportFormat> {
+        if path.is_dir() {
+            return Some(ImportFormat::Markdown);
+        }
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "md" | "markdown" => Some(ImportFormat::Markdown),
+            "txt" => Some(ImportFormat::TodoTxt),
+            "json" | "jsonl" => Some(ImportFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn importer(self) -> Box<dyn Importer> {
+        match self {
+            ImportFormat::Markdown => Box::new(MarkdownImporter),
+            ImportFormat::TodoTxt => Box::new(TodoTxtImporter),
+            ImportFormat::Json => Box::new(JsonImporter),
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ImportFormat::Markdown),
+            "todotxt" | "todo.txt" | "todo" => Ok(ImportFormat::TodoTxt),
+            "json" | "jsonl" => Ok(ImportFormat::Json),
+            _ => Err(format!(
+                "unknown format {}, expected markdown, todotxt or json",
+                format
+            )),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    read_to_string(path).map_err(|err| RarianError::ReadFile {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })
+}
+
+/// A time as RFC 3339, `YYYY-MM-DD HH:MM[:SS]` or `YYYY-MM-DD`, the last two in local time
+fn parse_time(text: &str) -> Option<SystemTime> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.into());
+    }
+    let local = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.into())
+}
+
+/// `text` with a `#tag` line added for the tags it doesn't mention, as tags are read from the text
+fn with_tags(text: String, tags: &[String]) -> String {
+    let note = Note::new(&text, Vec::new());
+    let existing = note.tags();
+    let missing: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().trim_start_matches('#').to_string())
+        .filter(|tag| !tag.is_empty() && !existing.contains(&tag.to_lowercase()))
+        .map(|tag| format!("#{}", tag.replace(' ', "-")))
+        .collect();
+    match missing.is_empty() {
+        true => text,
+        false => format!("{}\n\n{}", text.trim_end(), missing.join(" ")),
+    }
+}
+
+fn new_note(text: &str, links: Vec<String>) -> Note {
+    Note::new(
+        text,
+        links.into_iter().map(|link| Link::new(link, 1)).collect(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FrontMatterValue {
+    Scalar(String),
+    List(Vec<String>),
+}
+
+/// The subset of YAML front-matter that notes use: scalars, inline `[a, b]` lists and `- item`
+/// lists
+#[derive(Debug, Clone, Default)]
+struct FrontMatter {
+    values: HashMap<String, FrontMatterValue>,
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    if text.starts_with('"') {
+        if let Ok(unquoted) = serde_json::from_str::<String>(text) {
+            return unquoted;
+        }
+    }
+    if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        return text[1..text.len() - 1].replace("''", "'");
+    }
+    text.trim_matches('"').to_string()
+}
+
+impl FrontMatter {
+    /// The front-matter of `text`, if it has any, and the rest of the text
+    fn split(text: &str) -> (Option<FrontMatter>, &str) {
+        let Some(rest) = text
+            .strip_prefix("---\n")
+            .or_else(|| text.strip_prefix("---\r\n"))
+        else {
+            return (None, text);
+        };
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            if line.trim_end() == "---" {
+                let front_matter = FrontMatter::parse(&rest[..offset]);
+                return (Some(front_matter), &rest[offset + line.len()..]);
+            }
+            offset += line.len();
+        }
+        (None, text)
+    }
+
+    fn parse(yaml: &str) -> FrontMatter {
+        let mut values = HashMap::new();
+        let mut list_key: Option<String> = None;
+        for line in yaml.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if let (Some(item), Some(key)) = (trimmed.strip_prefix("- "), &list_key) {
+                if let Some(FrontMatterValue::List(items)) = values.get_mut(key) {
+                    items.push(unquote(item));
+                }
+                continue;
+            }
+            let Some((key, value)) = trimmed.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_lowercase(), value.trim());
+            let value = if value.is_empty() {
+                list_key = Some(key.clone());
+                FrontMatterValue::List(Vec::new())
+            } else if let Some(inline) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                list_key = None;
+                FrontMatterValue::List(
+                    inline
+                        .split(',')
+                        .map(unquote)
+                        .filter(|item| !item.is_empty())
+                        .collect(),
+                )
+            } else {
+                list_key = None;
+                FrontMatterValue::Scalar(unquote(value))
+            };
+            values.insert(key, value);
+        }
+        FrontMatter { values }
+    }
+
+    fn scalar(&self, key: &str) -> Option<&str> {
+        match self.values.get(key)? {
+            FrontMatterValue::Scalar(value) => Some(value),
+            FrontMatterValue::List(_) => None,
+        }
+    }
+
+    /// A list, or a scalar as a list of one
+    fn list(&self, key: &str) -> Vec<String> {
+        match self.values.get(key) {
+            Some(FrontMatterValue::List(items)) => items.clone(),
+            Some(FrontMatterValue::Scalar(value)) => vec![value.clone()],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Markdown files, each one a note. Front-matter keys from the export (id, created, updated,
+/// status, links, tags, done, due, priority) are kept, and files without an id are linked to
+/// themselves. The index pages of an export are skipped.
+pub struct MarkdownImporter;
+
+impl MarkdownImporter {
+    fn markdown_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        if path.is_file() {
+            files.push(path.to_path_buf());
+            return Ok(());
+        }
+        let entries = read_dir(path).map_err(|err| RarianError::ReadFile {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        })?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Hidden files and folders, like .obsidian or .git, aren't notes
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let is_markdown = path
+                .extension()
+                .is_some_and(|extension| extension == "md" || extension == "markdown");
+            if path.is_dir() || is_markdown {
+                MarkdownImporter::markdown_files(&path, files)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The note in a Markdown file, `None` for an index page
+    fn read_note(path: &Path, text: &str) -> Option<std::result::Result<Note, String>> {
+        let (front_matter, body) = FrontMatter::split(text);
+        let front_matter = front_matter.unwrap_or_default();
+        if front_matter.scalar("rarian").is_some() {
+            return None;
+        }
+        let body = body.trim_matches('\n');
+        if body.trim().is_empty() {
+            return Some(Err("the note is empty".to_string()));
+        }
+        let id = match front_matter.scalar("id") {
+            Some(id) => match Ulid::from_string(id) {
+                Ok(id) => Some(id),
+                Err(err) => return Some(Err(format!("invalid id {}: {}", id, err))),
+            },
+            None => None,
+        };
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut text = body.to_string();
+        let mut links = front_matter.list("links");
+        if id.is_none() {
+            // Other vaults name notes by their file rather than their first line
+            let first_line = Note::new(body, Vec::new())
+                .title()
+                .trim_start_matches('#')
+                .trim()
+                .to_string();
+            if !first_line.eq_ignore_ascii_case(&stem) {
+                text = format!("{}\n\n{}", stem, body);
+            }
+            if links.is_empty() {
+                let path = path.canonicalize().unwrap_or(path.to_path_buf());
+                links.push(path.to_string_lossy().to_string());
+            }
+        }
+        let mut tags = front_matter.list("tags");
+        tags.extend(front_matter.list("tag"));
+        let mut note = new_note(&with_tags(text, &tags), links);
+        if let Some(id) = id {
+            note.id = id;
+        }
+        let created = front_matter
+            .scalar("created")
+            .or(front_matter.scalar("date"));
+        note.creation_date = match created.map(|created| (created, parse_time(created))) {
+            Some((_, Some(time))) => time,
+            Some((created, None)) => return Some(Err(format!("invalid date {}", created))),
+            None => path
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::now()),
+        };
+        note.updated_date = front_matter.scalar("updated").and_then(parse_time);
+        if front_matter.scalar("status") == Some("archived") {
+            note.status = NoteStatus::Archived;
+        }
+        let done = front_matter.scalar("done");
+        let due = front_matter.scalar("due");
+        let priority = front_matter.scalar("priority");
+        if done.is_some() || due.is_some() || priority.is_some() {
+            note.task = Some(Task {
+                done: done == Some("true"),
+                due: due.and_then(|due| NaiveDate::parse_from_str(due, "%Y-%m-%d").ok()),
+                priority: priority.and_then(|priority| priority.parse().ok()),
+            });
+        }
+        Some(Ok(note))
+    }
+}
+
+impl Importer for MarkdownImporter {
+    fn read(&self, path: &Path) -> Result<Vec<ImportEntry>> {
+        let mut files = Vec::new();
+        MarkdownImporter::markdown_files(path, &mut files)?;
+        files.sort();
+        let mut entries = Vec::new();
+        // `[[page]]` links point to file names, which only match titles if nothing clashed
+        let mut pages: HashMap<String, (Ulid, String)> = HashMap::new();
+        for file in files {
+            // A file that can't be read fails on its own, like a note that can't be
+            let text = match read_file(&file) {
+                Ok(text) => text,
+                Err(err) => {
+                    entries.push(ImportEntry {
+                        source: file.to_string_lossy().to_string(),
+                        note: Err(err.to_string()),
+                    });
+                    continue;
+                }
+            };
+            let Some(note) = MarkdownImporter::read_note(&file, &text) else {
+                continue;
+            };
+            if let (Ok(note), Some(stem)) = (&note, file.file_stem()) {
+                let title = note.title().to_string();
+                pages.insert(stem.to_string_lossy().to_lowercase(), (note.id, title));
+            }
+            entries.push(ImportEntry {
+                source: file.to_string_lossy().to_string(),
+                note,
+            });
+        }
+        for entry in entries.iter_mut() {
+            if let Ok(note) = &mut entry.note {
+                let text = REFERENCE_REGEX.replace_all(&note.text, |captures: &Captures| {
+                    let page = captures[1].trim();
+                    match pages.get(&page.to_lowercase()) {
+                        Some((id, title)) if !title.eq_ignore_ascii_case(page) => {
+                            format!("[[{}]]", id)
+                        }
+                        _ => captures[0].to_string(),
+                    }
+                });
+                note.text = text.to_string();
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// todo.txt, a task per line: `x` for done, `(A)` for the priority, the completion and creation
+/// dates, and `due:YYYY-MM-DD`. Priorities below `(B)` are low.
+pub struct TodoTxtImporter;
+
+impl TodoTxtImporter {
+    fn take_date(rest: &mut &str) -> Option<NaiveDate> {
+        let (word, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+        let date = NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()?;
+        *rest = remaining.trim_start();
+        Some(date)
+    }
+
+    fn priority(letter: char) -> Option<Priority> {
+        match letter.to_ascii_uppercase() {
+            'A' => Some(Priority::High),
+            'B' => Some(Priority::Medium),
+            'C'..='Z' => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    fn read_line(line: &str) -> std::result::Result<Note, String> {
+        let mut rest = line.trim();
+        let done = rest.starts_with("x ");
+        if done {
+            rest = rest[2..].trim_start();
+        }
+        let mut priority = None;
+        let bytes = rest.as_bytes();
+        if bytes.len() > 3 && bytes[0] == b'(' && bytes[2] == b')' && bytes[3] == b' ' {
+            priority = TodoTxtImporter::priority(char::from(bytes[1]));
+            rest = rest[4..].trim_start();
+        }
+        let first = TodoTxtImporter::take_date(&mut rest);
+        let second = TodoTxtImporter::take_date(&mut rest);
+        let (completed, created) = match done {
+            true => (first, second),
+            false => (None, first),
+        };
+        let mut due = None;
+        let mut words = Vec::new();
+        for word in rest.split_whitespace() {
+            if let Some(date) = word.strip_prefix("due:") {
+                due = Some(
+                    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .map_err(|_| format!("invalid due date {}", date))?,
+                );
+            } else if let Some(letter) = word.strip_prefix("pri:").and_then(|p| p.chars().next()) {
+                priority = priority.or(TodoTxtImporter::priority(letter));
+            } else {
+                words.push(word);
+            }
+        }
+        if words.is_empty() {
+            return Err("the task is empty".to_string());
+        }
+        let mut note = new_note(&words.join(" "), Vec::new());
+        let local_time = |date: NaiveDate| {
+            date.and_hms_opt(0, 0, 0)
+                .and_then(|time| Local.from_local_datetime(&time).earliest())
+                .map(SystemTime::from)
+        };
+        if let Some(created) = created.and_then(local_time) {
+            note.creation_date = created;
+        }
+        note.updated_date = completed.and_then(local_time);
+        note.task = Some(Task {
+            done,
+            due,
+            priority,
+        });
+        Ok(note)
+    }
+}
+
+impl Importer for TodoTxtImporter {
+    fn read(&self, path: &Path) -> Result<Vec<ImportEntry>> {
+        let text = read_file(path)?;
+        Ok(text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| ImportEntry {
+                source: format!("{}:{}", path.display(), index + 1),
+                note: TodoTxtImporter::read_line(line),
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonLink {
+    Title(String),
+    /// A link as rarian stores it
+    Link {
+        link: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonTime {
+    Text(String),
+    /// Seconds since the Unix epoch
+    Unix(u64),
+    /// A time as rarian stores it
+    System(SystemTime),
+}
+
+impl JsonTime {
+    fn time(&self) -> std::result::Result<SystemTime, String> {
+        match self {
+            JsonTime::Text(text) => parse_time(text).ok_or(format!("invalid date {}", text)),
+            JsonTime::Unix(seconds) => SystemTime::UNIX_EPOCH
+                .checked_add(std::time::Duration::from_secs(*seconds))
+                .ok_or(format!("invalid date {}", seconds)),
+            JsonTime::System(time) => Ok(*time),
+        }
+    }
+}
+
+/// A note in the generic JSON schema. Only the text is required, and rarian's own `notes.json`
+/// reads as well.
+#[derive(Debug, Deserialize)]
+struct JsonNote {
+    id: Option<Ulid>,
+    /// Put before the text unless the text starts with it
+    title: Option<String>,
+    #[serde(alias = "body", alias = "content")]
+    text: String,
+    #[serde(default)]
+    links: Vec<JsonLink>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(alias = "creation_date")]
+    created: Option<JsonTime>,
+    #[serde(alias = "updated_date")]
+    updated: Option<JsonTime>,
+    status: Option<String>,
+    done: Option<bool>,
+    due: Option<NaiveDate>,
+    priority: Option<String>,
+    task: Option<Task>,
+}
+
+/// JSON notes, as an array or one object per line
+pub struct JsonImporter;
+
+impl JsonImporter {
+    fn read_note(value: serde_json::Value) -> std::result::Result<Note, String> {
+        let json: JsonNote = serde_json::from_value(value).map_err(|err| err.to_string())?;
+        let mut text = json.text;
+        if let Some(title) = json.title.filter(|title| !text.starts_with(title.as_str())) {
+            text = format!("{}\n\n{}", title, text);
+        }
+        if text.trim().is_empty() {
+            return Err("the note is empty".to_string());
+        }
+        let links = json
+            .links
+            .into_iter()
+            .map(|link| match link {
+                JsonLink::Title(link) | JsonLink::Link { link } => link,
+            })
+            .collect();
+        let mut note = new_note(&with_tags(text, &json.tags), links);
+        if let Some(id) = json.id {
+            note.id = id;
+        }
+        if let Some(created) = json.created {
+            note.creation_date = created.time()?;
+        }
+        note.updated_date = json.updated.map(|updated| updated.time()).transpose()?;
+        if json
+            .status
+            .is_some_and(|status| status.eq_ignore_ascii_case("archived"))
+        {
+            note.status = NoteStatus::Archived;
+        }
+        let priority = json
+            .priority
+            .map(|priority| priority.parse::<Priority>())
+            .transpose()?;
+        note.task = json.task;
+        if json.done.is_some() || json.due.is_some() || priority.is_some() {
+            note.task = Some(Task {
+                done: json.done.unwrap_or_default(),
+                due: json.due,
+                priority,
+            });
+        }
+        Ok(note)
+    }
+}
+
+impl Importer for JsonImporter {
+    fn read(&self, path: &Path) -> Result<Vec<ImportEntry>> {
+        let text = read_file(path)?;
+        let values: Vec<(String, std::result::Result<serde_json::Value, String>)> =
+            match serde_json::from_str::<Vec<serde_json::Value>>(&text) {
+                Ok(values) => values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, value)| (format!("{}[{}]", path.display(), index), Ok(value)))
+                    .collect(),
+                Err(_) => text
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.trim().is_empty())
+                    .map(|(index, line)| {
+                        let value = serde_json::from_str(line).map_err(|err| err.to_string());
+                        (format!("{}:{}", path.display(), index + 1), value)
+                    })
+                    .collect(),
+            };
+        let entries: Vec<ImportEntry> = values
+            .into_iter()
+            .map(|(source, value)| ImportEntry {
+                source,
+                // Rarian's own caches wrap every record in an envelope
+                note: value
+                    .map(|value| Envelope::from_value(value).record)
+                    .and_then(JsonImporter::read_note),
+            })
+            .collect();
+        // Logs like rarian's own notes.json have a line per change, and the last one wins
+        let mut seen = HashSet::new();
+        let mut latest: Vec<ImportEntry> = entries
+            .into_iter()
+            .rev()
+            .filter(|entry| match &entry.note {
+                Ok(note) => seen.insert(note.id),
+                Err(_) => true,
+            })
+            .collect();
+        latest.reverse();
+        Ok(latest)
+    }
+}
+
+/// What an import added, or would add in a dry run, and what it left out
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub imported: Vec<(String, Note)>,
+    /// Entries with the id or the text of an existing note, or of an earlier entry
+    pub duplicates: Vec<(String, Ulid)>,
+    pub failed: Vec<(String, String)>,
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (source, note) in self.imported.iter() {
+            writeln!(f, "import {} from {}", note.title(), source)?;
+        }
+        for (source, id) in self.duplicates.iter() {
+            writeln!(f, "skip {}: duplicate of {}", source, id)?;
+        }
+        for (source, message) in self.failed.iter() {
+            writeln!(f, "fail {}: {}", source, message)?;
+        }
+        writeln!(
+            f,
+            "{} imported, {} duplicates, {} failed",
+            self.imported.len(),
+            self.duplicates.len(),
+            self.failed.len()
+        )
+    }
+}
+
+/// Adds the notes of `entries` that aren't in `note_taker` yet, or only reports what it would add
+/// if `dry_run`. Notes without links are linked to `default_links`.
+pub fn import(
+    note_taker: &mut NoteTaker,
+    entries: Vec<ImportEntry>,
+    default_links: &[String],
+    dry_run: bool,
+) -> Result<ImportReport> {
+    let normalize = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut ids: HashSet<Ulid> = note_taker.notes().map(|note| note.id).collect();
+    let mut texts: HashMap<String, Ulid> = note_taker
+        .notes()
+        .map(|note| (normalize(&note.text), note.id))
+        .collect();
+    let mut report = ImportReport::default();
+    for entry in entries {
+        let mut note = match entry.note {
+            Ok(note) => note,
+            Err(message) => {
+                report.failed.push((entry.source, message));
+                continue;
+            }
+        };
+        if ids.contains(&note.id) {
+            report.duplicates.push((entry.source, note.id));
+            continue;
+        }
+        let text = normalize(&note.text);
+        if let Some(id) = texts.get(&text) {
+            report.duplicates.push((entry.source, *id));
+            continue;
+        }
+        if note.links().is_empty() {
+            for link in default_links {
+                note.add_link(Link::new(link.clone(), 1));
+            }
+        }
+        ids.insert(note.id);
+        texts.insert(text, note.id);
+        if !dry_run {
+            note_taker.insert_note(note.clone())?;
+        }
+        report.imported.push((entry.source, note));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod import_test {
+    use super::*;
+    use crate::export::Vault;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn todo_txt_lines_become_tasks() {
+        let done =
+            TodoTxtImporter::read_line("x 2026-10-02 2026-09-30 pri:A Call Bob +sales").unwrap();
+        assert_eq!(done.text, "Call Bob +sales");
+        assert_eq!(
+            done.task,
+            Some(Task {
+                done: true,
+                due: None,
+                priority: Some(Priority::High)
+            })
+        );
+        let created = NaiveDate::from_ymd_opt(2026, 9, 30).unwrap();
+        assert_eq!(crate::timeline::local_date(done.creation_date), created);
+        let open =
+            TodoTxtImporter::read_line("(C) 2026-10-01 Write report due:2026-10-20 @work").unwrap();
+        assert_eq!(open.text, "Write report @work");
+        assert_eq!(
+            open.task.unwrap().due,
+            NaiveDate::from_ymd_opt(2026, 10, 20)
+        );
+        assert!(TodoTxtImporter::read_line("(A) due:soon").is_err());
+    }
+
+    #[test]
+    fn exported_vaults_import_back_without_duplicates() {
+        let data_path = PathBuf::from("./testData/import");
+        let _ = remove_dir_all(&data_path);
+        let vault_path = data_path.join("vault");
+        create_dir_all(data_path.join("source")).unwrap();
+        create_dir_all(data_path.join("target")).unwrap();
+        let mut note_taker = NoteTaker::new(&data_path.join("source")).unwrap();
+        let design = note_taker
+            .add_note("Parser design\n#rust", vec!["Code".to_string()])
+            .unwrap();
+        note_taker
+            .add_note("Review [[Parser design]]", vec!["Code".to_string()])
+            .unwrap();
+        note_taker.archive_note(&design).unwrap();
+        Vault::from_notes(&note_taker).write(&vault_path).unwrap();
+        write(
+            vault_path.join("Meeting.md"),
+            "---\ntags: [team, weekly]\ndate: 2026-10-01\n---\nAgenda",
+        )
+        .unwrap();
+        write(vault_path.join("Binary.md"), [0xff, 0xfe, 0x00]).unwrap();
+
+        let mut imported = NoteTaker::new(&data_path.join("target")).unwrap();
+        let entries = MarkdownImporter.read(&vault_path).unwrap();
+        let dry_run = import(&mut imported, entries.clone(), &[], true).unwrap();
+        assert_eq!(dry_run.imported.len(), 3);
+        assert_eq!(dry_run.failed.len(), 1);
+        assert!(dry_run.failed[0].0.ends_with("Binary.md"));
+        assert_eq!(imported.notes().count(), 0);
+        let report = import(&mut imported, entries.clone(), &[], false).unwrap();
+        let again = import(&mut imported, entries, &[], false).unwrap();
+        remove_dir_all(&data_path).unwrap();
+
+        assert_eq!(report.imported.len(), 3);
+        assert_eq!(again.duplicates.len(), 3);
+        let restored = imported.get_note(&design).unwrap();
+        let original = note_taker.get_note(&design).unwrap();
+        assert_eq!(restored.text, original.text);
+        assert_eq!(restored.status, NoteStatus::Archived);
+        assert_eq!(restored.links()[0].link, "Code");
+        let meeting = imported
+            .notes()
+            .find(|note| note.title() == "Meeting")
+            .unwrap();
+        assert_eq!(meeting.text, "Meeting\n\nAgenda\n\n#team #weekly");
+        assert!(meeting.links()[0].link.ends_with("Meeting.md"));
+    }
+
+    #[test]
+    fn json_notes_are_read_and_the_ones_that_cant_be_are_reported() {
+        let data_path = PathBuf::from("./testData/import_json");
+        let _ = remove_dir_all(&data_path);
+        create_dir_all(&data_path).unwrap();
+        let array = data_path.join("notes.json");
+        write(
+            &array,
+            r#"[
+                {"title": "Groceries", "body": "milk", "tags": ["home"], "links": ["Shop"],
+                 "created": 1700000000, "due": "2026-10-20", "priority": "high"},
+                {"text": "hi", "created": 18446744073709551615},
+                {"text": "  "},
+                {"links": ["Shop"]}
+            ]"#,
+        )
+        .unwrap();
+        let id = Ulid::new();
+        let lines = data_path.join("notes.jsonl");
+        write(
+            &lines,
+            format!(
+                "{{\"id\": \"{id}\", \"text\": \"first\"}}\n\nnot json\n{{\"id\": \"{id}\", \"text\": \"second\", \"status\": \"archived\"}}\n"
+            ),
+        )
+        .unwrap();
+
+        let from_array = JsonImporter.read(&array).unwrap();
+        let from_lines = JsonImporter.read(&lines).unwrap();
+        remove_dir_all(&data_path).unwrap();
+
+        let groceries = from_array[0].note.as_ref().unwrap();
+        assert_eq!(groceries.text, "Groceries\n\nmilk\n\n#home");
+        assert_eq!(groceries.links()[0].link, "Shop");
+        assert_eq!(
+            groceries.creation_date,
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1700000000)
+        );
+        let task = groceries.task.clone().unwrap();
+        assert_eq!(task.due, NaiveDate::from_ymd_opt(2026, 10, 20));
+        assert_eq!(task.priority, Some(Priority::High));
+        assert_eq!(
+            from_array[1].note.as_ref().unwrap_err(),
+            "invalid date 18446744073709551615"
+        );
+        assert_eq!(
+            from_array[2].note.as_ref().unwrap_err(),
+            "the note is empty"
+        );
+        assert!(from_array[3].note.is_err());
+        assert!(from_array[3].source.ends_with("notes.json[3]"));
+
+        assert_eq!(from_lines.len(), 2);
+        assert!(from_lines[0].source.ends_with("notes.jsonl:3"));
+        assert!(from_lines[0].note.is_err());
+        let latest = from_lines[1].note.as_ref().unwrap();
+        assert_eq!((latest.id, latest.text.as_str()), (id, "second"));
+        assert_eq!(latest.status, NoteStatus::Archived);
+    }
+}
@@ -0,0 +1,66 @@
+//! Rarian gathers the apps you focus and the files you change, and links your notes to them.
+//!
+//! [`Rarian`] starts the gatherers and owns the note store on a service thread. Frontends make
+//! typed requests on its [`Bus`] and subscribe to its [`Notification`]s:
+//!
+//! ```no_run
+//! use rarian::bus::{AddNote, AppNotes};
+//! use rarian::{Config, Rarian};
+//!
+//! let config = Config::new(None).unwrap();
+//! let rarian = Rarian::start(&config).unwrap();
+//! let bus = rarian.bus();
+//! let notifications = bus.subscribe().unwrap();
+//! let id = bus
+//!     .request(AddNote {
+//!         text: "check the logs".to_string(),
+//!         links: vec!["terminal".to_string()],
+//!     })
+//!     .unwrap();
+//! let link = "terminal".to_string();
+//! let notes = bus.request(AppNotes { link }).unwrap();
+//! assert!(notes.iter().any(|note| note.id == id));
+//! rarian.stop();
+//! ```
+//!
+//! The note store and the logs can also be used directly, without starting the gatherers, see
+//! [`NoteTaker`], [`Timeline`] and [`Digest`].
+
+pub mod backup;
+pub mod bus;
+pub mod cacher;
+pub mod config;
+pub mod crypto;
+pub mod digest;
+pub mod error;
+pub mod export;
+pub mod gatherer;
+pub mod graph;
+pub mod import;
+pub mod lock;
+pub mod notes;
+pub mod oplog;
+pub mod relevance;
+pub mod reminders;
+pub mod retention;
+pub mod schema;
+pub mod service;
+pub mod supervisor;
+pub mod sync;
+pub mod tasks;
+pub mod timeline;
+
+pub use bus::{Bus, Middleware, Notification, Request};
+pub use config::Config;
+pub use digest::{Digest, DigestPeriod};
+pub use error::{RarianError, Result};
+pub use export::Vault;
+pub use gatherer::app_gatherer::{ActiveProcessEvent, AppGatherer};
+pub use gatherer::file_gatherer::{FileEvent, FileGatherer};
+pub use graph::{Graph, GraphFilter, GraphFormat};
+pub use notes::{Note, NoteStatus, NoteTaker};
+pub use relevance::NoteContext;
+pub use reminders::{Notifier, Reminder, Trigger};
+pub use service::Rarian;
+pub use tasks::{Priority, Task, TaskFilter};
+pub use timeline::{Timeline, TimelineEntry, TimelineKind};
@@ -0,0 +1,98 @@
+use crate::error::{RarianError, Result};
+use std::collections::HashMap;
+use std::fs::{read_to_string, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+pub const LOCK_FILE: &str = "rarian.lock";
+
+/// The data paths this process holds the lock of, so a frontend holding it can start the service
+/// on the same data path
+static HELD: Mutex<Option<HashMap<PathBuf, Weak<File>>>> = Mutex::new(None);
+
+/// An advisory lock on a data path, held by the one rarian that writes to it until dropped. Other
+/// processes can still read the caches, which lock every read and write on their own.
+#[derive(Debug, Clone)]
+pub struct DataLock {
+    _file: Arc<File>,
+}
+
+impl DataLock {
+    /// Locks `data_path`, failing with `InUse` if another process holds it
+    pub fn acquire(data_path: &Path) -> Result<DataLock> {
+        let path = data_path.join(LOCK_FILE);
+        let key = data_path
+            .canonicalize()
+            .unwrap_or_else(|_| data_path.to_path_buf());
+        let mut held = HELD.lock().unwrap_or_else(PoisonError::into_inner);
+        let held = held.get_or_insert_with(HashMap::new);
+        if let Some(file) = held.get(&key).and_then(Weak::upgrade) {
+            return Ok(DataLock { _file: file });
+        }
+        let open_error = |err: std::io::Error| RarianError::OpenCache {
+            path: path.clone(),
+            message: err.to_string(),
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(open_error)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(RarianError::InUse {
+                    path: data_path.to_path_buf(),
+                    holder: read_to_string(&path).unwrap_or_default().trim().to_string(),
+                })
+            }
+            Err(TryLockError::Error(err)) => return Err(open_error(err)),
+        }
+        // The process id tells whoever finds the data path locked which rarian holds it
+        file.set_len(0).map_err(open_error)?;
+        file.write_all(std::process::id().to_string().as_bytes())
+            .map_err(open_error)?;
+        let file = Arc::new(file);
+        held.insert(key, Arc::downgrade(&file));
+        Ok(DataLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod lock_test {
+    use super::*;
+
+    #[test]
+    fn only_one_process_holds_the_lock_of_a_data_path() {
+        let data_path = PathBuf::from("./testData/lock");
+        let _ = std::fs::remove_dir_all(&data_path);
+        std::fs::create_dir_all(&data_path).unwrap();
+        let lock = DataLock::acquire(&data_path).unwrap();
+        let shared = DataLock::acquire(&data_path);
+        // Another process opens the lock file on its own
+        let other = File::open(data_path.join(LOCK_FILE)).unwrap();
+        let locked_by_other = other.try_lock().is_ok();
+        drop(lock);
+        let still_locked = other.try_lock().is_err();
+        drop(shared);
+        let released = other.try_lock().is_ok();
+        let in_use = DataLock::acquire(&data_path);
+        drop(other);
+        let reacquired = DataLock::acquire(&data_path).is_ok();
+        std::fs::remove_dir_all(&data_path).unwrap();
+
+        assert!(!locked_by_other);
+        assert!(still_locked);
+        assert!(released);
+        match in_use {
+            Err(RarianError::InUse { holder, .. }) => {
+                assert_eq!(holder, std::process::id().to_string())
+            }
+            other => panic!("expected the data path in use, got {:?}", other),
+        }
+        assert!(reacquired);
+    }
+}
@@ -1,66 +1,54 @@
-use std::{
-    sync::mpsc::{channel, Sender},
-    thread::spawn,
-};
 mod app;
-mod cacher;
-mod config;
-mod gatherer;
-mod notes;
+mod cli;
+mod external_editor;
+mod passphrase;
 
 use crate::app::tui::run_app;
-use crate::gatherer::app_gatherer::AppGatherer;
-use crate::gatherer::file_gatherer::FileGatherer;
-use config::Config;
-use gatherer::app_gatherer::ActiveProcessEvent;
-use notes::{Note, NoteTaker};
-use ulid::Ulid;
-
-pub enum StateMachine {
-    RecentApps(usize, Sender<Vec<ActiveProcessEvent>>),
-    CurrentApp(Sender<Option<ActiveProcessEvent>>),
-    GetAppNotes(String, Sender<Vec<Note>>),
-    NewNote(String, Vec<String>),
-    ArchiveNote(Ulid),
-    EditNote(Ulid, String),
-    Quit,
-}
+use clap::Parser;
+use cli::{run_command, Args, Command};
+use passphrase::read_passphrase;
+use rarian::crypto::{is_encrypted, unlock, unlock_reading};
+use rarian::lock::DataLock;
+use rarian::{Config, Rarian};
 
 fn main() {
-    change_window_title();
-    let config = Config::new();
-    let app_gatherer = AppGatherer::new(&config);
-    let mut note_taker = NoteTaker::new(config.data_path.as_path());
-    let (action_tx, action_rx) = channel::<StateMachine>();
-    let file_gatherer = FileGatherer::new(action_tx.clone(), &config);
-    let app_thread = spawn(move || {
-        run_app(config, action_tx.clone());
-    });
+    if let Err(err) = run() {
+        eprintln!("rarian: {:#}", err);
+        std::process::exit(1);
+    }
+}
 
-    use StateMachine::*;
-    loop {
-        match action_rx.recv() {
-            Ok(RecentApps(n, tx)) => {
-                let _ = tx.send(app_gatherer.get_last_processes(n));
-            }
-            Ok(CurrentApp(tx)) => {
-                let _ = tx.send(app_gatherer.get_current());
-            }
-            Ok(GetAppNotes(link, tx)) => {
-                let _ = tx.send(note_taker.get_app_notes(&link));
-            }
-            Ok(NewNote(text, links)) => note_taker.add_note(&text, links),
-            Ok(ArchiveNote(note_id)) => note_taker.archive_note(&note_id),
-            Ok(EditNote(note_id, text)) => note_taker.edit_note(&note_id, &text),
-            Ok(Quit) => break,
-            Err(err) => {
-                println!("action error: {}", err);
+fn run() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let config = Config::new(args.data_path)?;
+    let writes = args.command.as_ref().is_none_or(Command::writes);
+    // Commands that only read go ahead while another rarian holds the data path
+    let lock = match writes {
+        true => Some(DataLock::acquire(&config.data_path)?),
+        false => DataLock::acquire(&config.data_path).ok(),
+    };
+    if is_encrypted(&config.data_path) {
+        match lock {
+            Some(_) => {
+                let quarantined = unlock(&config.data_path, &read_passphrase()?)?;
+                if quarantined > 0 {
+                    eprintln!(
+                        "{} records couldn't be decrypted and were moved to the quarantine folder",
+                        quarantined
+                    );
+                }
             }
+            None => unlock_reading(&config.data_path, &read_passphrase()?)?,
         }
     }
-    app_gatherer.close();
-    file_gatherer.close();
-    app_thread.join().unwrap();
+    if let Some(command) = args.command {
+        return run_command(command, &config);
+    }
+    change_window_title();
+    let rarian = Rarian::start(&config)?;
+    let result = run_app(config, rarian.bus());
+    rarian.stop();
+    Ok(result?)
 }
 
 fn change_window_title() {
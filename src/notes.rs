@@ -1,15 +1,31 @@
 use crate::cacher::{Cache, FileCacher, LoadFromCache};
+use crate::error::{RarianError, Result};
+use crate::oplog::{Op, Replica, Transport};
+use crate::relevance::{relevance, LinkProjects, NoteContext};
+use crate::reminders::Reminder;
+use crate::tasks::{checklist, task_order, toggle_checklist_item, ChecklistItem, Task, TaskFilter};
 use itertools::Itertools;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
 use std::{
+    cmp::Reverse,
     collections::HashMap,
+    fmt::Display,
     path::{Path, PathBuf},
+    sync::LazyLock,
     time::SystemTime,
 };
 use ulid::Ulid;
 
+/// A `[[note title or ulid]]` reference, with what is between the brackets
+pub(crate) static REFERENCE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[\[([^\[\]]+)\]\]").unwrap());
+/// How much editing a note adds to the weight of its links
+const EDIT_BOOST: usize = 1;
+static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|\s)#([\w-]+)").unwrap());
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-enum NoteStatus {
+pub enum NoteStatus {
     Active,
     Archived,
 }
@@ -18,11 +34,61 @@ enum NoteStatus {
 pub struct Link {
     pub link: String,
     pub weight: usize,
+    /// Whether the note stays at the top of the notes of this link
+    #[serde(default)]
+    pub pinned: bool,
+    /// Where the note was moved to among the notes of this link, if it was
+    #[serde(default)]
+    pub position: Option<usize>,
 }
 
 impl Link {
     pub fn new(link: String, weight: usize) -> Link {
-        Link { link, weight }
+        Link {
+            link,
+            weight,
+            pinned: false,
+            position: None,
+        }
+    }
+}
+
+/// How the notes of a link are ordered, after the pinned ones
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NoteSort {
+    #[default]
+    Relevance,
+    /// The order the notes were moved into, with the ones never moved last
+    Manual,
+    /// Newest first
+    Created,
+    /// Most recently edited first
+    Updated,
+    Alphabetical,
+}
+
+impl NoteSort {
+    /// The next sort mode, for cycling through them with a single key
+    pub fn next(self) -> NoteSort {
+        match self {
+            NoteSort::Relevance => NoteSort::Manual,
+            NoteSort::Manual => NoteSort::Created,
+            NoteSort::Created => NoteSort::Updated,
+            NoteSort::Updated => NoteSort::Alphabetical,
+            NoteSort::Alphabetical => NoteSort::Relevance,
+        }
+    }
+}
+
+impl Display for NoteSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteSort::Relevance => write!(f, "relevance"),
+            NoteSort::Manual => write!(f, "manual"),
+            NoteSort::Created => write!(f, "created"),
+            NoteSort::Updated => write!(f, "updated"),
+            NoteSort::Alphabetical => write!(f, "alphabetical"),
+        }
     }
 }
 
@@ -31,8 +97,18 @@ pub struct Note {
     pub id: Ulid,
     links: Vec<Link>,
     pub text: String,
-    creation_date: SystemTime,
-    status: NoteStatus,
+    pub creation_date: SystemTime,
+    #[serde(default)]
+    pub updated_date: Option<SystemTime>,
+    pub status: NoteStatus,
+    /// Set if the note is a task
+    #[serde(default)]
+    pub task: Option<Task>,
+    #[serde(default)]
+    pub reminder: Option<Reminder>,
+    /// The text of the other version when a sync merged two edits of the note, until it is edited
+    #[serde(default)]
+    pub conflict: Option<String>,
 }
 
 impl Note {
@@ -42,35 +118,209 @@ impl Note {
             links,
             text: text.to_string(),
             creation_date: SystemTime::now(),
+            updated_date: None,
             status: NoteStatus::Active,
+            task: None,
+            reminder: None,
+            conflict: None,
         }
     }
+
+    /// The apps and files the note was written in
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    pub(crate) fn set_links(&mut self, links: Vec<Link>) {
+        self.links = links;
+    }
+
+    pub(crate) fn add_link(&mut self, link: Link) {
+        self.links.push(link);
+    }
+
+    /// The link of the note to `link`, if it has one
+    pub fn link_to(&self, link: &str) -> Option<&Link> {
+        self.links.iter().find(|l| l.link == link)
+    }
+
+    /// The first line of the text, which `[[title]]` references match
+    pub fn title(&self) -> &str {
+        self.text
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The `[[note title or ulid]]` references in the text, in order and without repeats
+    pub fn references(&self) -> Vec<String> {
+        REFERENCE_REGEX
+            .captures_iter(&self.text)
+            .map(|captures| captures[1].trim().to_string())
+            .filter(|reference| !reference.is_empty())
+            .unique()
+            .collect()
+    }
+
+    /// Whether the note has a reminder that became due and wasn't dismissed yet
+    pub fn reminder_is_due(&self) -> bool {
+        self.reminder
+            .as_ref()
+            .is_some_and(|reminder| reminder.is_due())
+    }
+
+    /// The `- [ ]` items in the text
+    pub fn checklist(&self) -> Vec<ChecklistItem> {
+        checklist(&self.text)
+    }
+
+    /// The `#tags` in the text, lowercased and without repeats
+    pub fn tags(&self) -> Vec<String> {
+        TAG_REGEX
+            .captures_iter(&self.text)
+            .map(|captures| captures[1].to_lowercase())
+            .unique()
+            .collect()
+    }
+}
+
+/// A `[[reference]]` in a note and the note it currently resolves to, if any
+#[derive(Debug, Clone)]
+pub struct NoteReference {
+    pub reference: String,
+    pub target: Option<Note>,
+}
+
+/// The notes a note references and the notes that reference it
+#[derive(Debug, Clone)]
+pub struct NoteLinks {
+    pub note: Note,
+    pub outgoing: Vec<NoteReference>,
+    pub backlinks: Vec<Note>,
 }
 
 pub struct NoteTaker {
     cacher: FileCacher,
     notes: HashMap<Ulid, Note>,
+    replica: Replica,
 }
 
 impl NoteTaker {
-    pub fn new(data_path: &Path) -> Self {
+    pub fn new(data_path: &Path) -> Result<Self> {
+        let mut replica = Replica::new(data_path)?;
         let data_path: PathBuf = PathBuf::from(data_path).join("notes.json");
-        let mut cacher = FileCacher::new(data_path);
+        let mut cacher = FileCacher::new(data_path)?;
         let notes_from_cache: Vec<Note> = cacher.load_from_cache();
         let notes: HashMap<Ulid, Note> = notes_from_cache
             .into_iter()
             .sorted_by(|a, b| a.creation_date.cmp(&b.creation_date))
             .map(|note| (note.id, note))
             .collect();
-        let note_taker = NoteTaker { cacher, notes };
-        return note_taker;
+        // Stores from before the op log start it with the notes they have
+        if replica.is_empty() {
+            for note in notes
+                .values()
+                .sorted_by_key(|note| (note.creation_date, note.id))
+            {
+                replica.record_change(None, note)?;
+            }
+        }
+        let note_taker = NoteTaker {
+            cacher,
+            notes,
+            replica,
+        };
+        Ok(note_taker)
+    }
+
+    /// Stores a new or changed note, and records what changed in the op log
+    fn save(&mut self, note: Note) -> Result<()> {
+        self.replica
+            .record_change(self.notes.get(&note.id), &note)?;
+        self.cacher.cache(&note)?;
+        self.notes.insert(note.id, note);
+        Ok(())
     }
 
-    pub fn add_note(&mut self, text: &str, links: Vec<String>) {
+    /// The lines of the notes and the op log that couldn't be read when they were loaded
+    pub fn unreadable(&self) -> Vec<RarianError> {
+        [self.cacher.unreadable(), self.replica.unreadable()]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The op log that replicates the notes to other devices
+    pub fn replica(&self) -> &Replica {
+        &self.replica
+    }
+
+    /// Adds the ops of other devices to the op log and applies them to the notes, returning the
+    /// notes they changed
+    pub fn merge_ops(&mut self, ops: Vec<Op>) -> Result<Vec<Ulid>> {
+        let changed = self.replica.merge(ops)?;
+        let mut updated = Vec::new();
+        for id in changed.into_iter().sorted() {
+            let Some(state) = self.replica.state(&id) else {
+                continue;
+            };
+            let mut note = match self.notes.get(&id) {
+                Some(note) => note.clone(),
+                None => {
+                    let mut note = Note::new(&state.text, Vec::new());
+                    note.id = id;
+                    note.creation_date = state.creation_date;
+                    note
+                }
+            };
+            let links = state
+                .links
+                .iter()
+                .map(|link| match note.link_to(link) {
+                    Some(existing) => existing.clone(),
+                    None => Link::new(link.clone(), 1),
+                })
+                .collect();
+            note.set_links(links);
+            note.text = state.text;
+            note.status = state.status;
+            note.updated_date = note.updated_date.max(state.updated_date);
+            // The ops are in the log already, only the note changes
+            self.cacher.cache(&note)?;
+            self.notes.insert(note.id, note);
+            updated.push(id);
+        }
+        Ok(updated)
+    }
+
+    /// Exchanges ops with the other devices over `transport` and applies theirs
+    pub fn replicate(&mut self, transport: &mut impl Transport) -> Result<Vec<Ulid>> {
+        let ops: Vec<Op> = self.replica.ops().cloned().collect();
+        let received = transport.exchange(&ops)?;
+        self.merge_ops(received)
+    }
+
+    pub fn add_note(&mut self, text: &str, links: Vec<String>) -> Result<Ulid> {
         let links = links.into_iter().map(|l| Link::new(l, 1)).collect();
         let note = Note::new(text, links);
-        self.cacher.cache(&note).expect("cache event failed");
-        self.notes.insert(note.id, note);
+        let note_id = note.id;
+        self.save(note)?;
+        Ok(note_id)
+    }
+
+    /// Adds a note made elsewhere, like an imported one, keeping its id and dates
+    pub fn insert_note(&mut self, note: Note) -> Result<()> {
+        self.save(note)
+    }
+
+    pub fn get_note(&self, note_id: &Ulid) -> Option<&Note> {
+        self.notes.get(note_id)
+    }
+
+    /// Every note, including the archived ones, in no particular order
+    pub fn notes(&self) -> impl Iterator<Item = &Note> {
+        self.notes.values()
     }
 
     pub fn get_app_notes(&self, link: &str) -> Vec<Note> {
@@ -86,28 +336,513 @@ impl NoteTaker {
         notes_vec
     }
 
-    pub fn archive_note(&mut self, note_id: &Ulid) {
+    /// The active notes related to `context`, most relevant first. Notes linked to the app or file
+    /// of the context itself come before notes from similar contexts. Snoozed notes are left out,
+    /// and notes with a due reminder resurface in every context.
+    pub fn related_notes(&self, context: &NoteContext) -> Vec<Note> {
+        let mut link_projects = LinkProjects::new();
+        self.notes
+            .values()
+            .filter(|note| note.status == NoteStatus::Active)
+            .filter(|note| !note.reminder.as_ref().is_some_and(|r| r.is_snoozed()))
+            .map(|note| (relevance(note, context, &mut link_projects), note))
+            .filter(|(score, note)| *score > 0.0 || note.reminder_is_due())
+            .sorted_by(|(a_score, a), (b_score, b)| {
+                let a_exact = a.links.iter().any(|l| context.matches_exactly(&l.link));
+                let b_exact = b.links.iter().any(|l| context.matches_exactly(&l.link));
+                b_exact
+                    .cmp(&a_exact)
+                    .then(b_score.total_cmp(a_score))
+                    .then(b.creation_date.cmp(&a.creation_date))
+            })
+            .map(|(_, note)| note.clone())
+            .collect()
+    }
+
+    /// The notes related to `context` in the order of `sort`, with due reminders and then the
+    /// notes pinned to its app first, and the notes of similar contexts last
+    pub fn ordered_notes(&self, context: &NoteContext, sort: NoteSort) -> Vec<Note> {
+        let link = context.app_title.as_deref().unwrap_or_default();
+        let mut notes = self.related_notes(context);
+        // The sorts are stable, so ties keep the order of relevance
+        match sort {
+            NoteSort::Relevance => {}
+            NoteSort::Manual => notes.sort_by_key(|note| {
+                note.link_to(link)
+                    .and_then(|l| l.position)
+                    .unwrap_or(usize::MAX)
+            }),
+            NoteSort::Created => notes.sort_by_key(|note| Reverse(note.creation_date)),
+            NoteSort::Updated => {
+                notes.sort_by_key(|note| Reverse(note.updated_date.unwrap_or(note.creation_date)))
+            }
+            NoteSort::Alphabetical => notes.sort_by_key(|note| note.title().to_lowercase()),
+        }
+        notes.sort_by_key(|note| match note.link_to(link) {
+            _ if note.reminder_is_due() => 0,
+            Some(l) if l.pinned => 1,
+            Some(_) => 2,
+            None => 3,
+        });
+        notes
+    }
+
+    /// Changes the link of a note to `link`, linking it if it wasn't yet
+    fn update_link(
+        &mut self,
+        note_id: &Ulid,
+        link: &str,
+        update: impl FnOnce(&mut Link),
+    ) -> Result<()> {
+        let mut note = self
+            .notes
+            .get(note_id)
+            .ok_or(RarianError::NoteNotFound(*note_id))?
+            .clone();
+        if note.link_to(link).is_none() {
+            note.links.push(Link::new(link.to_string(), 1));
+        }
+        if let Some(existing) = note.links.iter_mut().find(|l| l.link == link) {
+            update(existing);
+        }
+        self.save(note)?;
+        Ok(())
+    }
+
+    /// Adds `amount` to the weight of the link of a note to `link`, linking it if it wasn't yet
+    pub fn boost_link(&mut self, note_id: &Ulid, link: &str, amount: usize) -> Result<()> {
+        self.update_link(note_id, link, |l| l.weight += amount)
+    }
+
+    /// Pins a note to the top of the notes of `link`, or unpins it
+    pub fn set_pinned(&mut self, note_id: &Ulid, link: &str, pinned: bool) -> Result<()> {
+        self.update_link(note_id, link, |l| l.pinned = pinned)
+    }
+
+    /// Moves the notes of `link` into the order of `order`, only saving the notes that moved
+    pub fn reorder(&mut self, link: &str, order: &[Ulid]) -> Result<()> {
+        for (position, note_id) in order.iter().enumerate() {
+            let note = self
+                .notes
+                .get(note_id)
+                .ok_or(RarianError::NoteNotFound(*note_id))?;
+            if note.link_to(link).and_then(|l| l.position) != Some(position) {
+                self.update_link(note_id, link, |l| l.position = Some(position))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The active task notes matching `filter`, open ones first and then by due date and priority
+    pub fn tasks(&self, filter: &TaskFilter) -> Vec<Note> {
+        self.notes
+            .values()
+            .filter(|note| note.status == NoteStatus::Active && filter.matches(note))
+            .sorted_by(|a, b| task_order(a, b))
+            .cloned()
+            .collect()
+    }
+
+    /// Makes a note a task, changes it, or makes it a plain note again with `None`
+    pub fn set_task(&mut self, note_id: &Ulid, task: Option<Task>) -> Result<()> {
+        let mut note = self
+            .notes
+            .get(note_id)
+            .ok_or(RarianError::NoteNotFound(*note_id))?
+            .clone();
+        note.task = task;
+        self.save(note)?;
+        Ok(())
+    }
+
+    /// Sets the reminder of a note, or dismisses it with `None`
+    pub fn set_reminder(&mut self, note_id: &Ulid, reminder: Option<Reminder>) -> Result<()> {
+        let mut note = self
+            .notes
+            .get(note_id)
+            .ok_or(RarianError::NoteNotFound(*note_id))?
+            .clone();
+        note.reminder = reminder;
+        self.save(note)?;
+        Ok(())
+    }
+
+    /// Makes the reminders that should fire at `now`, or in `context` if the focus just changed,
+    /// due and returns their notes
+    pub fn fire_reminders(
+        &mut self,
+        now: SystemTime,
+        context: Option<&NoteContext>,
+    ) -> Result<Vec<Note>> {
+        let firing: Vec<Note> = self
+            .notes
+            .values()
+            .filter(|note| note.status == NoteStatus::Active)
+            .filter(|note| {
+                note.reminder
+                    .as_ref()
+                    .is_some_and(|reminder| reminder.should_fire(now, context))
+            })
+            .cloned()
+            .collect();
+        let mut fired = Vec::new();
+        for mut note in firing {
+            if let Some(reminder) = note.reminder.as_mut() {
+                reminder.fired = Some(now);
+            }
+            self.save(note.clone())?;
+            fired.push(note);
+        }
+        Ok(fired)
+    }
+
+    /// Checks or unchecks the `- [ ]` item at `index` of a note, which is an edit of its text
+    pub fn toggle_checklist_item(&mut self, note_id: &Ulid, index: usize) -> Result<()> {
+        let note = self
+            .notes
+            .get(note_id)
+            .ok_or(RarianError::NoteNotFound(*note_id))?;
+        match toggle_checklist_item(&note.text, index) {
+            Some(text) => self.edit_note(note_id, &text),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves a reference by ulid, otherwise by title. Of several notes with the same title the
+    /// newest active one wins.
+    pub fn resolve(&self, reference: &str) -> Option<&Note> {
+        if let Some(note) = Ulid::from_string(reference)
+            .ok()
+            .and_then(|id| self.notes.get(&id))
+        {
+            return Some(note);
+        }
+        self.notes
+            .values()
+            .filter(|note| note.title().eq_ignore_ascii_case(reference))
+            .max_by_key(|note| (note.status == NoteStatus::Active, note.creation_date))
+    }
+
+    /// The active notes with a reference to `note_id`, newest first
+    pub fn backlinks(&self, note_id: &Ulid) -> Vec<Note> {
+        let mut backlinks: Vec<Note> = self
+            .notes
+            .values()
+            .filter(|note| note.id != *note_id && note.status == NoteStatus::Active)
+            .filter(|note| {
+                note.references().iter().any(|reference| {
+                    self.resolve(reference).map(|target| target.id) == Some(*note_id)
+                })
+            })
+            .cloned()
+            .collect();
+        backlinks.sort_by_key(|note| Reverse(note.creation_date));
+        backlinks
+    }
+
+    pub fn note_links(&self, note_id: &Ulid) -> Result<NoteLinks> {
+        let note = self
+            .notes
+            .get(note_id)
+            .ok_or(RarianError::NoteNotFound(*note_id))?;
+        let outgoing = note
+            .references()
+            .into_iter()
+            .map(|reference| NoteReference {
+                target: self.resolve(&reference).cloned(),
+                reference,
+            })
+            .collect();
+        Ok(NoteLinks {
+            note: note.clone(),
+            outgoing,
+            backlinks: self.backlinks(note_id),
+        })
+    }
+
+    pub fn archive_note(&mut self, note_id: &Ulid) -> Result<()> {
         match self.notes.get(note_id) {
             Some(note) => {
                 let mut note = note.to_owned();
                 note.status = NoteStatus::Archived;
-                self.cacher.cache(&note).expect("cache event failed");
-                self.notes.insert(note.id, note);
+                note.updated_date = Some(SystemTime::now());
+                self.save(note)?;
+                Ok(())
             }
-            None => println!("could not find note with id {}", note_id),
+            None => Err(RarianError::NoteNotFound(*note_id)),
         }
     }
 
-    pub fn edit_note(&mut self, note_id: &Ulid, text: &str) {
+    pub fn edit_note(&mut self, note_id: &Ulid, text: &str) -> Result<()> {
         match self.notes.get(note_id) {
             Some(note) => {
+                let old_title = note.title().to_string();
                 let mut note = note.to_owned();
                 note.text = text.to_string();
-                note.creation_date = SystemTime::now();
-                self.cacher.cache(&note).expect("cache event failed");
-                self.notes.insert(note.id, note);
+                note.conflict = None;
+                for link in note.links.iter_mut() {
+                    link.weight += EDIT_BOOST;
+                }
+                note.updated_date = Some(SystemTime::now());
+                let referencing = match old_title != note.title() {
+                    true => self.referencing_by_title(note_id, &old_title),
+                    false => Vec::new(),
+                };
+                let new_title = note.title().to_string();
+                self.save(note)?;
+                self.rename_references(referencing, &old_title, &new_title)
             }
-            None => println!("could not find note with id {}", note_id),
+            None => Err(RarianError::NoteNotFound(*note_id)),
         }
     }
+
+    /// The notes that reference `note_id` by its title rather than its ulid
+    fn referencing_by_title(&self, note_id: &Ulid, title: &str) -> Vec<Ulid> {
+        self.notes
+            .values()
+            .filter(|note| note.id != *note_id)
+            .filter(|note| {
+                note.references().iter().any(|reference| {
+                    reference.eq_ignore_ascii_case(title)
+                        && self.resolve(reference).map(|target| target.id) == Some(*note_id)
+                })
+            })
+            .map(|note| note.id)
+            .collect()
+    }
+
+    /// Points the `[[old title]]` references of renamed notes at the new title, so they keep
+    /// resolving. References to a note whose title was removed are left to show as missing.
+    fn rename_references(
+        &mut self,
+        referencing: Vec<Ulid>,
+        old_title: &str,
+        new_title: &str,
+    ) -> Result<()> {
+        if new_title.is_empty() {
+            return Ok(());
+        }
+        for id in referencing {
+            let Some(note) = self.notes.get(&id) else {
+                continue;
+            };
+            let mut note = note.clone();
+            note.text = REFERENCE_REGEX
+                .replace_all(&note.text, |captures: &Captures| {
+                    match captures[1].trim().eq_ignore_ascii_case(old_title) {
+                        true => format!("[[{}]]", new_title),
+                        false => captures[0].to_string(),
+                    }
+                })
+                .to_string();
+            note.updated_date = Some(SystemTime::now());
+            self.save(note)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod notes_test {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    fn note_taker(test_id: &str) -> (PathBuf, NoteTaker) {
+        let path = PathBuf::from("./testData").join(test_id);
+        create_dir_all(&path).unwrap();
+        let note_taker = NoteTaker::new(&path).unwrap();
+        (path, note_taker)
+    }
+
+    #[test]
+    fn references_are_parsed_from_the_text() {
+        let note = Note::new(
+            "see [[Deploy steps]] and [[ 01J0 ]], [[Deploy steps]] [[]]",
+            vec![],
+        );
+        assert_eq!(note.references(), vec!["Deploy steps", "01J0"]);
+    }
+
+    #[test]
+    fn references_resolve_by_title_or_ulid_into_backlinks() {
+        let (path, mut note_taker) = note_taker("notes_backlinks");
+        let target = note_taker
+            .add_note("Deploy steps\nrun make", vec![])
+            .unwrap();
+        let by_title = note_taker.add_note("see [[deploy steps]]", vec![]).unwrap();
+        let by_id = note_taker
+            .add_note(&format!("see [[{}]] and [[nothing]]", target), vec![])
+            .unwrap();
+        let links = note_taker.note_links(&by_id).unwrap();
+        remove_dir_all(&path).unwrap();
+
+        let targets: Vec<Option<Ulid>> = links
+            .outgoing
+            .iter()
+            .map(|reference| reference.target.as_ref().map(|note| note.id))
+            .collect();
+        assert_eq!(targets, vec![Some(target), None]);
+        let backlinks: Vec<Ulid> = note_taker
+            .backlinks(&target)
+            .iter()
+            .map(|note| note.id)
+            .collect();
+        assert_eq!(backlinks.len(), 2);
+        assert!(backlinks.contains(&by_title) && backlinks.contains(&by_id));
+    }
+
+    #[test]
+    fn renaming_a_note_updates_the_references_to_its_title() {
+        let (path, mut note_taker) = note_taker("notes_rename");
+        let target = note_taker.add_note("Old title", vec![]).unwrap();
+        let referencing = note_taker
+            .add_note("see [[old title]] and [[Other]]", vec![])
+            .unwrap();
+        note_taker.edit_note(&target, "New title\nmore").unwrap();
+        note_taker.archive_note(&target).unwrap();
+        let reloaded = NoteTaker::new(&path).unwrap();
+        remove_dir_all(&path).unwrap();
+
+        let text = &reloaded.get_note(&referencing).unwrap().text;
+        assert_eq!(text, "see [[New title]] and [[Other]]");
+        let outgoing = reloaded.note_links(&referencing).unwrap().outgoing;
+        let archived_target = outgoing[0].target.as_ref().unwrap();
+        assert_eq!(archived_target.status, NoteStatus::Archived);
+    }
+
+    #[test]
+    fn related_notes_rank_exact_links_first_and_keep_boosts() {
+        let (path, mut note_taker) = note_taker("notes_related");
+        let app = "main.rs - rarian - Code".to_string();
+        let exact = note_taker.add_note("exact", vec![app.clone()]).unwrap();
+        let boosted = note_taker.add_note("boosted", vec![app.clone()]).unwrap();
+        let similar = note_taker
+            .add_note("similar", vec!["lib.rs - rarian - Code".to_string()])
+            .unwrap();
+        note_taker
+            .add_note("unrelated", vec!["Firefox".to_string()])
+            .unwrap();
+        note_taker.boost_link(&boosted, &app, 5).unwrap();
+        note_taker.boost_link(&similar, "Firefox", 1).unwrap();
+        note_taker.edit_note(&exact, "exact, edited").unwrap();
+        let reloaded = NoteTaker::new(&path).unwrap();
+        remove_dir_all(&path).unwrap();
+
+        let context = NoteContext::new(Some(app), None);
+        let related: Vec<Ulid> = reloaded
+            .related_notes(&context)
+            .iter()
+            .map(|note| note.id)
+            .collect();
+        assert_eq!(related, vec![boosted, exact, similar]);
+        let weights: Vec<usize> = reloaded
+            .get_note(&exact)
+            .unwrap()
+            .links()
+            .iter()
+            .map(|link| link.weight)
+            .collect();
+        assert_eq!(weights, vec![1 + EDIT_BOOST]);
+        assert_eq!(reloaded.get_note(&similar).unwrap().links().len(), 2);
+    }
+
+    #[test]
+    fn pins_and_manual_order_are_kept_per_link() {
+        let (path, mut note_taker) = note_taker("notes_order");
+        let app = "terminal".to_string();
+        let first = note_taker.add_note("b first", vec![app.clone()]).unwrap();
+        let second = note_taker.add_note("c second", vec![app.clone()]).unwrap();
+        let third = note_taker
+            .add_note("a third", vec![app.clone(), "editor".to_string()])
+            .unwrap();
+        note_taker.edit_note(&first, "b first, edited").unwrap();
+        note_taker.reorder(&app, &[second, third, first]).unwrap();
+        note_taker.set_pinned(&first, &app, true).unwrap();
+        let reloaded = NoteTaker::new(&path).unwrap();
+        remove_dir_all(&path).unwrap();
+
+        let ordered = |link: &str, sort: NoteSort| -> Vec<Ulid> {
+            let context = NoteContext::new(Some(link.to_string()), None);
+            reloaded
+                .ordered_notes(&context, sort)
+                .iter()
+                .map(|note| note.id)
+                .collect()
+        };
+        assert_eq!(ordered(&app, NoteSort::Manual), vec![first, second, third]);
+        assert_eq!(ordered(&app, NoteSort::Created), vec![first, third, second]);
+        assert_eq!(
+            ordered(&app, NoteSort::Alphabetical),
+            vec![first, third, second]
+        );
+        assert_eq!(ordered(&app, NoteSort::Updated), vec![first, third, second]);
+        // Pins and positions belong to the link they were set on
+        assert_eq!(ordered("editor", NoteSort::Manual), vec![third]);
+    }
+
+    #[test]
+    fn tasks_are_filtered_ordered_and_checked_off() {
+        let (path, mut note_taker) = note_taker("notes_tasks");
+        let due = |day: u32| NaiveDate::from_ymd_opt(2026, 10, day);
+        let later = note_taker
+            .add_note("later\n- [ ] one\n- [ ] two", vec!["terminal".to_string()])
+            .unwrap();
+        let sooner = note_taker.add_note("sooner", vec![]).unwrap();
+        let done = note_taker.add_note("done", vec![]).unwrap();
+        note_taker.add_note("not a task", vec![]).unwrap();
+        note_taker
+            .set_task(
+                &later,
+                Some(Task {
+                    due: due(20),
+                    ..Task::default()
+                }),
+            )
+            .unwrap();
+        note_taker
+            .set_task(
+                &sooner,
+                Some(Task {
+                    due: due(10),
+                    ..Task::default()
+                }),
+            )
+            .unwrap();
+        note_taker
+            .set_task(
+                &done,
+                Some(Task {
+                    done: true,
+                    ..Task::default()
+                }),
+            )
+            .unwrap();
+        note_taker.toggle_checklist_item(&later, 1).unwrap();
+        let reloaded = NoteTaker::new(&path).unwrap();
+        remove_dir_all(&path).unwrap();
+
+        let ids = |filter: TaskFilter| -> Vec<Ulid> {
+            reloaded.tasks(&filter).iter().map(|note| note.id).collect()
+        };
+        assert_eq!(ids(TaskFilter::default()), vec![sooner, later]);
+        let all = TaskFilter {
+            include_done: true,
+            ..TaskFilter::default()
+        };
+        assert_eq!(ids(all), vec![sooner, later, done]);
+        let due_soon = TaskFilter {
+            due_before: due(15),
+            ..TaskFilter::default()
+        };
+        assert_eq!(ids(due_soon), vec![sooner]);
+        let linked = TaskFilter {
+            link: Some("terminal".to_string()),
+            ..TaskFilter::default()
+        };
+        assert_eq!(ids(linked), vec![later]);
+        let later = reloaded.get_note(&later).unwrap();
+        assert_eq!(later.text, "later\n- [ ] one\n- [x] two");
+        assert!(later.task.is_some());
+    }
 }
@@ -0,0 +1,463 @@
+use crate::cacher::{Cache, FileCacher, LoadFromCache};
+use crate::error::{RarianError, Result};
+use crate::notes::{Note, NoteStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{create_dir_all, read_dir, read_to_string, write, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use ulid::Ulid;
+
+/// Orders ops by their Lamport clock, and ops with the same clock by their device, so every
+/// replica orders them the same
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OpId {
+    pub clock: u64,
+    pub device: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OpKind {
+    Create { text: String, links: Vec<String> },
+    EditText { text: String },
+    AddLink { link: String },
+    RemoveLink { link: String },
+    SetStatus { status: NoteStatus },
+}
+
+/// A change to a note made on a device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Op {
+    pub id: OpId,
+    pub note: Ulid,
+    /// When it was made, for the dates of the note rather than its order
+    pub time: SystemTime,
+    pub kind: OpKind,
+}
+
+/// A note as its ops make it. Of concurrent edits of the text, the status or a link, the one with
+/// the highest `OpId` wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteState {
+    pub id: Ulid,
+    pub text: String,
+    /// In the order they were first added
+    pub links: Vec<String>,
+    pub status: NoteStatus,
+    pub creation_date: SystemTime,
+    pub updated_date: Option<SystemTime>,
+}
+
+/// The op log of a device: its own ops and the ones it received, appended to `ops.json`
+pub struct Replica {
+    device: String,
+    clock: u64,
+    ops: BTreeMap<OpId, Op>,
+    cacher: FileCacher,
+}
+
+impl Replica {
+    /// Loads the op log in the data path, as the device named in its `device` file, which is
+    /// created on first use
+    pub fn new(data_path: &Path) -> Result<Replica> {
+        let device_path = data_path.join("device");
+        let device = match read_to_string(&device_path) {
+            Ok(device) if !device.trim().is_empty() => device.trim().to_string(),
+            _ => {
+                let device = Ulid::new().to_string();
+                write(&device_path, &device).map_err(|err| RarianError::WriteCache {
+                    path: device_path.clone(),
+                    message: err.to_string(),
+                })?;
+                device
+            }
+        };
+        Replica::with_device(data_path.join("ops.json"), device)
+    }
+
+    pub fn with_device(path: PathBuf, device: String) -> Result<Replica> {
+        let mut cacher = FileCacher::new(path)?;
+        let loaded: Vec<Op> = cacher.load_from_cache();
+        let ops: BTreeMap<OpId, Op> = loaded.into_iter().map(|op| (op.id.clone(), op)).collect();
+        let clock = ops.keys().map(|id| id.clock).max().unwrap_or(0);
+        Ok(Replica {
+            device,
+            clock,
+            ops,
+            cacher,
+        })
+    }
+
+    /// The lines of the op log that couldn't be read when it was loaded
+    pub fn unreadable(&self) -> Option<RarianError> {
+        self.cacher.unreadable()
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Every op, in order
+    pub fn ops(&self) -> impl Iterator<Item = &Op> {
+        self.ops.values()
+    }
+
+    fn append(&mut self, op: Op) -> Result<()> {
+        self.cacher.cache(&op)?;
+        self.clock = self.clock.max(op.id.clock);
+        self.ops.insert(op.id.clone(), op);
+        Ok(())
+    }
+
+    /// Makes a local op, after every op this replica has seen
+    pub fn apply(&mut self, note: Ulid, kind: OpKind, time: SystemTime) -> Result<()> {
+        let id = OpId {
+            clock: self.clock + 1,
+            device: self.device.clone(),
+        };
+        self.append(Op {
+            id,
+            note,
+            time,
+            kind,
+        })
+    }
+
+    /// Records the ops that turn `old` into `new`, or that create `new` if there was no `old`. The
+    /// ops are stamped with the time they are made, except the creation, which carries the
+    /// creation date of the note.
+    pub fn record_change(&mut self, old: Option<&Note>, new: &Note) -> Result<()> {
+        let time = SystemTime::now();
+        let links: Vec<String> = new.links().iter().map(|link| link.link.clone()).collect();
+        let Some(old) = old else {
+            self.apply(
+                new.id,
+                OpKind::Create {
+                    text: new.text.clone(),
+                    links,
+                },
+                new.creation_date,
+            )?;
+            if new.status != NoteStatus::Active {
+                let status = new.status.clone();
+                self.apply(new.id, OpKind::SetStatus { status }, time)?;
+            }
+            return Ok(());
+        };
+        if old.text != new.text {
+            let text = new.text.clone();
+            self.apply(new.id, OpKind::EditText { text }, time)?;
+        }
+        for link in links.iter() {
+            if old.link_to(link).is_none() {
+                let link = link.clone();
+                self.apply(new.id, OpKind::AddLink { link }, time)?;
+            }
+        }
+        for link in old.links() {
+            if new.link_to(&link.link).is_none() {
+                let link = link.link.clone();
+                self.apply(new.id, OpKind::RemoveLink { link }, time)?;
+            }
+        }
+        if old.status != new.status {
+            let status = new.status.clone();
+            self.apply(new.id, OpKind::SetStatus { status }, time)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the ops this replica didn't have yet, and returns the notes they changed
+    pub fn merge(&mut self, ops: impl IntoIterator<Item = Op>) -> Result<HashSet<Ulid>> {
+        let mut changed = HashSet::new();
+        for op in ops {
+            if self.ops.contains_key(&op.id) {
+                continue;
+            }
+            changed.insert(op.note);
+            self.append(op)?;
+        }
+        Ok(changed)
+    }
+
+    /// The state of a note, `None` if no op created it yet
+    pub fn state(&self, note: &Ulid) -> Option<NoteState> {
+        self.states_of(&[*note].into_iter().collect()).pop()
+    }
+
+    /// The state of every note created so far
+    pub fn states(&self) -> Vec<NoteState> {
+        let notes = self.ops.values().map(|op| op.note).collect();
+        self.states_of(&notes)
+    }
+
+    fn states_of(&self, notes: &HashSet<Ulid>) -> Vec<NoteState> {
+        let mut states: BTreeMap<Ulid, NoteState> = BTreeMap::new();
+        // Whether each link is there after the last op on it, and when it was first added
+        let mut links: HashMap<Ulid, BTreeMap<String, (bool, OpId)>> = HashMap::new();
+        for op in self.ops.values().filter(|op| notes.contains(&op.note)) {
+            if let OpKind::Create {
+                text,
+                links: created,
+            } = &op.kind
+            {
+                // A note created twice with the same id keeps the first text
+                let state = states.entry(op.note).or_insert(NoteState {
+                    id: op.note,
+                    text: text.clone(),
+                    links: Vec::new(),
+                    status: NoteStatus::Active,
+                    creation_date: op.time,
+                    updated_date: None,
+                });
+                state.creation_date = state.creation_date.min(op.time);
+                let note_links = links.entry(op.note).or_default();
+                for link in created {
+                    note_links
+                        .entry(link.clone())
+                        .or_insert((true, op.id.clone()));
+                }
+                continue;
+            }
+            // Ops come in order, and a note is created before anything else is done to it
+            let Some(state) = states.get_mut(&op.note) else {
+                continue;
+            };
+            state.updated_date = state.updated_date.max(Some(op.time));
+            match &op.kind {
+                OpKind::Create { .. } => {}
+                OpKind::EditText { text } => state.text = text.clone(),
+                OpKind::SetStatus { status } => state.status = status.clone(),
+                OpKind::AddLink { link } => {
+                    let entry = links.entry(op.note).or_default();
+                    let added = entry
+                        .get(link)
+                        .map(|(_, added)| added.clone())
+                        .unwrap_or(op.id.clone());
+                    entry.insert(link.clone(), (true, added));
+                }
+                OpKind::RemoveLink { link } => {
+                    if let Some(entry) = links.entry(op.note).or_default().get_mut(link) {
+                        entry.0 = false;
+                    }
+                }
+            }
+        }
+        for (note, state) in states.iter_mut() {
+            let mut present: Vec<(&OpId, &String)> = links
+                .get(note)
+                .into_iter()
+                .flatten()
+                .filter(|(_, (present, _))| *present)
+                .map(|(link, (_, added))| (added, link))
+                .collect();
+            present.sort();
+            state.links = present.into_iter().map(|(_, link)| link.clone()).collect();
+        }
+        states.into_values().collect()
+    }
+}
+
+/// Carries ops between replicas
+pub trait Transport {
+    /// Sends the ops of this replica and returns the ones of the others, which may include ops
+    /// it has already
+    fn exchange(&mut self, ops: &[Op]) -> Result<Vec<Op>>;
+}
+
+/// A folder shared by every device, like a network drive or a synced folder, where each device
+/// appends its ops to a file of its own
+pub struct FolderTransport {
+    dir: PathBuf,
+    device: String,
+}
+
+impl FolderTransport {
+    pub fn new(dir: PathBuf, device: &str) -> FolderTransport {
+        FolderTransport {
+            dir,
+            device: device.to_string(),
+        }
+    }
+
+    fn read_ops(path: &Path) -> Vec<Op> {
+        read_to_string(path)
+            .map(|text| {
+                text.lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Transport for FolderTransport {
+    fn exchange(&mut self, ops: &[Op]) -> Result<Vec<Op>> {
+        let io_error = |path: &Path, err: std::io::Error| RarianError::WriteCache {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        };
+        create_dir_all(&self.dir).map_err(|err| io_error(&self.dir, err))?;
+        let own_path = self.dir.join(format!("{}.jsonl", self.device));
+        let published: HashSet<OpId> = FolderTransport::read_ops(&own_path)
+            .into_iter()
+            .map(|op| op.id)
+            .collect();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&own_path)
+            .map_err(|err| io_error(&own_path, err))?;
+        for op in ops.iter().filter(|op| !published.contains(&op.id)) {
+            let line = serde_json::to_string(op).unwrap() + "\n";
+            file.write_all(line.as_bytes())
+                .map_err(|err| io_error(&own_path, err))?;
+        }
+        let entries = read_dir(&self.dir).map_err(|err| io_error(&self.dir, err))?;
+        Ok(entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| *path != own_path && path.extension().is_some_and(|e| e == "jsonl"))
+            .flat_map(|path| FolderTransport::read_ops(&path))
+            .collect())
+    }
+}
+
+/// A connection to another replica, like a TCP socket. Ops are sent as JSON lines ending with an
+/// empty line. The side that connected sends first, so neither waits for the other forever.
+pub struct StreamTransport<S: Read + Write> {
+    stream: S,
+    sends_first: bool,
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    pub fn new(stream: S, sends_first: bool) -> StreamTransport<S> {
+        StreamTransport {
+            stream,
+            sends_first,
+        }
+    }
+
+    fn send(&mut self, ops: &[Op]) -> std::io::Result<()> {
+        for op in ops {
+            let line = serde_json::to_string(op).unwrap() + "\n";
+            self.stream.write_all(line.as_bytes())?;
+        }
+        self.stream.write_all(b"\n")?;
+        self.stream.flush()
+    }
+
+    fn receive(&mut self) -> std::io::Result<Vec<Op>> {
+        let mut reader = BufReader::new(&mut self.stream);
+        let mut ops = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                return Ok(ops);
+            }
+            if let Ok(op) = serde_json::from_str(&line) {
+                ops.push(op);
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Transport for StreamTransport<S> {
+    fn exchange(&mut self, ops: &[Op]) -> Result<Vec<Op>> {
+        let result = match self.sends_first {
+            true => self.send(ops).and_then(|_| self.receive()),
+            false => self
+                .receive()
+                .and_then(|received| self.send(ops).map(|_| received)),
+        };
+        result.map_err(|err| RarianError::Sync(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod oplog_test {
+    use super::*;
+    use std::fs::remove_dir_all;
+    use std::time::Duration;
+
+    fn replica(dir: &Path, device: &str) -> Replica {
+        Replica::with_device(dir.join(format!("{}.json", device)), device.to_string()).unwrap()
+    }
+
+    fn exchange(a: &mut Replica, b: &mut Replica) {
+        let from_a: Vec<Op> = a.ops().cloned().collect();
+        let from_b: Vec<Op> = b.ops().cloned().collect();
+        a.merge(from_b).unwrap();
+        b.merge(from_a).unwrap();
+    }
+
+    #[test]
+    fn concurrent_replicas_converge_in_any_order() {
+        let dir = PathBuf::from("./testData/oplog");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let (mut a, mut b, mut c) = (replica(&dir, "a"), replica(&dir, "b"), replica(&dir, "c"));
+        let time = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let note = Ulid::new();
+        let create = OpKind::Create {
+            text: "Plan".to_string(),
+            links: vec!["Code".to_string()],
+        };
+        a.apply(note, create, time(1)).unwrap();
+        exchange(&mut a, &mut b);
+        exchange(&mut b, &mut c);
+
+        // Concurrent edits at the same clock, link changes and an archive
+        let edit = |text: &str| OpKind::EditText {
+            text: text.to_string(),
+        };
+        a.apply(note, edit("Plan A"), time(5)).unwrap();
+        b.apply(note, edit("Plan B"), time(3)).unwrap();
+        b.apply(
+            note,
+            OpKind::AddLink {
+                link: "Firefox".to_string(),
+            },
+            time(3),
+        )
+        .unwrap();
+        c.apply(
+            note,
+            OpKind::RemoveLink {
+                link: "Code".to_string(),
+            },
+            time(4),
+        )
+        .unwrap();
+        c.apply(
+            note,
+            OpKind::SetStatus {
+                status: NoteStatus::Archived,
+            },
+            time(4),
+        )
+        .unwrap();
+
+        exchange(&mut c, &mut a);
+        exchange(&mut a, &mut b);
+        exchange(&mut b, &mut c);
+        let states = [a.state(&note), b.state(&note), c.state(&note)];
+        // Reloading gives the same state as well
+        let reloaded = replica(&dir, "a").state(&note);
+        remove_dir_all(&dir).unwrap();
+
+        let state = states[0].clone().unwrap();
+        assert!(states.iter().all(|other| *other == Some(state.clone())));
+        assert_eq!(reloaded, Some(state.clone()));
+        // The edits have the same clock, so the device decides
+        assert_eq!(state.text, "Plan B");
+        assert_eq!(state.links, vec!["Firefox".to_string()]);
+        assert_eq!(state.status, NoteStatus::Archived);
+        assert_eq!(state.updated_date, Some(time(5)));
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{bail, Context, Result};
+use ratatui::crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+use std::env;
+use std::io::{stderr, Write};
+
+/// Read instead of prompting, for scripts
+const PASSPHRASE_VAR: &str = "RARIAN_PASSPHRASE";
+const NEW_PASSPHRASE_VAR: &str = "RARIAN_NEW_PASSPHRASE";
+
+/// The passphrase of the data path, from `$RARIAN_PASSPHRASE` or typed without echo
+pub fn read_passphrase() -> Result<String> {
+    match env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => prompt("Passphrase: "),
+    }
+}
+
+/// A new passphrase, from `$RARIAN_NEW_PASSPHRASE` or typed twice without echo
+pub fn read_new_passphrase() -> Result<String> {
+    let passphrase = match env::var(NEW_PASSPHRASE_VAR) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let passphrase = prompt("New passphrase: ")?;
+            if prompt("Repeat the new passphrase: ")? != passphrase {
+                bail!("the passphrases are different");
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        bail!("the passphrase is empty");
+    }
+    Ok(passphrase)
+}
+
+fn prompt(message: &str) -> Result<String> {
+    eprint!("{}", message);
+    stderr().flush()?;
+    enable_raw_mode().context("failed to read the passphrase from the terminal")?;
+    let typed = read_line();
+    let _ = disable_raw_mode();
+    eprintln!();
+    typed
+}
+
+fn read_line() -> Result<String> {
+    let mut typed = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(typed),
+            KeyCode::Esc => bail!("cancelled"),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                bail!("cancelled")
+            }
+            KeyCode::Backspace => {
+                typed.pop();
+            }
+            KeyCode::Char(c) => typed.push(c),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::digest::project_of;
+use crate::notes::{Link, Note};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// How much a link matching the context exactly counts, per unit of link strength
+const EXACT_MATCH: f64 = 4.0;
+/// How much a link to a file in the project of the context counts
+const PROJECT_MATCH: f64 = 2.0;
+/// How much an app title sharing all its words with the context counts
+const SIMILAR_TITLE: f64 = 2.0;
+/// How much a note mentioning the git branch of the context counts, regardless of its links
+const BRANCH_MATCH: f64 = 2.0;
+/// Branches most work happens on, which say nothing about what a note is about
+const DEFAULT_BRANCHES: [&str; 2] = ["main", "master"];
+
+/// The projects of the files linked to by the notes being ranked, kept for one ranking as finding
+/// one walks up the file system and many notes link to the same files
+pub type LinkProjects = HashMap<String, Option<String>>;
+
+fn link_project<'a>(link: &str, link_projects: &'a mut LinkProjects) -> &'a Option<String> {
+    link_projects
+        .entry(link.to_string())
+        .or_insert_with(|| project_of(Path::new(link)))
+}
+
+/// What the user is working on, which notes are ranked against
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NoteContext {
+    pub app_title: Option<String>,
+    pub file_path: Option<PathBuf>,
+    pub project: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl NoteContext {
+    /// The context of an app, with the project and branch of the file last edited in it
+    pub fn new(app_title: Option<String>, file_path: Option<PathBuf>) -> NoteContext {
+        NoteContext {
+            project: file_path.as_deref().and_then(project_of),
+            branch: file_path.as_deref().and_then(branch_of),
+            app_title,
+            file_path,
+        }
+    }
+
+    /// Whether `link` is the app or file of the context itself
+    pub fn matches_exactly(&self, link: &str) -> bool {
+        self.app_title.as_deref() == Some(link)
+            || self
+                .file_path
+                .as_ref()
+                .is_some_and(|path| path.as_os_str() == link)
+    }
+}
+
+/// The git branch checked out in the repository a path is in, if any
+pub fn branch_of(path: &Path) -> Option<String> {
+    let repository = path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").is_dir())?;
+    let head = read_to_string(repository.join(".git").join("HEAD")).ok()?;
+    head.trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(|branch| branch.to_string())
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 2)
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Links that were boosted count for more, but with diminishing returns so a note that was shown
+/// for hours doesn't drown out everything else
+fn strength(link: &Link) -> f64 {
+    1.0 + (link.weight.max(1) as f64).ln()
+}
+
+fn link_match(
+    link: &str,
+    context: &NoteContext,
+    title_words: &HashSet<String>,
+    link_projects: &mut LinkProjects,
+) -> f64 {
+    if context.matches_exactly(link) {
+        return EXACT_MATCH;
+    }
+    if Path::new(link).is_absolute() {
+        return match (&context.project, link_project(link, link_projects)) {
+            (Some(project), Some(link_project)) if project == link_project => PROJECT_MATCH,
+            _ => 0.0,
+        };
+    }
+    let link_words = words(link);
+    if link_words.is_empty() {
+        return 0.0;
+    }
+    let shared = link_words.intersection(title_words).count();
+    SIMILAR_TITLE * shared as f64 / link_words.len() as f64
+}
+
+/// How relevant a note is to a context, from the weighted overlap of its links with the app, file,
+/// project and branch of the context. Zero means unrelated. `link_projects` is shared by the notes
+/// ranked together.
+pub fn relevance(note: &Note, context: &NoteContext, link_projects: &mut LinkProjects) -> f64 {
+    let title_words = context.app_title.as_deref().map(words).unwrap_or_default();
+    let mut score: f64 = note
+        .links()
+        .iter()
+        .map(|link| link_match(&link.link, context, &title_words, link_projects) * strength(link))
+        .sum();
+    if let Some(branch) = &context.branch {
+        let branch = branch.to_lowercase();
+        if !DEFAULT_BRANCHES.contains(&branch.as_str())
+            && note.text.to_lowercase().contains(&branch)
+        {
+            score += BRANCH_MATCH;
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod relevance_test {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    fn note(text: &str, links: &[(&str, usize)]) -> Note {
+        let links = links
+            .iter()
+            .map(|(link, weight)| Link::new(link.to_string(), *weight))
+            .collect();
+        Note::new(text, links)
+    }
+
+    fn score(note: &Note, context: &NoteContext) -> f64 {
+        relevance(note, context, &mut LinkProjects::new())
+    }
+
+    #[test]
+    fn exact_matches_outrank_similar_titles_and_boosts_count() {
+        let context = NoteContext::new(Some("main.rs - rarian - Code".to_string()), None);
+        let exact = note("exact", &[("main.rs - rarian - Code", 1)]);
+        let boosted = note("boosted", &[("main.rs - rarian - Code", 5)]);
+        let similar = note("similar", &[("lib.rs - rarian - Code", 1)]);
+        let unrelated = note("unrelated", &[("Firefox", 1)]);
+        assert!(score(&boosted, &context) > score(&exact, &context));
+        assert!(score(&exact, &context) > score(&similar, &context));
+        assert!(score(&similar, &context) > 0.0);
+        assert_eq!(score(&unrelated, &context), 0.0);
+    }
+
+    #[test]
+    fn files_in_the_same_project_and_branch_mentions_are_related() {
+        let repository = std::env::current_dir()
+            .unwrap()
+            .join("testData/relevance_project");
+        create_dir_all(repository.join(".git")).unwrap();
+        write(repository.join(".git/HEAD"), "ref: refs/heads/fix-login\n").unwrap();
+        let file = repository.join("src").join("login.rs");
+        let context = NoteContext::new(Some("terminal".to_string()), Some(file.clone()));
+        let other_file = repository.join("README.md").to_string_lossy().to_string();
+        let same_project = note("docs", &[(other_file.as_str(), 1)]);
+        let branch_note = note("the fix-login ticket", &[("Firefox", 1)]);
+        let same_file = note("this file", &[(file.to_str().unwrap(), 1)]);
+        let project_score = score(&same_project, &context);
+        remove_dir_all(&repository).unwrap();
+
+        assert_eq!(context.project.as_deref(), Some("relevance_project"));
+        assert_eq!(context.branch.as_deref(), Some("fix-login"));
+        assert_eq!(project_score, PROJECT_MATCH);
+        assert_eq!(score(&branch_note, &context), BRANCH_MATCH);
+        assert_eq!(score(&same_file, &context), EXACT_MATCH);
+    }
+}
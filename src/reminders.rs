@@ -0,0 +1,328 @@
+use crate::cacher::{Cache, FileCacher};
+use crate::error::{RarianError, Result};
+use crate::notes::Note;
+use crate::relevance::NoteContext;
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// The time of day reminders for a day without a time are set to
+fn morning() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap()
+}
+
+/// What makes a reminder due
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Trigger {
+    /// At a wall-clock time, with the note shown as usual until then
+    At(SystemTime),
+    /// At a wall-clock time, with the note hidden until then
+    Snooze(SystemTime),
+    /// The next time an app with this title, or a file in this project, is focused
+    Context {
+        app: Option<String>,
+        project: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reminder {
+    pub trigger: Trigger,
+    /// When the reminder became due, it stays due until it is dismissed
+    #[serde(default)]
+    pub fired: Option<SystemTime>,
+}
+
+impl Reminder {
+    pub fn new(trigger: Trigger) -> Reminder {
+        Reminder {
+            trigger,
+            fired: None,
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.fired.is_some()
+    }
+
+    /// Whether the note is hidden until the reminder is due
+    pub fn is_snoozed(&self) -> bool {
+        matches!(self.trigger, Trigger::Snooze(_)) && !self.is_due()
+    }
+
+    /// Whether the reminder should become due at `now`, or in `context` if the focus just changed
+    pub fn should_fire(&self, now: SystemTime, context: Option<&NoteContext>) -> bool {
+        if self.is_due() {
+            return false;
+        }
+        match &self.trigger {
+            Trigger::At(time) | Trigger::Snooze(time) => *time <= now,
+            Trigger::Context { app, project } => context.is_some_and(|context| {
+                let title = context
+                    .app_title
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let app_matches = app
+                    .as_ref()
+                    .is_some_and(|app| title.contains(&app.to_lowercase()));
+                // Editors usually put the name of the open folder in their title
+                let project_matches = project.as_ref().is_some_and(|project| {
+                    context.project.as_ref() == Some(project)
+                        || title.contains(&project.to_lowercase())
+                });
+                app_matches || project_matches
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Reminder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format_time =
+            |time: &SystemTime| DateTime::<Local>::from(*time).format("%Y-%m-%d %H:%M");
+        match &self.trigger {
+            Trigger::At(time) => write!(f, "at {}", format_time(time)),
+            Trigger::Snooze(time) => write!(f, "snoozed until {}", format_time(time)),
+            Trigger::Context { app, project } => {
+                let contexts: Vec<&str> = [app, project]
+                    .into_iter()
+                    .flatten()
+                    .map(|context| context.as_str())
+                    .collect();
+                write!(f, "next time in {}", contexts.join(" or "))
+            }
+        }
+    }
+}
+
+/// Parses `30m`, `2h`, `1d` or `1w`
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let unit = text.chars().last()?;
+    let amount: u64 = text[..text.len() - unit.len_utf8()].trim().parse().ok()?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+fn local_time(date: NaiveDate, time: NaiveTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date.and_time(time)).earliest()
+}
+
+/// Parses a time relative to `now`: `YYYY-MM-DD HH:MM`, `YYYY-MM-DD`, `HH:MM`, `tomorrow`, a
+/// weekday like `monday`, or a duration like `2h`. Days without a time mean 9:00, and a time or
+/// weekday that already passed means the next one.
+pub fn parse_time(text: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    let text = text.trim().to_lowercase();
+    if let Some(duration) = parse_duration(&text) {
+        return now.checked_add_signed(chrono::Duration::from_std(duration).ok()?);
+    }
+    if let Ok(time) = chrono::NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M") {
+        return local_time(time.date(), time.time());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(&text, "%Y-%m-%d") {
+        return local_time(date, morning());
+    }
+    let today = now.date_naive();
+    if let Ok(time) = NaiveTime::parse_from_str(&text, "%H:%M") {
+        let date = match time > now.time() {
+            true => today,
+            false => today.checked_add_days(Days::new(1))?,
+        };
+        return local_time(date, time);
+    }
+    if text == "tomorrow" {
+        return local_time(today.checked_add_days(Days::new(1))?, morning());
+    }
+    let weekday: Weekday = text.parse().ok()?;
+    let days_ahead =
+        (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+    // The same weekday means next week
+    let days_ahead = if days_ahead == 0 { 7 } else { days_ahead };
+    local_time(
+        today.checked_add_days(Days::new(u64::from(days_ahead)))?,
+        morning(),
+    )
+}
+
+/// Delivers due reminders, on the service thread
+pub trait Notifier: Send {
+    fn notify(&mut self, note: &Note) -> Result<()>;
+}
+
+/// Which notifier the config asks for
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    #[default]
+    Desktop,
+    /// Appends reminders to `reminders.json` in the data path
+    Log,
+    /// Only shows reminders in the TUI
+    Off,
+}
+
+impl NotifierKind {
+    pub fn notifier(self, data_path: &Path) -> Result<Box<dyn Notifier>> {
+        Ok(match self {
+            NotifierKind::Desktop => Box::new(DesktopNotifier),
+            NotifierKind::Log => Box::new(LogNotifier::new(data_path)?),
+            NotifierKind::Off => Box::new(NoNotifier),
+        })
+    }
+}
+
+/// Delivers nothing, for when reminders are only shown in the TUI
+pub struct NoNotifier;
+
+impl Notifier for NoNotifier {
+    fn notify(&mut self, _note: &Note) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct DeliveredReminder {
+    time: SystemTime,
+    note: Note,
+}
+
+/// Appends every due reminder to `reminders.json` in the data path
+pub struct LogNotifier {
+    cacher: FileCacher,
+}
+
+impl LogNotifier {
+    pub fn new(data_path: &Path) -> Result<LogNotifier> {
+        Ok(LogNotifier {
+            cacher: FileCacher::new(data_path.join("reminders.json"))?,
+        })
+    }
+}
+
+impl Notifier for LogNotifier {
+    fn notify(&mut self, note: &Note) -> Result<()> {
+        self.cacher.cache(&DeliveredReminder {
+            time: SystemTime::now(),
+            note: note.clone(),
+        })
+    }
+}
+
+/// Shows due reminders as desktop notifications, with the notification tool of the platform
+pub struct DesktopNotifier;
+
+impl DesktopNotifier {
+    fn command(title: &str, body: &str) -> Command {
+        if cfg!(target_os = "macos") {
+            let mut command = Command::new("osascript");
+            let script = format!(
+                "display notification {:?} with title {:?}",
+                body,
+                format!("rarian: {}", title)
+            );
+            command.args(["-e", &script]);
+            command
+        } else if cfg!(target_os = "windows") {
+            let mut command = Command::new("powershell");
+            let script = format!(
+                "[reflection.assembly]::loadwithpartialname('System.Windows.Forms') | Out-Null; \
+                 $n = New-Object System.Windows.Forms.NotifyIcon; \
+                 $n.Icon = [System.Drawing.SystemIcons]::Information; $n.Visible = $true; \
+                 $n.ShowBalloonTip(10000, 'rarian: {}', '{}', 'Info')",
+                title.replace('\'', "''"),
+                body.replace('\'', "''")
+            );
+            command.args(["-NoProfile", "-Command", &script]);
+            command
+        } else {
+            let mut command = Command::new("notify-send");
+            command.args(["--app-name=rarian", &format!("rarian: {}", title), body]);
+            command
+        }
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn notify(&mut self, note: &Note) -> Result<()> {
+        let notify_error = |message: String| RarianError::Notify(message);
+        let status = DesktopNotifier::command(note.title(), &note.text)
+            .status()
+            .map_err(|err| notify_error(err.to_string()))?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(notify_error(format!(
+                "notification command failed with {}",
+                status
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod reminders_test {
+    use super::*;
+
+    fn context(app: &str, project: Option<&str>) -> NoteContext {
+        NoteContext {
+            app_title: Some(app.to_string()),
+            project: project.map(|project| project.to_string()),
+            ..NoteContext::default()
+        }
+    }
+
+    #[test]
+    fn times_are_parsed_relative_to_now() {
+        // A Wednesday
+        let now = Local.with_ymd_and_hms(2026, 10, 21, 14, 30, 0).unwrap();
+        let parse = |text: &str| {
+            parse_time(text, now)
+                .unwrap()
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        };
+        assert_eq!(parse("2h"), "2026-10-21 16:30");
+        assert_eq!(parse("monday"), "2026-10-26 09:00");
+        assert_eq!(parse("Wednesday"), "2026-10-28 09:00");
+        assert_eq!(parse("tomorrow"), "2026-10-22 09:00");
+        assert_eq!(parse("15:00"), "2026-10-21 15:00");
+        assert_eq!(parse("08:00"), "2026-10-22 08:00");
+        assert_eq!(parse("2026-11-02"), "2026-11-02 09:00");
+        assert_eq!(parse("2026-11-02 17:45"), "2026-11-02 17:45");
+        assert_eq!(parse_time("someday", now), None);
+        assert_eq!(parse_time("400000000000000000m", now), None);
+        assert_eq!(parse_time("100000000w", now), None);
+    }
+
+    #[test]
+    fn reminders_fire_on_time_or_context_once() {
+        let now = SystemTime::now();
+        let past = Reminder::new(Trigger::Snooze(now - Duration::from_secs(1)));
+        let future = Reminder::new(Trigger::At(now + Duration::from_secs(60)));
+        assert!(past.should_fire(now, None));
+        assert!(!future.should_fire(now, None));
+        assert!(!future.is_snoozed() && past.is_snoozed());
+
+        let repository = Reminder::new(Trigger::Context {
+            app: None,
+            project: Some("rarian".to_string()),
+        });
+        assert!(!repository.should_fire(now, None));
+        assert!(!repository.should_fire(now, Some(&context("Firefox", None))));
+        assert!(repository.should_fire(now, Some(&context("terminal", Some("rarian")))));
+        assert!(repository.should_fire(now, Some(&context("main.rs - Rarian - Code", None))));
+        let fired = Reminder {
+            fired: Some(now),
+            ..repository
+        };
+        assert!(fired.is_due() && !fired.should_fire(now, Some(&context("rarian", None))));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::backup::rewrite_snapshots;
+use crate::cacher::{Cache, FileCacher, LoadFromCache};
+use crate::error::{RarianError, Result};
+use crate::gatherer::app_gatherer::ActiveProcessEvent;
+use crate::gatherer::file_gatherer::FileEvent;
+use crate::timeline::local_date;
+use chrono::{Local, NaiveDate, TimeZone};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn default_keep_daily_totals() -> bool {
+    true
+}
+
+/// How long the activity logs are kept, set in the `[retention]` table of the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Days the app focus events are kept, forever if unset
+    #[serde(default)]
+    pub app_events_days: Option<u64>,
+    /// Days the file change events are kept, forever if unset
+    #[serde(default)]
+    pub file_events_days: Option<u64>,
+    /// Whether the time spent in each app is kept per day in `daily.json` when its events go
+    #[serde(default = "default_keep_daily_totals")]
+    pub keep_daily_totals: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            app_events_days: None,
+            file_events_days: None,
+            keep_daily_totals: default_keep_daily_totals(),
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.app_events_days.is_some() || self.file_events_days.is_some()
+    }
+}
+
+/// The time spent in an app on a day, kept after its focus events are removed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyTotal {
+    pub date: NaiveDate,
+    pub app_title: String,
+    pub duration: Duration,
+}
+
+/// The daily totals of `dates`, summed per app
+pub fn daily_totals(data_path: &Path, dates: &[NaiveDate]) -> Result<Vec<DailyTotal>> {
+    let totals: Vec<DailyTotal> = FileCacher::new(data_path.join("daily.json"))?.load_from_cache();
+    let mut summed: BTreeMap<(NaiveDate, String), Duration> = BTreeMap::new();
+    for total in totals
+        .into_iter()
+        .filter(|total| dates.contains(&total.date))
+    {
+        *summed.entry((total.date, total.app_title)).or_default() += total.duration;
+    }
+    Ok(summed
+        .into_iter()
+        .map(|((date, app_title), duration)| DailyTotal {
+            date,
+            app_title,
+            duration,
+        })
+        .collect())
+}
+
+/// What to erase from the activity logs. App events are erased if their window title or app name
+/// matches `app`, file events lose the paths that match `path`, and either only before `before`.
+/// Without `app` or `path`, everything before `before` is erased.
+#[derive(Debug, Clone, Default)]
+pub struct Purge {
+    pub app: Option<Regex>,
+    pub path: Option<Regex>,
+    pub before: Option<SystemTime>,
+}
+
+impl Purge {
+    pub fn new(app: Option<&str>, path: Option<&str>, before: Option<NaiveDate>) -> Result<Purge> {
+        let invalid = |err: regex::Error| RarianError::InvalidPattern(err.to_string());
+        Ok(Purge {
+            app: app.map(Regex::new).transpose().map_err(invalid)?,
+            path: path.map(glob_regex).transpose().map_err(invalid)?,
+            before: before.map(start_of_day),
+        })
+    }
+
+    fn is_before(&self, time: SystemTime) -> bool {
+        self.before.is_none_or(|before| time < before)
+    }
+
+    fn purges_apps(&self) -> bool {
+        self.app.is_some() || self.path.is_none()
+    }
+
+    fn purges_files(&self) -> bool {
+        self.path.is_some() || self.app.is_none()
+    }
+}
+
+/// A regex for a glob, where `**` matches across folders and `*` and `?` within one
+fn glob_regex(glob: &str) -> std::result::Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str(r"[^/\\]*"),
+            '?' => pattern.push_str(r"[^/\\]"),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    Regex::new(&(pattern + "$"))
+}
+
+fn start_of_day(date: NaiveDate) -> SystemTime {
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map(SystemTime::from)
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PurgeReport {
+    pub app_events: usize,
+    pub file_events: usize,
+    pub daily_totals: usize,
+    /// The snapshots the activity was erased from too
+    pub snapshots: usize,
+    /// The snapshots left as they were, being encrypted with another passphrase
+    pub snapshots_skipped: usize,
+}
+
+impl Display for PurgeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} app events, {} file events and {} daily totals removed",
+            self.app_events, self.file_events, self.daily_totals
+        )?;
+        if self.snapshots > 0 {
+            writeln!(f, "erased from {} snapshots too", self.snapshots)?;
+        }
+        if self.snapshots_skipped > 0 {
+            writeln!(
+                f,
+                "{} snapshots encrypted with another passphrase were left as they were",
+                self.snapshots_skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Erases the activity `purge` selects from the logs in the data path and its snapshots, including
+/// the daily totals of the apps it selects
+pub fn purge(data_path: &Path, purge: &Purge) -> Result<PurgeReport> {
+    let mut report = purge_logs(data_path, purge)?;
+    (report.snapshots, report.snapshots_skipped) = rewrite_snapshots(data_path, |snapshot| {
+        purge_logs(snapshot, purge).map(|_| ())
+    })?;
+    Ok(report)
+}
+
+fn purge_logs(data_path: &Path, purge: &Purge) -> Result<PurgeReport> {
+    let mut report = PurgeReport::default();
+    let matches_app = |app: &ActiveProcessEvent| {
+        purge.app.as_ref().is_none_or(|regex| {
+            regex.is_match(app.get_title()) || regex.is_match(app.get_app_name())
+        })
+    };
+    if purge.purges_apps() {
+        report.app_events =
+            FileCacher::new(data_path.join("apps.json"))?.rewrite(|app: ActiveProcessEvent| {
+                let erased = purge.is_before(app.get_start_time()) && matches_app(&app);
+                (!erased).then_some(app)
+            })?;
+        report.daily_totals =
+            FileCacher::new(data_path.join("daily.json"))?.rewrite(|total: DailyTotal| {
+                let erased = purge.is_before(start_of_day(total.date))
+                    && purge
+                        .app
+                        .as_ref()
+                        .is_none_or(|regex| regex.is_match(&total.app_title));
+                (!erased).then_some(total)
+            })?;
+    }
+    if purge.purges_files() {
+        report.file_events = FileCacher::new(data_path.join("files.json"))?.rewrite(
+            |mut file_event: FileEvent| {
+                if !purge.is_before(file_event.timestamp) {
+                    return Some(file_event);
+                }
+                file_event.event.paths.retain(|path| {
+                    purge
+                        .path
+                        .as_ref()
+                        .is_some_and(|regex| !regex.is_match(&path.to_string_lossy()))
+                });
+                (!file_event.event.paths.is_empty()).then_some(file_event)
+            },
+        )?;
+    }
+    Ok(report)
+}
+
+/// Removes the events older than the config keeps, adding up the time spent in each app per day
+/// first if the daily totals are kept
+pub fn enforce(data_path: &Path, config: &RetentionConfig, now: SystemTime) -> Result<PurgeReport> {
+    let mut report = PurgeReport::default();
+    let cutoff = |days: u64| now.checked_sub(DAY * days as u32);
+    if let Some(cutoff) = config.app_events_days.and_then(cutoff) {
+        let mut totals: BTreeMap<(NaiveDate, String), Duration> = BTreeMap::new();
+        report.app_events =
+            FileCacher::new(data_path.join("apps.json"))?.rewrite(|app: ActiveProcessEvent| {
+                if app.get_start_time() >= cutoff {
+                    return Some(app);
+                }
+                let day = local_date(app.get_start_time());
+                *totals
+                    .entry((day, app.get_title().to_string()))
+                    .or_default() += app.get_duration();
+                None
+            })?;
+        if config.keep_daily_totals && !totals.is_empty() {
+            let mut cacher = FileCacher::new(data_path.join("daily.json"))?;
+            for ((date, app_title), duration) in totals {
+                cacher.cache(&DailyTotal {
+                    date,
+                    app_title,
+                    duration,
+                })?;
+            }
+        }
+    }
+    if let Some(cutoff) = config.file_events_days.and_then(cutoff) {
+        report.file_events =
+            FileCacher::new(data_path.join("files.json"))?.rewrite(|file_event: FileEvent| {
+                (file_event.timestamp >= cutoff).then_some(file_event)
+            })?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod retention_test {
+    use super::*;
+    use crate::backup::{create, snapshots, SnapshotReason};
+    use notify::event::{EventKind, ModifyKind};
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::PathBuf;
+
+    fn app_event(title: &str, start: SystemTime, secs: u64) -> ActiveProcessEvent {
+        serde_json::from_value(serde_json::json!({
+            "process": {
+                "title": title,
+                "app_name": "browser",
+                "window_id": "1",
+                "process_path": "/bin/app",
+                "process_id": 1,
+            },
+            "active_start_time": start,
+            "active_duration": Duration::from_secs(secs),
+        }))
+        .unwrap()
+    }
+
+    fn file_event(paths: &[&str], timestamp: SystemTime) -> FileEvent {
+        let mut event = notify::Event::new(EventKind::Modify(ModifyKind::Any));
+        for path in paths {
+            event = event.add_path(PathBuf::from(path));
+        }
+        FileEvent { event, timestamp }
+    }
+
+    fn write_logs(data_path: &Path, apps: &[ActiveProcessEvent], files: &[FileEvent]) {
+        let _ = remove_dir_all(data_path);
+        create_dir_all(data_path).unwrap();
+        let mut cacher = FileCacher::new(data_path.join("apps.json")).unwrap();
+        for app in apps {
+            cacher.cache(app).unwrap();
+        }
+        let mut cacher = FileCacher::new(data_path.join("files.json")).unwrap();
+        for file in files {
+            cacher.cache(file).unwrap();
+        }
+    }
+
+    fn load<T: serde::de::DeserializeOwned>(data_path: &Path, store: &str) -> Vec<T> {
+        FileCacher::new(data_path.join(store))
+            .unwrap()
+            .load_from_cache()
+    }
+
+    #[test]
+    fn old_events_are_removed_and_app_time_is_kept_per_day() {
+        let data_path = PathBuf::from("./testData/retention");
+        let now = SystemTime::now();
+        let old = now - DAY * 40;
+        let apps = [
+            app_event("bank", old, 60),
+            app_event("bank", old + Duration::from_secs(60), 30),
+            app_event("mail", now - DAY, 10),
+        ];
+        let files = [
+            file_event(&["/src/old.rs"], now - DAY * 8),
+            file_event(&["/src/new.rs"], now - DAY),
+        ];
+        write_logs(&data_path, &apps, &files);
+        let config = RetentionConfig {
+            app_events_days: Some(30),
+            file_events_days: Some(7),
+            keep_daily_totals: true,
+        };
+        let report = enforce(&data_path, &config, now).unwrap();
+        let apps: Vec<ActiveProcessEvent> = load(&data_path, "apps.json");
+        let files: Vec<FileEvent> = load(&data_path, "files.json");
+        let totals = daily_totals(&data_path, &[local_date(old)]).unwrap();
+        remove_dir_all(&data_path).unwrap();
+
+        assert_eq!((report.app_events, report.file_events), (2, 1));
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].get_title(), "mail");
+        assert_eq!(files[0].event.paths, vec![PathBuf::from("/src/new.rs")]);
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].app_title, "bank");
+        assert_eq!(totals[0].duration, Duration::from_secs(90));
+    }
+
+    #[test]
+    fn purge_erases_only_the_selected_activity() {
+        let data_path = PathBuf::from("./testData/purge");
+        let now = SystemTime::now();
+        let apps = [
+            app_event("My Bank - Firefox", now - DAY * 3, 60),
+            app_event("My Bank - Firefox", now, 60),
+            app_event("Docs", now - DAY * 3, 60),
+        ];
+        let files = [
+            file_event(
+                &["/home/me/secret/a.txt", "/home/me/code/b.rs"],
+                now - DAY * 3,
+            ),
+            file_event(&["/home/me/secret/c.txt"], now - DAY * 3),
+        ];
+        write_logs(&data_path, &apps, &files);
+        let yesterday = local_date(now - DAY);
+        let snapshot = create(&data_path, SnapshotReason::Manual).unwrap();
+        let purged = Purge::new(Some("(?i)bank"), Some("**/secret/*"), Some(yesterday)).unwrap();
+        let report = purge(&data_path, &purged).unwrap();
+        let apps: Vec<ActiveProcessEvent> = load(&data_path, "apps.json");
+        let files: Vec<FileEvent> = load(&data_path, "files.json");
+        let snapshot_apps: Vec<ActiveProcessEvent> = load(&snapshot.path, "apps.json");
+        let snapshot_files = snapshot.path.join("files.json").exists();
+        let verified = snapshots(&data_path).unwrap()[0].verify();
+        remove_dir_all(&data_path).unwrap();
+
+        assert_eq!((report.app_events, report.file_events), (1, 1));
+        assert_eq!((report.snapshots, report.snapshots_skipped), (1, 0));
+        assert_eq!(snapshot_apps.len(), 2);
+        assert!(snapshot_files);
+        assert_eq!(verified, Ok(()));
+        let titles: Vec<&str> = apps.iter().map(|app| app.get_title()).collect();
+        assert_eq!(titles, vec!["My Bank - Firefox", "Docs"]);
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[0].event.paths,
+            vec![PathBuf::from("/home/me/code/b.rs")]
+        );
+    }
+}
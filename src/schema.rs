@@ -0,0 +1,273 @@
+use crate::bus::AuditRecord;
+use crate::cacher::{BadLine, FileCacher};
+use crate::error::Result;
+use crate::gatherer::app_gatherer::ActiveProcessEvent;
+use crate::gatherer::file_gatherer::FileEvent;
+use crate::notes::Note;
+use crate::oplog::Op;
+use crate::reminders::DeliveredReminder;
+use crate::retention::DailyTotal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+
+/// The version of the records written before they had envelopes
+pub const LEGACY_VERSION: u32 = 0;
+
+/// Every record is written in an envelope with the version of its format, so old records can be
+/// upgraded when they are read
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Envelope {
+    pub version: u32,
+    pub record: Value,
+}
+
+impl Envelope {
+    /// Reads an envelope, or a bare record from before envelopes as the legacy version
+    pub fn from_value(value: Value) -> Envelope {
+        match value {
+            Value::Object(ref object) if object.contains_key("version") => {
+                serde_json::from_value(value.clone()).unwrap_or(Envelope {
+                    version: LEGACY_VERSION,
+                    record: value,
+                })
+            }
+            value => Envelope {
+                version: LEGACY_VERSION,
+                record: value,
+            },
+        }
+    }
+}
+
+type Upgrade = fn(Value) -> std::result::Result<Value, String>;
+
+/// Upgrades a record of a cache from one version of its format to the next
+pub struct Migration {
+    pub store: &'static str,
+    pub from: u32,
+    pub upgrade: Upgrade,
+}
+
+/// The migrations of every cache. The version of a cache is one after its last migration, and
+/// bare records only need an envelope, so a cache without migrations is at version 1. When the
+/// format of a record changes in a way `#[serde(default)]` can't cover, add a migration from its
+/// current version here.
+pub const MIGRATIONS: &[Migration] = &[];
+
+pub struct Migrations<'a> {
+    migrations: &'a [Migration],
+}
+
+impl Default for Migrations<'static> {
+    fn default() -> Self {
+        Migrations::new(MIGRATIONS)
+    }
+}
+
+impl<'a> Migrations<'a> {
+    pub fn new(migrations: &'a [Migration]) -> Migrations<'a> {
+        Migrations { migrations }
+    }
+
+    /// The version records of `store` are written in
+    pub fn current_version(&self, store: &str) -> u32 {
+        self.migrations
+            .iter()
+            .filter(|migration| migration.store == store)
+            .map(|migration| migration.from + 1)
+            .max()
+            .unwrap_or(LEGACY_VERSION + 1)
+    }
+
+    /// Upgrades a record of `store` to the current version
+    pub fn upgrade(&self, store: &str, envelope: Envelope) -> std::result::Result<Value, String> {
+        let current = self.current_version(store);
+        if envelope.version > current {
+            return Err(format!(
+                "version {} is newer than this rarian reads ({})",
+                envelope.version, current
+            ));
+        }
+        let mut record = envelope.record;
+        for version in envelope.version..current {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|migration| migration.store == store && migration.from == version);
+            if let Some(migration) = migration {
+                record = (migration.upgrade)(record)
+                    .map_err(|err| format!("upgrading from version {}: {}", version, err))?;
+            }
+        }
+        Ok(record)
+    }
+}
+
+/// What `doctor` found in a cache
+#[derive(Debug, Clone, Default)]
+pub struct StoreReport {
+    pub store: String,
+    /// How many records are in each version
+    pub versions: BTreeMap<u32, usize>,
+    pub bad_lines: Vec<BadLine>,
+    /// Whether the bad lines were moved to the quarantine and the records upgraded
+    pub fixed: bool,
+}
+
+impl Display for StoreReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let versions: Vec<String> = self
+            .versions
+            .iter()
+            .map(|(version, count)| format!("{} at version {}", count, version))
+            .collect();
+        let versions = match versions.is_empty() {
+            true => "no records".to_string(),
+            false => versions.join(", "),
+        };
+        writeln!(f, "{}: {}", self.store, versions)?;
+        for bad_line in self.bad_lines.iter() {
+            writeln!(f, "  line {}: {}", bad_line.number, bad_line.reason)?;
+        }
+        if self.fixed && !self.bad_lines.is_empty() {
+            writeln!(f, "  moved to quarantine/{}", self.store)?;
+        }
+        Ok(())
+    }
+}
+
+fn examine<T: Serialize + DeserializeOwned>(
+    data_path: &Path,
+    store: &str,
+    fix: bool,
+) -> Result<Option<StoreReport>> {
+    let path = data_path.join(store);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut cacher = FileCacher::new(path)?;
+    let (versions, bad_lines) = cacher.check::<T>();
+    if fix {
+        cacher.quarantine(&bad_lines, &data_path.join("quarantine"))?;
+        cacher.rewrite(|record: T| Some(record))?;
+    }
+    Ok(Some(StoreReport {
+        store: store.to_string(),
+        versions,
+        bad_lines,
+        fixed: fix,
+    }))
+}
+
+/// Checks every cache in the data path for records that can't be read and records in old versions.
+/// With `fix`, moves the lines that can't be read to the `quarantine` folder and rewrites the other
+/// records in the current version.
+pub fn doctor(data_path: &Path, fix: bool) -> Result<Vec<StoreReport>> {
+    let reports = [
+        examine::<Note>(data_path, "notes.json", fix)?,
+        examine::<ActiveProcessEvent>(data_path, "apps.json", fix)?,
+        examine::<FileEvent>(data_path, "files.json", fix)?,
+        examine::<Op>(data_path, "ops.json", fix)?,
+        examine::<DeliveredReminder>(data_path, "reminders.json", fix)?,
+        examine::<AuditRecord>(data_path, "audit.json", fix)?,
+        examine::<DailyTotal>(data_path, "daily.json", fix)?,
+    ];
+    Ok(reports.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod schema_test {
+    use super::*;
+    use itertools::Itertools;
+    use serde_json::json;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            store: "notes.json",
+            from: 1,
+            upgrade: |mut record| {
+                let body = record["body"].take();
+                record["text"] = body;
+                Ok(record)
+            },
+        },
+        Migration {
+            store: "notes.json",
+            from: 2,
+            upgrade: |mut record| {
+                record["text"] = Value::String(format!("{}!", record["text"].as_str().unwrap()));
+                Ok(record)
+            },
+        },
+    ];
+
+    #[test]
+    fn records_are_upgraded_through_every_migration_since_their_version() {
+        let migrations = Migrations::new(MIGRATIONS);
+        assert_eq!(migrations.current_version("notes.json"), 3);
+        assert_eq!(migrations.current_version("apps.json"), 1);
+
+        let bare = Envelope::from_value(json!({"body": "old"}));
+        assert_eq!(bare.version, LEGACY_VERSION);
+        let upgraded = migrations.upgrade("notes.json", bare).unwrap();
+        assert_eq!(upgraded["text"], "old!");
+
+        let enveloped = Envelope::from_value(json!({"version": 2, "record": {"text": "new"}}));
+        let upgraded = migrations.upgrade("notes.json", enveloped).unwrap();
+        assert_eq!(upgraded["text"], "new!");
+
+        let future = Envelope::from_value(json!({"version": 4, "record": {}}));
+        assert!(migrations.upgrade("notes.json", future).is_err());
+    }
+
+    #[test]
+    fn doctor_reports_and_quarantines_the_lines_that_cant_be_read() {
+        let data_path = std::path::PathBuf::from("./testData/doctor");
+        let _ = std::fs::remove_dir_all(&data_path);
+        std::fs::create_dir_all(&data_path).unwrap();
+        let legacy = serde_json::to_string(&Note::new("legacy", vec![])).unwrap();
+        let future = json!({"version": 9, "record": Note::new("future", vec![])}).to_string();
+        let lines = [legacy.as_str(), "{\"text\": 3", "", future.as_str()];
+        std::fs::write(data_path.join("notes.json"), lines.join("\n") + "\n").unwrap();
+        crate::NoteTaker::new(&data_path)
+            .unwrap()
+            .add_note("current", vec![])
+            .unwrap();
+
+        let checked = doctor(&data_path, false).unwrap();
+        let fixed = doctor(&data_path, true).unwrap();
+        let rechecked = doctor(&data_path, false).unwrap();
+        let quarantined = std::fs::read_to_string(data_path.join("quarantine/notes.json")).unwrap();
+        let notes: Vec<String> = crate::NoteTaker::new(&data_path)
+            .unwrap()
+            .notes()
+            .map(|note| note.text.clone())
+            .sorted()
+            .collect();
+        std::fs::remove_dir_all(&data_path).unwrap();
+
+        let notes_report = &checked[0];
+        assert_eq!(notes_report.store, "notes.json");
+        assert_eq!(notes_report.versions, BTreeMap::from([(0, 1), (1, 1)]));
+        let numbers: Vec<usize> = notes_report
+            .bad_lines
+            .iter()
+            .map(|bad| bad.number)
+            .collect();
+        assert_eq!(numbers, vec![2, 4]);
+        assert!(notes_report.bad_lines[1].reason.contains("newer"));
+        assert!(fixed[0].fixed);
+        assert_eq!(rechecked[0].versions, BTreeMap::from([(1, 2)]));
+        assert!(rechecked[0].bad_lines.is_empty());
+        assert_eq!(
+            quarantined.lines().collect::<Vec<_>>(),
+            vec![lines[1], lines[3]]
+        );
+        assert_eq!(notes, vec!["current", "legacy"]);
+    }
+}
@@ -0,0 +1,571 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{Builder, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use ulid::Ulid;
+
+use crate::backup;
+use crate::bus::{AuditLog, Bus, Message, Middleware, Notification};
+use crate::config::Config;
+use crate::error::{RarianError, Result};
+use crate::gatherer::app_gatherer::AppGatherer;
+use crate::gatherer::config_watcher::ConfigWatcher;
+use crate::gatherer::file_gatherer::FileGatherer;
+use crate::lock::DataLock;
+use crate::notes::{Note, NoteSort, NoteTaker};
+use crate::relevance::NoteContext;
+use crate::reminders::Notifier;
+use crate::retention;
+use crate::sync::{self, GitSync, SyncOutcome};
+use crate::timeline::Timeline;
+
+/// Each this long an app stays focused while its notes are shown adds one to their weight
+const FOCUS_BOOST_INTERVAL: Duration = Duration::from_secs(60);
+/// The most a single stretch of focus adds to the weight of a note
+const MAX_FOCUS_BOOST: usize = 5;
+/// How often the reminders that are due at a time are checked
+const REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How often the events older than the retention config keeps are removed
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often the service checks whether the daily snapshot was taken
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// What boosting a note by hand adds to its weight
+const MANUAL_BOOST: usize = 5;
+
+fn notify(subscribers: &mut Vec<Sender<Notification>>, notification: Notification) {
+    subscribers.retain(|tx| tx.send(notification.clone()).is_ok());
+}
+
+/// Notifies subscribers that a change happened, or why it failed
+fn notify_change<T>(
+    subscribers: &mut Vec<Sender<Notification>>,
+    result: &Result<T>,
+    notification: Notification,
+) {
+    match result {
+        Ok(_) => notify(subscribers, notification),
+        Err(err) => notify(subscribers, Notification::Error(err.clone())),
+    }
+}
+
+/// Replies to a request, returning whether it succeeded for the middleware
+fn reply<T>(tx: Sender<Result<T>>, result: Result<T>) -> Result<()> {
+    let status = result.as_ref().map(|_| ()).map_err(Clone::clone);
+    // The requester may have timed out and stopped listening
+    let _ = tx.send(result);
+    status
+}
+
+/// A running rarian: the note store and both gatherers, owned by a service thread that answers
+/// requests on its `Bus` in order. Dropping it stops the gatherers and the thread.
+pub struct Rarian {
+    bus: Bus,
+    service_thread: Option<JoinHandle<()>>,
+    /// Keeps other processes from writing to the data path until the service thread has stopped
+    _lock: DataLock,
+}
+
+impl Rarian {
+    /// Loads the notes in the data path of `config` and starts gathering apps and file changes.
+    /// Fails with `InUse` if another process is running rarian on the same data path.
+    pub fn start(config: &Config) -> Result<Rarian> {
+        let mut middleware: Vec<Box<dyn Middleware>> = Vec::new();
+        if config.audit_log {
+            middleware.push(Box::new(AuditLog::new(config.data_path.clone())?));
+        }
+        Rarian::start_with_middleware(config, middleware)
+    }
+
+    /// Like `start`, with `middleware` run around every message the service thread handles
+    pub fn start_with_middleware(
+        config: &Config,
+        middleware: Vec<Box<dyn Middleware>>,
+    ) -> Result<Rarian> {
+        let notifier = config.notifier.notifier(&config.data_path)?;
+        Rarian::start_with_notifier(config, middleware, notifier)
+    }
+
+    /// Like `start_with_middleware`, with due reminders delivered to `notifier` rather than the one
+    /// in the config
+    pub fn start_with_notifier(
+        config: &Config,
+        middleware: Vec<Box<dyn Middleware>>,
+        notifier: Box<dyn Notifier>,
+    ) -> Result<Rarian> {
+        let lock = DataLock::acquire(&config.data_path)?;
+        backup::on_startup(&config.data_path, &config.backup)?;
+        let note_taker = NoteTaker::new(config.data_path.as_path())?;
+        let (tx, rx) = channel::<Message>();
+        let bus = Bus::new(tx);
+        let app_gatherer = AppGatherer::new(bus.clone(), config)?;
+        let file_gatherer = FileGatherer::new(bus.clone(), config)?;
+        let config_watcher = ConfigWatcher::new(bus.clone(), config)?;
+        let mut unreadable = note_taker.unreadable();
+        unreadable.extend(app_gatherer.unreadable());
+        let mut service = Service {
+            note_taker,
+            app_gatherer,
+            file_gatherer,
+            config_watcher,
+            config: config.clone(),
+            data_path: config.data_path.clone(),
+            subscribers: Vec::new(),
+            middleware,
+            notifier,
+            bus: bus.clone(),
+            git_sync: config
+                .sync
+                .clone()
+                .map(|sync_config| GitSync::new(&config.data_path, sync_config)),
+            sync_interval: config.sync.as_ref().and_then(|sync| sync.interval()),
+            syncing: false,
+            last_sync: None,
+            last_retention: None,
+            last_backup: None,
+            last_file: None,
+            shown: None,
+            unreadable,
+        };
+        let service_thread = Builder::new()
+            .name("rarian service".to_string())
+            .spawn(move || {
+                service.run(rx);
+                service.close();
+            })
+            .expect("failed to spawn thread");
+        Ok(Rarian {
+            bus,
+            service_thread: Some(service_thread),
+            _lock: lock,
+        })
+    }
+
+    /// A handle for making requests, which can be cloned and passed to other threads
+    pub fn bus(&self) -> Bus {
+        self.bus.clone()
+    }
+
+    /// Stops the gatherers and waits for the service thread to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(service_thread) = self.service_thread.take() {
+            // The service thread may have stopped already if a frontend asked it to quit
+            let _ = self.bus.quit();
+            let _ = service_thread.join();
+        }
+    }
+}
+
+impl Drop for Rarian {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// The notes last shown for the focused app, which gain weight the longer it stays focused
+struct ShownNotes {
+    app_title: String,
+    notes: Vec<Ulid>,
+    since: Instant,
+}
+
+struct Service {
+    note_taker: NoteTaker,
+    app_gatherer: AppGatherer,
+    /// Replaced when the paths it watches change
+    file_gatherer: FileGatherer,
+    config_watcher: ConfigWatcher,
+    /// The config as last applied
+    config: Config,
+    data_path: PathBuf,
+    subscribers: Vec<Sender<Notification>>,
+    middleware: Vec<Box<dyn Middleware>>,
+    notifier: Box<dyn Notifier>,
+    /// For the sync thread to report back on
+    bus: Bus,
+    git_sync: Option<GitSync>,
+    sync_interval: Option<Duration>,
+    syncing: bool,
+    last_sync: Option<Instant>,
+    last_retention: Option<Instant>,
+    last_backup: Option<Instant>,
+    last_file: Option<PathBuf>,
+    shown: Option<ShownNotes>,
+    /// The caches with lines that couldn't be read when rarian started, told to every subscriber
+    unreadable: Vec<RarianError>,
+}
+
+impl Service {
+    /// Handles messages until asked to quit
+    fn run(&mut self, rx: Receiver<Message>) {
+        // Reminders may have become due while rarian wasn't running
+        self.check_reminders(None);
+        let mut last_reminder_check = Instant::now();
+        loop {
+            // The bus of the `Rarian` handle is only dropped after it asked to quit, so this
+            // can't end on a disconnect before that
+            let message = match rx.recv_timeout(REMINDER_CHECK_INTERVAL) {
+                Ok(Message::Quit) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => None,
+            };
+            // A busy bus never times out, so the time is checked as well
+            if last_reminder_check.elapsed() >= REMINDER_CHECK_INTERVAL {
+                last_reminder_check = Instant::now();
+                self.check_reminders(None);
+            }
+            let sync_is_due = self.sync_interval.is_some_and(|interval| {
+                self.last_sync
+                    .is_none_or(|last_sync| last_sync.elapsed() >= interval)
+            });
+            if sync_is_due && !self.syncing {
+                if let Err(err) = self.start_sync() {
+                    notify(&mut self.subscribers, Notification::Error(err));
+                }
+            }
+            let retention_is_due = self
+                .last_retention
+                .is_none_or(|last_retention| last_retention.elapsed() >= RETENTION_INTERVAL);
+            if self.config.retention.is_enabled() && retention_is_due {
+                self.last_retention = Some(Instant::now());
+                let enforced =
+                    retention::enforce(&self.data_path, &self.config.retention, SystemTime::now());
+                if let Err(err) = enforced {
+                    notify(&mut self.subscribers, Notification::Error(err));
+                }
+            }
+            let backup_is_due = self
+                .last_backup
+                .is_none_or(|last_backup| last_backup.elapsed() >= BACKUP_INTERVAL);
+            if backup_is_due {
+                self.last_backup = Some(Instant::now());
+                if let Err(err) =
+                    backup::daily(&self.data_path, &self.config.backup, SystemTime::now())
+                {
+                    notify(&mut self.subscribers, Notification::Error(err));
+                }
+            }
+            let Some(message) = message else {
+                continue;
+            };
+            let name = message.name();
+            for middleware in self.middleware.iter_mut() {
+                middleware.before(&message);
+            }
+            let start = Instant::now();
+            let result = self.handle(message);
+            for middleware in self.middleware.iter_mut() {
+                middleware.after(name, &result, start.elapsed());
+            }
+        }
+    }
+
+    fn handle(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::RecentApps(request, tx) => {
+                reply(tx, Ok(self.app_gatherer.get_last_processes(request.count)))
+            }
+            Message::CurrentApp(_, tx) => reply(tx, Ok(self.app_gatherer.get_current())),
+            Message::AppNotes(request, tx) => {
+                reply(tx, Ok(self.note_taker.get_app_notes(&request.link)))
+            }
+            Message::RelatedNotes(request, tx) => {
+                let notes = self.related_notes(request.app_title, request.sort);
+                reply(tx, Ok(notes))
+            }
+            Message::AddNote(request, tx) => {
+                let result = self.note_taker.add_note(&request.text, request.links);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::EditNote(request, tx) => {
+                let result = self.note_taker.edit_note(&request.id, &request.text);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::ArchiveNote(request, tx) => {
+                let result = self.note_taker.archive_note(&request.id);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::BoostNote(request, tx) => {
+                let result = self
+                    .note_taker
+                    .boost_link(&request.id, &request.link, MANUAL_BOOST);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::PinNote(request, tx) => {
+                let result = self
+                    .note_taker
+                    .set_pinned(&request.id, &request.link, request.pinned);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::ReorderNotes(request, tx) => {
+                let result = self.note_taker.reorder(&request.link, &request.order);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::SetTask(request, tx) => {
+                let result = self.note_taker.set_task(&request.id, request.task);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::ToggleChecklistItem(request, tx) => {
+                let result = self
+                    .note_taker
+                    .toggle_checklist_item(&request.id, request.index);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                reply(tx, result)
+            }
+            Message::GetTasks(request, tx) => reply(tx, Ok(self.note_taker.tasks(&request.filter))),
+            Message::SetReminder(request, tx) => {
+                let result = self.note_taker.set_reminder(&request.id, request.reminder);
+                notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+                let result = reply(tx, result);
+                // A time that already passed is due right away
+                self.check_reminders(None);
+                result
+            }
+            Message::SyncNotes(_, tx) => {
+                let result = self.start_sync();
+                reply(tx, result)
+            }
+            Message::SyncFinished(result) => {
+                self.syncing = false;
+                let result = result.and_then(|outcome| self.apply_sync(&outcome));
+                if let Err(err) = &result {
+                    notify(&mut self.subscribers, Notification::Error(err.clone()));
+                }
+                result
+            }
+            Message::GetNoteLinks(request, tx) => {
+                reply(tx, self.note_taker.note_links(&request.id))
+            }
+            Message::GetTimeline(request, tx) => {
+                reply(tx, Timeline::load(&self.data_path, request.date))
+            }
+            Message::CurrentAppChanged => {
+                let current = self.app_gatherer.get_current();
+                let current_title = current.as_ref().map(|app| app.get_title());
+                if self.shown.as_ref().map(|shown| shown.app_title.as_str()) != current_title {
+                    self.boost_shown_notes();
+                }
+                let context = NoteContext::new(
+                    current_title.map(|title| title.to_string()),
+                    self.last_file.clone(),
+                );
+                self.check_reminders(Some(&context));
+                notify(
+                    &mut self.subscribers,
+                    Notification::CurrentAppChanged(current),
+                );
+                notify(&mut self.subscribers, Notification::RecentAppsChanged);
+                Ok(())
+            }
+            Message::FileChanged(path) => {
+                self.last_file = Some(path);
+                Ok(())
+            }
+            Message::ConfigChanged => {
+                let result = self.reload_config();
+                if let Err(err) = &result {
+                    notify(&mut self.subscribers, Notification::Error(err.clone()));
+                }
+                result
+            }
+            Message::Subscribe(tx) => {
+                let current = self.app_gatherer.get_current();
+                let _ = tx.send(Notification::CurrentAppChanged(current));
+                for err in self.unreadable.iter() {
+                    let _ = tx.send(Notification::Error(err.clone()));
+                }
+                self.subscribers.push(tx);
+                Ok(())
+            }
+            Message::ReportError(err) => {
+                notify(&mut self.subscribers, Notification::Error(err));
+                Ok(())
+            }
+            Message::Quit => Ok(()),
+        }
+    }
+
+    /// Applies the config file as it is now, keeping the config as it was if the file is invalid
+    fn reload_config(&mut self) -> Result<()> {
+        let config = self.config.reload()?;
+        if config == self.config {
+            return Ok(());
+        }
+        // What can fail is set up first, so a config that fails leaves the previous one running
+        let notifier = match config.notifier != self.config.notifier {
+            true => Some(config.notifier.notifier(&self.data_path)?),
+            false => None,
+        };
+        let watching_changed = config.watcher_paths != self.config.watcher_paths
+            || config.ignore_paths != self.config.ignore_paths
+            || config.comment_identifier != self.config.comment_identifier;
+        let file_gatherer = match watching_changed {
+            true => Some(FileGatherer::new(self.bus.clone(), &config)?),
+            false => None,
+        };
+        if let Some(notifier) = notifier {
+            self.notifier = notifier;
+        }
+        if let Some(file_gatherer) = file_gatherer {
+            std::mem::replace(&mut self.file_gatherer, file_gatherer).close();
+        }
+        if config.sleep_duration != self.config.sleep_duration {
+            self.app_gatherer.set_sleep_duration(config.sleep_duration);
+        }
+        if config.sync != self.config.sync {
+            self.git_sync = config
+                .sync
+                .clone()
+                .map(|sync_config| GitSync::new(&self.data_path, sync_config));
+            self.sync_interval = config.sync.as_ref().and_then(|sync| sync.interval());
+        }
+        self.config = config;
+        notify(
+            &mut self.subscribers,
+            Notification::ConfigChanged(self.config.clone()),
+        );
+        Ok(())
+    }
+
+    /// Stops the gatherers and the config watcher
+    fn close(self) {
+        self.app_gatherer.close();
+        self.file_gatherer.close();
+        self.config_watcher.close();
+    }
+
+    /// The notes related to an app, remembering them if it is focused so they can be boosted
+    fn related_notes(&mut self, app_title: String, sort: NoteSort) -> Vec<Note> {
+        let focused = self
+            .app_gatherer
+            .get_current()
+            .is_some_and(|current| current.get_title() == app_title);
+        let file_path = match focused {
+            true => self.last_file.clone(),
+            false => None,
+        };
+        let context = NoteContext::new(Some(app_title.clone()), file_path);
+        let notes = self.note_taker.ordered_notes(&context, sort);
+        if focused {
+            let since = match self.shown.take() {
+                Some(shown) if shown.app_title == app_title => shown.since,
+                _ => Instant::now(),
+            };
+            self.shown = Some(ShownNotes {
+                app_title,
+                notes: notes.iter().map(|note| note.id).collect(),
+                since,
+            });
+        }
+        notes
+    }
+
+    /// Syncs a snapshot of the notes on another thread, as git may take a while on the network.
+    /// Does nothing if a sync is running already.
+    fn start_sync(&mut self) -> Result<()> {
+        let Some(git_sync) = self.git_sync.clone() else {
+            return Err(RarianError::Sync(
+                "no [sync] remote in the config".to_string(),
+            ));
+        };
+        if self.syncing {
+            return Ok(());
+        }
+        self.syncing = true;
+        self.last_sync = Some(Instant::now());
+        let notes: Vec<Note> = self.note_taker.notes().cloned().collect();
+        let bus = self.bus.clone();
+        let spawned = Builder::new()
+            .name("rarian sync".to_string())
+            .spawn(move || {
+                // The service thread may have stopped meanwhile, and then nobody needs the result
+                let _ = bus.sync_finished(git_sync.sync(&notes));
+            });
+        if let Err(err) = spawned {
+            self.syncing = false;
+            return Err(RarianError::Sync(err.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Saves what a sync changed, merged with the edits made while it ran
+    fn apply_sync(&mut self, outcome: &SyncOutcome) -> Result<()> {
+        let changed = sync::apply(&mut self.note_taker, outcome)?;
+        if !changed.is_empty() {
+            notify(&mut self.subscribers, Notification::NotesChanged);
+        }
+        let conflicts: Vec<Note> = outcome
+            .conflicts
+            .iter()
+            .filter_map(|id| self.note_taker.get_note(id).cloned())
+            .collect();
+        if !conflicts.is_empty() {
+            notify(
+                &mut self.subscribers,
+                Notification::SyncConflicts(conflicts),
+            );
+        }
+        Ok(())
+    }
+
+    /// Fires the reminders that are due now, or in `context` if the focus just changed, and
+    /// delivers them to the notifier and the subscribers
+    fn check_reminders(&mut self, context: Option<&NoteContext>) {
+        let due = match self.note_taker.fire_reminders(SystemTime::now(), context) {
+            Ok(due) => due,
+            Err(err) => {
+                notify(&mut self.subscribers, Notification::Error(err));
+                return;
+            }
+        };
+        if due.is_empty() {
+            return;
+        }
+        notify(&mut self.subscribers, Notification::NotesChanged);
+        for note in due {
+            if let Err(err) = self.notifier.notify(&note) {
+                notify(&mut self.subscribers, Notification::Error(err));
+            }
+            notify(&mut self.subscribers, Notification::ReminderDue(note));
+        }
+    }
+
+    /// Adds the time the app was focused to the notes shown for it that are linked to it
+    fn boost_shown_notes(&mut self) {
+        let Some(shown) = self.shown.take() else {
+            return;
+        };
+        let intervals = shown.since.elapsed().as_secs() / FOCUS_BOOST_INTERVAL.as_secs();
+        let boost = (intervals as usize).min(MAX_FOCUS_BOOST);
+        if boost == 0 {
+            return;
+        }
+        let linked: Vec<Ulid> = shown
+            .notes
+            .into_iter()
+            .filter(|id| {
+                self.note_taker.get_note(id).is_some_and(|note| {
+                    note.links().iter().any(|link| link.link == shown.app_title)
+                })
+            })
+            .collect();
+        if linked.is_empty() {
+            return;
+        }
+        let result = linked
+            .iter()
+            .try_for_each(|id| self.note_taker.boost_link(id, &shown.app_title, boost));
+        notify_change(&mut self.subscribers, &result, Notification::NotesChanged);
+    }
+}
@@ -0,0 +1,63 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::thread::{sleep, Builder, JoinHandle};
+use std::time::Duration;
+
+use crate::bus::Bus;
+use crate::error::RarianError;
+
+const MAX_RESTARTS: usize = 5;
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+thread_local! {
+    static SUPERVISED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current thread was started by `spawn_supervised`, whose panics are reported to the
+/// service thread instead of ending rarian
+pub fn is_supervised_thread() -> bool {
+    SUPERVISED.with(|supervised| supervised.get())
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+/// Runs `task` on a new thread, and runs it again if it panics, up to `MAX_RESTARTS` times. Every
+/// crash is reported to the service thread.
+pub fn spawn_supervised<F>(name: &str, bus: Bus, mut task: F) -> JoinHandle<()>
+where
+    F: FnMut() + Send + 'static,
+{
+    let name = name.to_string();
+    Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            SUPERVISED.with(|supervised| supervised.set(true));
+            for restarts in 0..=MAX_RESTARTS {
+                match catch_unwind(AssertUnwindSafe(&mut task)) {
+                    Ok(()) => return,
+                    Err(panic) => {
+                        let next_step = match restarts < MAX_RESTARTS {
+                            true => "restarting",
+                            false => "giving up",
+                        };
+                        let error = RarianError::ThreadCrashed {
+                            name: name.clone(),
+                            message: format!("{}, {}", panic_message(&panic), next_step),
+                        };
+                        if bus.report_error(error).is_err() {
+                            return;
+                        }
+                        sleep(RESTART_DELAY);
+                    }
+                }
+            }
+        })
+        .expect("failed to spawn thread")
+}
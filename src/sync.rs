@@ -0,0 +1,506 @@
+use crate::crypto::is_encrypted;
+use crate::error::{RarianError, Result};
+use crate::notes::{Link, Note, NoteStatus, NoteTaker};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use ulid::Ulid;
+
+/// The folder of the note files in the sync repository
+const NOTES_DIR: &str = "notes";
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+/// Where the notes are synced to, from the `[sync]` table of the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncConfig {
+    /// A git remote, like a URL or the path of a bare repository
+    pub remote: String,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    /// How often the running app syncs, never if not set
+    #[serde(default)]
+    pub interval_minutes: Option<u64>,
+}
+
+impl SyncConfig {
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval_minutes
+            .filter(|minutes| *minutes > 0)
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+}
+
+fn to_json(note: &Note) -> String {
+    serde_json::to_string_pretty(note).unwrap() + "\n"
+}
+
+fn same(a: &Note, b: &Note) -> bool {
+    to_json(a) == to_json(b)
+}
+
+/// The value of a field after a three-way merge: the side that changed it, or the newer side if
+/// both did
+fn pick<T: PartialEq + Clone>(base: Option<&T>, newer: &T, older: &T) -> T {
+    if newer == older || Some(older) == base {
+        newer.clone()
+    } else if Some(newer) == base {
+        older.clone()
+    } else {
+        newer.clone()
+    }
+}
+
+/// The links of both sides, without the ones either side removed since `base`. Weights only grow,
+/// so the heavier one is kept, and the pin and position of each link are merged like fields.
+fn merge_links(base: Option<&[Link]>, newer: &[Link], older: &[Link]) -> Vec<Link> {
+    let find = |links: &[Link], name: &str| links.iter().find(|l| l.link == name).cloned();
+    let mut merged = Vec::new();
+    for name in newer.iter().chain(older).map(|link| link.link.as_str()) {
+        if merged.iter().any(|link: &Link| link.link == name) {
+            continue;
+        }
+        let base_link = base.and_then(|base| find(base, name));
+        let link = match (find(newer, name), find(older, name)) {
+            (Some(newer), Some(older)) => Link {
+                weight: newer.weight.max(older.weight),
+                pinned: pick(
+                    base_link.as_ref().map(|base| &base.pinned),
+                    &newer.pinned,
+                    &older.pinned,
+                ),
+                position: pick(
+                    base_link.as_ref().map(|base| &base.position),
+                    &newer.position,
+                    &older.position,
+                ),
+                ..newer
+            },
+            // A link in the base that one side lacks was removed there
+            (Some(_), None) | (None, Some(_)) if base_link.is_some() => continue,
+            (Some(link), None) | (None, Some(link)) => link,
+            (None, None) => continue,
+        };
+        merged.push(link);
+    }
+    merged
+}
+
+/// Merges two versions of a note that were changed since `base`, the version they share if any.
+/// The result doesn't depend on which side is which, so every device merges to the same note. When
+/// both sides changed the text, the newer text wins and the other one is kept as the conflict of
+/// the note. Returns whether there was a conflict.
+pub fn merge_notes(base: Option<&Note>, ours: &Note, theirs: &Note) -> (Note, bool) {
+    if same(ours, theirs) || base.is_some_and(|base| same(base, theirs)) {
+        return (ours.clone(), false);
+    }
+    if base.is_some_and(|base| same(base, ours)) {
+        return (theirs.clone(), false);
+    }
+    let key = |note: &Note| {
+        (
+            note.updated_date.unwrap_or(note.creation_date),
+            to_json(note),
+        )
+    };
+    let (newer, older) = match key(ours) >= key(theirs) {
+        true => (ours, theirs),
+        false => (theirs, ours),
+    };
+    let mut merged = newer.clone();
+    let base_text = base.map(|base| base.text.clone());
+    merged.text = pick(base_text.as_ref(), &newer.text, &older.text);
+    let conflict = newer.text != older.text
+        && base_text
+            .as_ref()
+            .is_none_or(|base| *base != newer.text && *base != older.text);
+    merged.conflict = match conflict {
+        true => Some(older.text.clone()),
+        false => pick(
+            base.map(|base| &base.conflict),
+            &newer.conflict,
+            &older.conflict,
+        ),
+    };
+    merged.status = match (&newer.status, &older.status) {
+        (NoteStatus::Archived, _) | (_, NoteStatus::Archived)
+            if base.is_none_or(|base| base.status == NoteStatus::Active) =>
+        {
+            NoteStatus::Archived
+        }
+        _ => pick(base.map(|base| &base.status), &newer.status, &older.status),
+    };
+    merged.task = pick(base.map(|base| &base.task), &newer.task, &older.task);
+    merged.reminder = pick(
+        base.map(|base| &base.reminder),
+        &newer.reminder,
+        &older.reminder,
+    );
+    merged.creation_date = newer.creation_date.min(older.creation_date);
+    merged.updated_date = newer.updated_date.max(older.updated_date);
+    merged.set_links(merge_links(
+        base.map(|base| base.links()),
+        newer.links(),
+        older.links(),
+    ));
+    (merged, conflict)
+}
+
+/// A note that the sync changed, with the local version it was merged from
+#[derive(Debug, Clone)]
+pub struct SyncedNote {
+    /// The note as it was when the sync started, `None` if it came from another device
+    pub local: Option<Note>,
+    pub synced: Note,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncOutcome {
+    pub changed: Vec<SyncedNote>,
+    /// The notes whose text was changed on two devices
+    pub conflicts: Vec<Ulid>,
+    pub pushed: bool,
+}
+
+/// Syncs the notes through a git repository in the data path, with a file per note, and a remote
+/// shared with other devices
+#[derive(Debug, Clone)]
+pub struct GitSync {
+    repo: PathBuf,
+    config: SyncConfig,
+}
+
+impl GitSync {
+    pub fn new(data_path: &Path, config: SyncConfig) -> GitSync {
+        GitSync {
+            repo: data_path.join("sync"),
+            config,
+        }
+    }
+
+    fn run_git(&self, args: &[&str]) -> std::result::Result<String, String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo)
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .output()
+            .map_err(|err| format!("failed to run git: {}", err))?;
+        match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+            false => Err(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+        }
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        self.run_git(args).map_err(RarianError::Sync)
+    }
+
+    /// The commit a revision points to, if it exists
+    fn commit_of(&self, revision: &str) -> Option<String> {
+        self.run_git(&[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", revision),
+        ])
+        .ok()
+        .map(|commit| commit.trim().to_string())
+    }
+
+    fn init(&self) -> Result<()> {
+        let sync_error = |err: std::io::Error| RarianError::Sync(err.to_string());
+        create_dir_all(self.repo.join(NOTES_DIR)).map_err(sync_error)?;
+        if !self.repo.join(".git").exists() {
+            self.git(&["init", "--quiet"])?;
+            self.git(&[
+                "symbolic-ref",
+                "HEAD",
+                &format!("refs/heads/{}", self.config.branch),
+            ])?;
+            self.git(&["config", "user.name", "rarian"])?;
+            self.git(&["config", "user.email", "rarian@localhost"])?;
+        }
+        match self.run_git(&["remote", "get-url", "origin"]) {
+            Ok(url) if url.trim() == self.config.remote => Ok(()),
+            Ok(_) => self
+                .git(&["remote", "set-url", "origin", &self.config.remote])
+                .map(|_| ()),
+            Err(_) => self
+                .git(&["remote", "add", "origin", &self.config.remote])
+                .map(|_| ()),
+        }
+    }
+
+    fn note_path(id: &Ulid) -> String {
+        format!("{}/{}.json", NOTES_DIR, id)
+    }
+
+    fn write_note(&self, note: &Note) -> Result<()> {
+        let path = self.repo.join(GitSync::note_path(&note.id));
+        let json = to_json(note);
+        if read_to_string(&path).is_ok_and(|existing| existing == json) {
+            return Ok(());
+        }
+        write(&path, json).map_err(|err| RarianError::Sync(err.to_string()))
+    }
+
+    fn commit(&self, message: &str) -> Result<()> {
+        self.git(&["add", "--all"])?;
+        if !self.git(&["status", "--porcelain"])?.trim().is_empty() {
+            self.git(&["commit", "--quiet", "-m", message])?;
+        }
+        Ok(())
+    }
+
+    /// A note file at a revision, `None` if it isn't there
+    fn note_at(&self, revision: &str, path: &str) -> Result<Option<Note>> {
+        match self.run_git(&["show", &format!("{}:{}", revision, path)]) {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|err| RarianError::Sync(format!("invalid note {}: {}", path, err))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The note files that changed from `from` to `to`, or every note file at `to`
+    fn changed_files(&self, from: Option<&str>, to: &str) -> Result<Vec<String>> {
+        let files = match from {
+            Some(from) => self.git(&["diff", "--name-only", from, to, "--", NOTES_DIR])?,
+            None => self.git(&["ls-tree", "-r", "--name-only", to, "--", NOTES_DIR])?,
+        };
+        Ok(files.lines().map(|file| file.to_string()).collect())
+    }
+
+    /// Commits the local notes, merges them with the ones on the remote and pushes the result.
+    /// Returns the notes that differ from `local` afterwards, for the caller to apply.
+    pub fn sync(&self, local: &[Note]) -> Result<SyncOutcome> {
+        if self.repo.parent().is_some_and(is_encrypted) {
+            return Err(RarianError::Sync(
+                "the notes are encrypted and the repository would hold them in plaintext"
+                    .to_string(),
+            ));
+        }
+        self.init()?;
+        for note in local {
+            self.write_note(note)?;
+        }
+        self.commit("Sync local notes")?;
+        let branch = &self.config.branch;
+        self.git(&["fetch", "--quiet", "origin"])?;
+        let remote_ref = format!("origin/{}", branch);
+        let mut outcome = SyncOutcome::default();
+        let mut changed_files: Vec<String> = Vec::new();
+        if let Some(remote) = self.commit_of(&remote_ref) {
+            match self.commit_of("HEAD") {
+                None => {
+                    self.git(&["reset", "--quiet", "--hard", &remote])?;
+                    changed_files = self.changed_files(None, &remote)?;
+                }
+                Some(head) => {
+                    let base = self
+                        .run_git(&["merge-base", &head, &remote])
+                        .ok()
+                        .map(|base| base.trim().to_string());
+                    if base.as_ref() == Some(&remote) {
+                        // The remote has nothing new
+                    } else if base.as_ref() == Some(&head) {
+                        self.git(&["merge", "--quiet", "--ff-only", &remote])?;
+                        changed_files = self.changed_files(Some(&head), &remote)?;
+                    } else {
+                        changed_files =
+                            self.merge(base.as_deref(), &head, &remote, &mut outcome)?;
+                    }
+                }
+            }
+        }
+
+        let local: HashMap<Ulid, &Note> = local.iter().map(|note| (note.id, note)).collect();
+        for file in changed_files.iter().collect::<HashSet<_>>() {
+            let Some(synced) = self.note_at("HEAD", file)? else {
+                continue;
+            };
+            let local = local.get(&synced.id).copied();
+            if local.is_none_or(|local| !same(local, &synced)) {
+                outcome.changed.push(SyncedNote {
+                    local: local.cloned(),
+                    synced,
+                });
+            }
+        }
+        if self.commit_of("HEAD").is_some() {
+            self.git(&["push", "--quiet", "origin", &format!("HEAD:{}", branch)])?;
+            outcome.pushed = true;
+        }
+        Ok(outcome)
+    }
+
+    /// Merges diverged histories note by note, as git can't merge JSON. Returns the note files
+    /// that changed on the remote.
+    fn merge(
+        &self,
+        base: Option<&str>,
+        head: &str,
+        remote: &str,
+        outcome: &mut SyncOutcome,
+    ) -> Result<Vec<String>> {
+        let theirs_changed = self.changed_files(base, remote)?;
+        let ours_changed: HashSet<String> = self.changed_files(base, head)?.into_iter().collect();
+        // Keep the local tree and record the remote as merged, then write the merged notes
+        self.git(&[
+            "merge",
+            "--quiet",
+            "--no-commit",
+            "--allow-unrelated-histories",
+            "-s",
+            "ours",
+            remote,
+        ])?;
+        // A merge left half done would block every later sync
+        if let Err(err) =
+            self.write_merge(base, head, remote, &theirs_changed, &ours_changed, outcome)
+        {
+            let _ = self.run_git(&["merge", "--abort"]);
+            return Err(err);
+        }
+        Ok(theirs_changed)
+    }
+
+    /// Writes the merged notes of a merge in progress and commits it
+    fn write_merge(
+        &self,
+        base: Option<&str>,
+        head: &str,
+        remote: &str,
+        theirs_changed: &[String],
+        ours_changed: &HashSet<String>,
+        outcome: &mut SyncOutcome,
+    ) -> Result<()> {
+        for file in theirs_changed.iter() {
+            let Some(theirs) = self.note_at(remote, file)? else {
+                continue;
+            };
+            let merged = match ours_changed.contains(file) {
+                true => match self.note_at(head, file)? {
+                    Some(ours) => {
+                        let base_note = match base {
+                            Some(base) => self.note_at(base, file)?,
+                            None => None,
+                        };
+                        let (merged, conflict) = merge_notes(base_note.as_ref(), &ours, &theirs);
+                        if conflict {
+                            outcome.conflicts.push(merged.id);
+                        }
+                        merged
+                    }
+                    None => theirs,
+                },
+                false => theirs,
+            };
+            self.write_note(&merged)?;
+        }
+        self.git(&["add", "--all"])?;
+        self.git(&[
+            "commit",
+            "--quiet",
+            "--no-edit",
+            "-m",
+            "Merge notes from other devices",
+        ])?;
+        Ok(())
+    }
+}
+
+/// Saves the notes a sync changed, merged once more with any local change made while it ran.
+/// Returns the notes that changed.
+pub fn apply(note_taker: &mut NoteTaker, outcome: &SyncOutcome) -> Result<Vec<Ulid>> {
+    let mut changed = Vec::new();
+    for synced_note in outcome.changed.iter() {
+        let current = note_taker.get_note(&synced_note.synced.id);
+        let merged = match current {
+            Some(current) => {
+                merge_notes(synced_note.local.as_ref(), current, &synced_note.synced).0
+            }
+            None => synced_note.synced.clone(),
+        };
+        if current.is_none_or(|current| !same(current, &merged)) {
+            changed.push(merged.id);
+            note_taker.insert_note(merged)?;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod sync_test {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn edited(note: &Note, text: &str, seconds: u64) -> Note {
+        let mut note = note.clone();
+        note.text = text.to_string();
+        note.updated_date = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        note
+    }
+
+    #[test]
+    fn merges_are_the_same_from_both_sides() {
+        let base = Note::new("Plan", vec![Link::new("Code".to_string(), 1)]);
+        let mut ours = edited(&base, "Plan\n- ship it", 100);
+        ours.add_link(Link::new("Firefox".to_string(), 2));
+        let mut theirs = edited(&base, "Plan", 50);
+        theirs.status = NoteStatus::Archived;
+
+        let (merged, conflict) = merge_notes(Some(&base), &ours, &theirs);
+        let (reverse, _) = merge_notes(Some(&base), &theirs, &ours);
+        assert!(!conflict);
+        assert_eq!(to_json(&merged), to_json(&reverse));
+        assert_eq!(merged.text, "Plan\n- ship it");
+        assert_eq!(merged.status, NoteStatus::Archived);
+        assert_eq!(merged.links().len(), 2);
+
+        let theirs = edited(&base, "Plan\n- wait", 200);
+        let (merged, conflict) = merge_notes(Some(&base), &ours, &theirs);
+        let (reverse, _) = merge_notes(Some(&base), &theirs, &ours);
+        assert!(conflict);
+        assert_eq!(to_json(&merged), to_json(&reverse));
+        assert_eq!(merged.text, "Plan\n- wait");
+        assert_eq!(merged.conflict.as_deref(), Some("Plan\n- ship it"));
+    }
+
+    #[test]
+    fn links_removed_on_one_side_stay_removed_and_pins_are_kept() {
+        let mut base = Note::new(
+            "Plan",
+            vec![
+                Link::new("Code".to_string(), 1),
+                Link::new("Firefox".to_string(), 1),
+            ],
+        );
+        base.updated_date = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10));
+        let mut ours = edited(&base, "Plan", 100);
+        ours.set_links(vec![base.links()[0].clone()]);
+        let mut theirs = edited(&base, "Plan\n- ship it", 50);
+        let mut pinned = theirs.links().to_vec();
+        pinned[0].pinned = true;
+        pinned[0].position = Some(2);
+        theirs.set_links(pinned);
+
+        let (merged, _) = merge_notes(Some(&base), &ours, &theirs);
+        let (reverse, _) = merge_notes(Some(&base), &theirs, &ours);
+        assert_eq!(to_json(&merged), to_json(&reverse));
+        let links: Vec<&str> = merged.links().iter().map(|l| l.link.as_str()).collect();
+        assert_eq!(links, vec!["Code"]);
+        assert!(merged.links()[0].pinned);
+        assert_eq!(merged.links()[0].position, Some(2));
+    }
+}
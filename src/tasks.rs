@@ -0,0 +1,190 @@
+use crate::notes::Note;
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+static CHECKLIST_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\s*[-*+] \[)([ xX])(\] ?)(.*)$").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl Priority {
+    /// The next priority, for cycling through them with a single key. No priority comes after low.
+    pub fn next(priority: Option<Priority>) -> Option<Priority> {
+        match priority {
+            None => Some(Priority::High),
+            Some(Priority::High) => Some(Priority::Medium),
+            Some(Priority::Medium) => Some(Priority::Low),
+            Some(Priority::Low) => None,
+        }
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::High => write!(f, "high"),
+            Priority::Medium => write!(f, "medium"),
+            Priority::Low => write!(f, "low"),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        match priority.to_lowercase().as_str() {
+            "high" | "h" => Ok(Priority::High),
+            "medium" | "m" => Ok(Priority::Medium),
+            "low" | "l" => Ok(Priority::Low),
+            _ => Err(format!(
+                "unknown priority {}, expected high, medium or low",
+                priority
+            )),
+        }
+    }
+}
+
+/// What makes a note a task
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Task {
+    pub done: bool,
+    pub due: Option<NaiveDate>,
+    pub priority: Option<Priority>,
+}
+
+impl Task {
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        !self.done && self.due.is_some_and(|due| due < today)
+    }
+}
+
+/// A Markdown `- [ ]` item in the text of a note
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecklistItem {
+    /// The line of the text the item is on
+    pub line: usize,
+    pub done: bool,
+    pub text: String,
+}
+
+/// The `- [ ]` and `- [x]` items in `text`, in order
+pub fn checklist(text: &str) -> Vec<ChecklistItem> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            CHECKLIST_REGEX
+                .captures(text)
+                .map(|captures| ChecklistItem {
+                    line,
+                    done: &captures[2] != " ",
+                    text: captures[4].trim().to_string(),
+                })
+        })
+        .collect()
+}
+
+/// `text` with the checklist item at `index` checked if it wasn't, and unchecked if it was
+pub fn toggle_checklist_item(text: &str, index: usize) -> Option<String> {
+    let item = checklist(text).into_iter().nth(index)?;
+    let lines: Vec<String> = text
+        .split('\n')
+        .enumerate()
+        .map(|(line, text)| match line == item.line {
+            true => CHECKLIST_REGEX
+                .replace(text, |captures: &regex::Captures| {
+                    let mark = if item.done { " " } else { "x" };
+                    format!("{}{}{}{}", &captures[1], mark, &captures[3], &captures[4])
+                })
+                .to_string(),
+            false => text.to_string(),
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+/// Which tasks `rarian tasks` and the tasks view list. Every filter that is set has to match.
+#[derive(Debug, Clone, Default)]
+pub struct TaskFilter {
+    pub include_done: bool,
+    /// Only tasks due on or before this day
+    pub due_before: Option<NaiveDate>,
+    pub priority: Option<Priority>,
+    /// Only tasks linked to this app or file
+    pub link: Option<String>,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, note: &Note) -> bool {
+        let Some(task) = &note.task else {
+            return false;
+        };
+        (self.include_done || !task.done)
+            && self
+                .due_before
+                .is_none_or(|before| task.due.is_some_and(|due| due <= before))
+            && self
+                .priority
+                .is_none_or(|priority| task.priority == Some(priority))
+            && self
+                .link
+                .as_ref()
+                .is_none_or(|link| note.link_to(link).is_some())
+    }
+}
+
+/// Open tasks before done ones, then the earliest due, the highest priority and the oldest first
+pub fn task_order(a: &Note, b: &Note) -> Ordering {
+    let key = |note: &Note| {
+        let task = note.task.clone().unwrap_or_default();
+        (
+            task.done,
+            task.due.is_none(),
+            task.due,
+            task.priority.is_none(),
+            task.priority,
+            note.creation_date,
+        )
+    };
+    key(a).cmp(&key(b))
+}
+
+#[cfg(test)]
+mod tasks_test {
+    use super::*;
+
+    #[test]
+    fn checklist_items_are_parsed_and_toggled() {
+        let text = "Release\n- [ ] tag\n  * [x] changelog\n- [] not an item\n- [X] publish";
+        let items = checklist(text);
+        let summary: Vec<(usize, bool, &str)> = items
+            .iter()
+            .map(|item| (item.line, item.done, item.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, false, "tag"),
+                (2, true, "changelog"),
+                (4, true, "publish")
+            ]
+        );
+        let toggled = toggle_checklist_item(text, 0).unwrap();
+        let toggled = toggle_checklist_item(&toggled, 1).unwrap();
+        assert_eq!(
+            toggled,
+            "Release\n- [x] tag\n  * [ ] changelog\n- [] not an item\n- [X] publish"
+        );
+        assert_eq!(toggle_checklist_item(text, 3), None);
+    }
+}
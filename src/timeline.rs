@@ -0,0 +1,333 @@
+use crate::cacher::{FileCacher, LoadFromCache};
+use crate::error::Result;
+use crate::gatherer::app_gatherer::ActiveProcessEvent;
+use crate::gatherer::file_gatherer::FileEvent;
+use crate::notes::{Note, NoteStatus};
+use chrono::{DateTime, Local, NaiveDate};
+use notify::EventKind;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteAction {
+    Created,
+    Edited,
+    Archived,
+}
+
+impl Display for NoteAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteAction::Created => write!(f, "created"),
+            NoteAction::Edited => write!(f, "edited"),
+            NoteAction::Archived => write!(f, "archived"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TimelineKind {
+    AppFocus {
+        duration: Duration,
+    },
+    FileChange {
+        path: PathBuf,
+        change: String,
+    },
+    Note {
+        id: Ulid,
+        action: NoteAction,
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelineEntry {
+    pub time: SystemTime,
+    pub kind: TimelineKind,
+    /// The title of the window that was focused when this entry happened
+    pub app_title: Option<String>,
+}
+
+impl TimelineEntry {
+    pub fn is_app_focus(&self) -> bool {
+        matches!(self.kind, TimelineKind::AppFocus { .. })
+    }
+}
+
+impl Display for TimelineEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = DateTime::<Local>::from(self.time).format("%H:%M:%S");
+        match &self.kind {
+            TimelineKind::AppFocus { duration } => write!(
+                f,
+                "{} {} ({})",
+                time,
+                self.app_title.as_deref().unwrap_or_default(),
+                format_duration(duration)
+            ),
+            TimelineKind::FileChange { path, change } => {
+                write!(f, "{}   file {}: {}", time, change, path.display())
+            }
+            TimelineKind::Note { action, text, .. } => {
+                let first_line = text.lines().next().unwrap_or_default();
+                write!(f, "{}   note {}: {}", time, action, first_line)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub date: NaiveDate,
+    pub entries: Vec<TimelineEntry>,
+}
+
+impl Timeline {
+    pub fn load(data_path: &Path, date: NaiveDate) -> Result<Timeline> {
+        let apps: Vec<ActiveProcessEvent> =
+            FileCacher::new(data_path.join("apps.json"))?.load_from_cache();
+        let files: Vec<FileEvent> =
+            FileCacher::new(data_path.join("files.json"))?.load_from_cache();
+        let notes: Vec<Note> = FileCacher::new(data_path.join("notes.json"))?.load_from_cache();
+        Ok(Timeline::from_records(date, apps, files, notes))
+    }
+
+    /// Reads the logs once and builds a timeline for each of `dates`
+    pub fn load_days(data_path: &Path, dates: &[NaiveDate]) -> Result<Vec<Timeline>> {
+        let apps: Vec<ActiveProcessEvent> =
+            FileCacher::new(data_path.join("apps.json"))?.load_from_cache();
+        let files: Vec<FileEvent> =
+            FileCacher::new(data_path.join("files.json"))?.load_from_cache();
+        let notes: Vec<Note> = FileCacher::new(data_path.join("notes.json"))?.load_from_cache();
+        Ok(dates
+            .iter()
+            .map(|date| Timeline::from_records(*date, apps.clone(), files.clone(), notes.clone()))
+            .collect())
+    }
+
+    /// Merges the three logs into one chronological stream for `date`, tagging every file and note
+    /// entry with the app that was focused when it happened.
+    pub fn from_records(
+        date: NaiveDate,
+        apps: Vec<ActiveProcessEvent>,
+        files: Vec<FileEvent>,
+        notes: Vec<Note>,
+    ) -> Timeline {
+        let mut apps: Vec<ActiveProcessEvent> = apps
+            .into_iter()
+            .filter(|app| local_date(app.get_start_time()) == date)
+            .collect();
+        apps.sort_by_key(|app| app.get_start_time());
+        let focused_at = |time: SystemTime| -> Option<String> {
+            apps.iter()
+                .rev()
+                .find(|app| {
+                    app.get_start_time() <= time && time < app.get_start_time() + app.get_duration()
+                })
+                .map(|app| app.get_title().to_string())
+        };
+
+        let mut entries: Vec<TimelineEntry> = apps
+            .iter()
+            .map(|app| TimelineEntry {
+                time: app.get_start_time(),
+                kind: TimelineKind::AppFocus {
+                    duration: app.get_duration(),
+                },
+                app_title: Some(app.get_title().to_string()),
+            })
+            .collect();
+
+        let mut last_file_change: Option<(PathBuf, Option<String>)> = None;
+        for file_event in files
+            .into_iter()
+            .filter(|file_event| local_date(file_event.timestamp) == date)
+        {
+            let change = match file_event.event.kind {
+                EventKind::Create(_) => "created",
+                EventKind::Modify(_) => "modified",
+                EventKind::Remove(_) => "removed",
+                _ => continue,
+            };
+            let app_title = focused_at(file_event.timestamp);
+            for path in file_event.event.paths {
+                // Editors emit several events per save, only keep the first in every focus span
+                let key = (path.clone(), app_title.clone());
+                if last_file_change.as_ref() == Some(&key) {
+                    continue;
+                }
+                last_file_change = Some(key);
+                entries.push(TimelineEntry {
+                    time: file_event.timestamp,
+                    kind: TimelineKind::FileChange {
+                        path,
+                        change: change.to_string(),
+                    },
+                    app_title: app_title.clone(),
+                });
+            }
+        }
+
+        for (time, note, action) in note_history(notes) {
+            if local_date(time) != date {
+                continue;
+            }
+            entries.push(TimelineEntry {
+                time,
+                kind: TimelineKind::Note {
+                    id: note.id,
+                    action,
+                    text: note.text,
+                },
+                app_title: focused_at(time),
+            });
+        }
+
+        entries.sort_by_key(|entry| entry.time);
+        Timeline { date, entries }
+    }
+}
+
+impl Display for Timeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.date.format("%A %Y-%m-%d"))?;
+        if self.entries.is_empty() {
+            return writeln!(f, "no activity recorded");
+        }
+        for entry in self.entries.iter() {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays the notes log, which holds a full snapshot of a note on every change, into the actions
+/// that produced each snapshot.
+pub fn note_history(notes: Vec<Note>) -> Vec<(SystemTime, Note, NoteAction)> {
+    let mut previous: HashMap<Ulid, Note> = HashMap::new();
+    let mut history = Vec::new();
+    for note in notes {
+        let action = match previous.get(&note.id) {
+            None => Some((note.creation_date, NoteAction::Created)),
+            Some(prev) if prev.status != note.status && note.status == NoteStatus::Archived => {
+                Some((
+                    note.updated_date.unwrap_or(note.creation_date),
+                    NoteAction::Archived,
+                ))
+            }
+            Some(prev) if prev.text != note.text => Some((
+                note.updated_date.unwrap_or(note.creation_date),
+                NoteAction::Edited,
+            )),
+            Some(_) => None,
+        };
+        if let Some((time, action)) = action {
+            history.push((time, note.clone(), action));
+        }
+        previous.insert(note.id, note);
+    }
+    history
+}
+
+pub fn local_date(time: SystemTime) -> NaiveDate {
+    DateTime::<Local>::from(time).date_naive()
+}
+
+pub fn format_duration(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        s if s >= 3600 => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+        s if s >= 60 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+#[cfg(test)]
+mod timeline_test {
+    use super::*;
+    use notify::event::{AccessKind, ModifyKind};
+
+    fn app_event(title: &str, start: SystemTime, secs: u64) -> ActiveProcessEvent {
+        serde_json::from_value(serde_json::json!({
+            "process": {
+                "title": title,
+                "app_name": title,
+                "window_id": "1",
+                "process_path": "/bin/app",
+                "process_id": 1,
+            },
+            "active_start_time": start,
+            "active_duration": Duration::from_secs(secs),
+        }))
+        .unwrap()
+    }
+
+    fn file_event(path: &str, kind: EventKind, timestamp: SystemTime) -> FileEvent {
+        FileEvent {
+            event: notify::Event::new(kind).add_path(PathBuf::from(path)),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn entries_are_correlated_with_the_focused_app() {
+        let start = SystemTime::now() - Duration::from_secs(60);
+        let apps = vec![
+            app_event("editor", start, 20),
+            app_event("browser", start + Duration::from_secs(20), 20),
+        ];
+        let modify = EventKind::Modify(ModifyKind::Any);
+        let files = vec![
+            file_event("/src/main.rs", modify, start + Duration::from_secs(5)),
+            file_event("/src/main.rs", modify, start + Duration::from_secs(6)),
+            file_event("/src/main.rs", EventKind::Access(AccessKind::Any), start),
+            file_event("/tmp/page.html", modify, start + Duration::from_secs(25)),
+        ];
+        let mut note = Note::new("read the docs", vec![]);
+        note.creation_date = start + Duration::from_secs(30);
+        let date = local_date(start);
+
+        let timeline = Timeline::from_records(date, apps, files, vec![note]);
+        let summary: Vec<(bool, Option<&str>)> = timeline
+            .entries
+            .iter()
+            .map(|entry| (entry.is_app_focus(), entry.app_title.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (true, Some("editor")),
+                (false, Some("editor")),
+                (true, Some("browser")),
+                (false, Some("browser")),
+                (false, Some("browser")),
+            ]
+        );
+    }
+
+    #[test]
+    fn note_history_replays_edits_and_archives() {
+        let note = Note::new("first", vec![]);
+        let mut edited = note.clone();
+        edited.text = "second".to_string();
+        let mut archived = edited.clone();
+        archived.status = NoteStatus::Archived;
+
+        let actions: Vec<NoteAction> = note_history(vec![note, edited.clone(), edited, archived])
+            .into_iter()
+            .map(|(_, _, action)| action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                NoteAction::Created,
+                NoteAction::Edited,
+                NoteAction::Archived
+            ]
+        );
+    }
+}
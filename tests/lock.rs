@@ -0,0 +1,44 @@
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use rarian::lock::DataLock;
+use rarian::{NoteTaker, RarianError, Task};
+
+fn rarian(data_path: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rarian"))
+        .arg("--data-path")
+        .arg(data_path)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn commands_that_write_fail_while_rarian_runs_and_the_others_read() {
+    let data_path = PathBuf::from("./testData/lock_cli");
+    let _ = remove_dir_all(&data_path);
+    create_dir_all(&data_path).unwrap();
+    let mut note_taker = NoteTaker::new(&data_path).unwrap();
+    let id = note_taker
+        .add_note("Water the plants", vec!["Garden".to_string()])
+        .unwrap();
+    note_taker.set_task(&id, Some(Task::default())).unwrap();
+
+    let lock = DataLock::acquire(&data_path).unwrap();
+    let tasks = rarian(&data_path, &["tasks"]);
+    let purge = rarian(&data_path, &["purge", "--before", "2000-01-01"]);
+    drop(lock);
+    let purge_after = rarian(&data_path, &["purge", "--before", "2000-01-01"]);
+    let other_process = DataLock::acquire(&data_path);
+    remove_dir_all(&data_path).unwrap();
+
+    assert!(tasks.status.success());
+    assert!(String::from_utf8_lossy(&tasks.stdout).contains("Water the plants"));
+    assert!(!purge.status.success());
+    let error = String::from_utf8_lossy(&purge.stderr);
+    assert!(error.contains("in use by another rarian"), "{}", error);
+    assert!(error.contains(&std::process::id().to_string()), "{}", error);
+    assert!(purge_after.status.success());
+    assert!(!matches!(other_process, Err(RarianError::InUse { .. })));
+}
@@ -0,0 +1,44 @@
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::PathBuf;
+
+use rarian::{NoteTaker, RarianError};
+use ulid::Ulid;
+
+fn data_path(test_id: &str) -> PathBuf {
+    let path = PathBuf::from("./testData").join(test_id);
+    create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn notes_are_reloaded_from_the_data_path() {
+    let path = data_path("note_store_reload");
+    let mut note_taker = NoteTaker::new(&path).unwrap();
+    let kept = note_taker
+        .add_note("kept", vec!["editor".to_string()])
+        .unwrap();
+    let archived = note_taker
+        .add_note("archived", vec!["editor".to_string()])
+        .unwrap();
+    note_taker.edit_note(&kept, "kept and edited").unwrap();
+    note_taker.archive_note(&archived).unwrap();
+
+    let note_taker = NoteTaker::new(&path).unwrap();
+    remove_dir_all(&path).unwrap();
+    let texts: Vec<String> = note_taker
+        .get_app_notes("editor")
+        .into_iter()
+        .map(|note| note.text)
+        .collect();
+    assert_eq!(texts, vec!["kept and edited".to_string()]);
+}
+
+#[test]
+fn editing_a_missing_note_fails() {
+    let path = data_path("note_store_missing");
+    let mut note_taker = NoteTaker::new(&path).unwrap();
+    let id = Ulid::new();
+    let result = note_taker.edit_note(&id, "text");
+    remove_dir_all(&path).unwrap();
+    assert!(matches!(result, Err(RarianError::NoteNotFound(missing)) if missing == id));
+}
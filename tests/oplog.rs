@@ -0,0 +1,104 @@
+use std::fs::{create_dir_all, remove_dir_all};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use rarian::oplog::{FolderTransport, StreamTransport};
+use rarian::{NoteStatus, NoteTaker};
+
+fn device(root: &Path, name: &str) -> NoteTaker {
+    let data_path = root.join(name);
+    create_dir_all(&data_path).unwrap();
+    NoteTaker::new(&data_path).unwrap()
+}
+
+fn replicate(note_taker: &mut NoteTaker, shared: &Path) {
+    let device = note_taker.replica().device().to_string();
+    let mut transport = FolderTransport::new(shared.to_path_buf(), &device);
+    note_taker.replicate(&mut transport).unwrap();
+}
+
+#[test]
+fn devices_replicating_through_a_shared_folder_converge() {
+    let root = PathBuf::from("./testData/oplog_folder");
+    let _ = remove_dir_all(&root);
+    let shared = root.join("shared");
+    let mut devices = [
+        device(&root, "laptop"),
+        device(&root, "desktop"),
+        device(&root, "phone"),
+    ];
+
+    let id = devices[0]
+        .add_note("Release plan", vec!["Code".to_string()])
+        .unwrap();
+    for note_taker in devices.iter_mut() {
+        replicate(note_taker, &shared);
+    }
+    assert_eq!(devices[2].get_note(&id).unwrap().text, "Release plan");
+
+    // Every device changes the note before hearing from the others
+    devices[0].edit_note(&id, "Release plan\n- tag").unwrap();
+    devices[1].boost_link(&id, "Firefox", 1).unwrap();
+    devices[2].archive_note(&id).unwrap();
+    let other = devices[2].add_note("Standup", vec![]).unwrap();
+    for note_taker in devices.iter_mut().rev() {
+        replicate(note_taker, &shared);
+    }
+    for note_taker in devices.iter_mut() {
+        replicate(note_taker, &shared);
+    }
+    let notes: Vec<_> = devices
+        .iter()
+        .map(|note_taker| note_taker.get_note(&id).unwrap().clone())
+        .collect();
+    let others = devices
+        .iter()
+        .all(|note_taker| note_taker.get_note(&other).is_some());
+    // The op log survives a restart
+    let reloaded = NoteTaker::new(&root.join("phone")).unwrap();
+    let reloaded_state = reloaded.replica().state(&id);
+    remove_dir_all(&root).unwrap();
+
+    assert!(others);
+    for note in notes.iter() {
+        assert_eq!(note.text, "Release plan\n- tag");
+        assert_eq!(note.status, NoteStatus::Archived);
+        assert!(note.link_to("Code").is_some());
+        assert!(note.link_to("Firefox").is_some());
+    }
+    assert_eq!(
+        reloaded_state.map(|state| state.text),
+        Some(notes[0].text.clone())
+    );
+}
+
+#[test]
+fn devices_replicate_over_a_connection() {
+    let root = PathBuf::from("./testData/oplog_stream");
+    let _ = remove_dir_all(&root);
+    let mut listening = device(&root, "desktop");
+    let mut connecting = device(&root, "laptop");
+    let listened = listening.add_note("Listened", vec![]).unwrap();
+    let connected = connecting.add_note("Connected", vec![]).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        listening
+            .replicate(&mut StreamTransport::new(stream, false))
+            .unwrap();
+        listening
+    });
+    let stream = TcpStream::connect(address).unwrap();
+    let changed = connecting
+        .replicate(&mut StreamTransport::new(stream, true))
+        .unwrap();
+    let listening = server.join().unwrap();
+    remove_dir_all(&root).unwrap();
+
+    assert_eq!(changed, vec![listened]);
+    assert_eq!(listening.get_note(&connected).unwrap().text, "Connected");
+    assert_eq!(connecting.get_note(&listened).unwrap().text, "Listened");
+}
@@ -0,0 +1,341 @@
+use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::Local;
+use rarian::bus::{
+    AddNote, AppNotes, ArchiveNote, CurrentApp, EditNote, GetTimeline, Message, SetReminder,
+};
+use rarian::notes::Note;
+use rarian::reminders::{Notifier, NotifierKind, Reminder, Trigger};
+use rarian::{Bus, Config, Middleware, Notification, Rarian, RarianError, Result, TimelineKind};
+use ulid::Ulid;
+
+fn config(test_id: &str) -> Config {
+    let data_path = PathBuf::from("./testData").join(test_id);
+    create_dir_all(&data_path).unwrap();
+    Config {
+        data_path,
+        watcher_paths: vec![],
+        ignore_paths: vec![],
+        comment_identifier: "@#$".to_string(),
+        sleep_duration: Duration::from_millis(16),
+        audit_log: false,
+        notifier: NotifierKind::Off,
+        sync: None,
+        retention: Default::default(),
+        backup: Default::default(),
+    }
+}
+
+/// Waits for the next notes change, skipping the app changes the gatherer reports meanwhile
+fn wait_for_notes_changed(notifications: &Receiver<Notification>) {
+    loop {
+        match notifications.recv_timeout(Duration::from_secs(5)) {
+            Ok(Notification::NotesChanged) => return,
+            Ok(Notification::Error(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => {}
+            Err(err) => panic!("no notes change: {}", err),
+        }
+    }
+}
+
+fn add_note(bus: &Bus, text: &str) -> Result<Ulid> {
+    bus.request(AddNote {
+        text: text.to_string(),
+        links: vec!["terminal".to_string()],
+    })
+}
+
+#[test]
+fn notes_added_on_the_bus_are_queried_and_notified() {
+    let config = config("service_notes");
+    let rarian = Rarian::start(&config).unwrap();
+    let bus = rarian.bus();
+    let notifications = bus.subscribe().unwrap();
+    assert!(matches!(
+        notifications.recv_timeout(Duration::from_secs(5)),
+        Ok(Notification::CurrentAppChanged(_))
+    ));
+
+    let id = add_note(&bus, "write the tests").unwrap();
+    wait_for_notes_changed(&notifications);
+    let link = "terminal".to_string();
+    let notes = bus.request(AppNotes { link: link.clone() }).unwrap();
+    assert_eq!(
+        notes.iter().map(|note| note.id).collect::<Vec<_>>(),
+        vec![id]
+    );
+
+    bus.request(ArchiveNote { id }).unwrap();
+    wait_for_notes_changed(&notifications);
+    assert!(bus.request(AppNotes { link }).unwrap().is_empty());
+
+    let date = Local::now().date_naive();
+    let timeline = bus.request(GetTimeline { date }).unwrap();
+    let note_entries = timeline
+        .entries
+        .iter()
+        .filter(|entry| matches!(entry.kind, TimelineKind::Note { .. }))
+        .count();
+    rarian.stop();
+    remove_dir_all(&config.data_path).unwrap();
+    assert_eq!(note_entries, 2);
+}
+
+#[test]
+fn failed_changes_are_returned_to_the_requester() {
+    let config = config("service_errors");
+    let rarian = Rarian::start(&config).unwrap();
+    let id = Ulid::new();
+    let result = rarian.bus().request(EditNote {
+        id,
+        text: "text".to_string(),
+    });
+    rarian.stop();
+    remove_dir_all(&config.data_path).unwrap();
+    assert!(matches!(result, Err(RarianError::NoteNotFound(missing)) if missing == id));
+}
+
+#[derive(Clone, Default)]
+struct RecordNames(Arc<Mutex<Vec<(&'static str, bool)>>>);
+
+impl Middleware for RecordNames {
+    fn after(&mut self, name: &'static str, result: &Result<()>, _elapsed: Duration) {
+        self.0.lock().unwrap().push((name, result.is_ok()));
+    }
+}
+
+#[test]
+fn middleware_sees_every_change_and_whether_it_failed() {
+    let mut config = config("service_middleware");
+    config.audit_log = true;
+    let names = RecordNames::default();
+    let rarian = Rarian::start_with_middleware(&config, vec![Box::new(names.clone())]).unwrap();
+    let bus = rarian.bus();
+    add_note(&bus, "audited").unwrap();
+    let _ = bus.request(ArchiveNote { id: Ulid::new() });
+    rarian.stop();
+
+    let changes: Vec<(&str, bool)> = names
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(name, _)| name.ends_with("Note"))
+        .cloned()
+        .collect();
+    assert_eq!(changes, vec![("AddNote", true), ("ArchiveNote", false)]);
+
+    // `start_with_middleware` only runs the middleware it is given
+    let audit_path = config.data_path.join("audit.json");
+    let audited = read_to_string(&audit_path).unwrap_or_default();
+    remove_dir_all(&config.data_path).unwrap();
+    assert!(audited.is_empty());
+}
+
+#[test]
+fn the_audit_log_records_changes_and_their_errors() {
+    let mut config = config("service_audit");
+    config.audit_log = true;
+    let rarian = Rarian::start(&config).unwrap();
+    let bus = rarian.bus();
+    add_note(&bus, "audited").unwrap();
+    let _ = bus.request(ArchiveNote { id: Ulid::new() });
+    rarian.stop();
+
+    let audited = read_to_string(config.data_path.join("audit.json")).unwrap();
+    remove_dir_all(&config.data_path).unwrap();
+    let lines: Vec<&str> = audited.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains("Added") && lines[0].contains("\"error\":null"));
+    assert!(lines[1].contains("Archived") && lines[1].contains("could not find note"));
+}
+
+#[test]
+fn requests_time_out_when_nothing_answers() {
+    let (tx, rx) = channel::<Message>();
+    let bus = Bus::new(tx).with_timeout(Duration::from_millis(10));
+    let result = bus.request(CurrentApp);
+    assert!(matches!(result, Err(RarianError::Timeout("CurrentApp"))));
+    drop(rx);
+    assert!(matches!(
+        bus.request(CurrentApp),
+        Err(RarianError::Disconnected)
+    ));
+}
+
+struct RecordingNotifier(Arc<Mutex<Vec<String>>>);
+
+impl Notifier for RecordingNotifier {
+    fn notify(&mut self, note: &Note) -> Result<()> {
+        self.0.lock().unwrap().push(note.text.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn due_reminders_are_delivered_once_to_the_notifier_and_subscribers() {
+    let config = config("service_reminders");
+    let delivered = Arc::new(Mutex::new(Vec::new()));
+    let notifier = Box::new(RecordingNotifier(delivered.clone()));
+    let rarian = Rarian::start_with_notifier(&config, Vec::new(), notifier).unwrap();
+    let bus = rarian.bus();
+    let notifications = bus.subscribe().unwrap();
+
+    let id = add_note(&bus, "call back").unwrap();
+    let later = add_note(&bus, "not yet").unwrap();
+    let now = SystemTime::now();
+    let snooze = |time| Some(Reminder::new(Trigger::Snooze(time)));
+    bus.request(SetReminder {
+        id: later,
+        reminder: snooze(now + Duration::from_secs(3600)),
+    })
+    .unwrap();
+    bus.request(SetReminder {
+        id,
+        reminder: snooze(now - Duration::from_secs(1)),
+    })
+    .unwrap();
+    let due = loop {
+        match notifications.recv_timeout(Duration::from_secs(5)) {
+            Ok(Notification::ReminderDue(note)) => break note,
+            Ok(Notification::Error(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => {}
+            Err(err) => panic!("no reminder: {}", err),
+        }
+    };
+    let notes = bus
+        .request(AppNotes {
+            link: "terminal".to_string(),
+        })
+        .unwrap();
+    rarian.stop();
+    remove_dir_all(&config.data_path).unwrap();
+
+    assert_eq!(due.id, id);
+    assert_eq!(*delivered.lock().unwrap(), vec!["call back".to_string()]);
+    let due_notes: Vec<Ulid> = notes
+        .iter()
+        .filter(|note| note.reminder_is_due())
+        .map(|note| note.id)
+        .collect();
+    assert_eq!(due_notes, vec![id]);
+}
+
+/// Waits for the next notification `select` picks, skipping the ones it doesn't
+fn wait_for<T>(
+    notifications: &Receiver<Notification>,
+    mut select: impl FnMut(Notification) -> Option<T>,
+) -> T {
+    loop {
+        match notifications.recv_timeout(Duration::from_secs(5)) {
+            Ok(notification) => {
+                if let Some(selected) = select(notification) {
+                    return selected;
+                }
+            }
+            Err(err) => panic!("no notification: {}", err),
+        }
+    }
+}
+
+#[test]
+fn config_changes_apply_while_running_and_invalid_ones_are_reported() {
+    let config = config("service_config");
+    let watched = PathBuf::from("./testData/service_config_watched");
+    let _ = remove_dir_all(&watched);
+    create_dir_all(&watched).unwrap();
+    let config_path = config.data_path.join("config.toml");
+    let rarian = Rarian::start(&config).unwrap();
+    let bus = rarian.bus();
+    let notifications = bus.subscribe().unwrap();
+
+    let mut changed = config.clone();
+    changed.watcher_paths = vec![watched.clone()];
+    changed.comment_identifier = "%%".to_string();
+    changed.sleep_duration = Duration::from_millis(50);
+    std::fs::write(&config_path, toml::to_string(&changed).unwrap()).unwrap();
+    let applied = wait_for(&notifications, |notification| match notification {
+        Notification::ConfigChanged(config) => Some(config),
+        Notification::Error(err) => panic!("unexpected error: {}", err),
+        _ => None,
+    });
+    // The file gatherer restarts watching the new path with the new identifier
+    std::thread::sleep(Duration::from_millis(200));
+    let code = watched.join("code.txt");
+    std::fs::write(&code, "let x = 1; %%rename x%%").unwrap();
+    wait_for_notes_changed(&notifications);
+    let stripped = read_to_string(&code).unwrap();
+
+    std::fs::write(&config_path, "sleep_duration = 3\n").unwrap();
+    let error = wait_for(&notifications, |notification| match notification {
+        Notification::Error(err) => Some(err),
+        _ => None,
+    });
+
+    // A config whose notifier can't start applies none of its changes
+    create_dir_all(config.data_path.join("reminders.json")).unwrap();
+    let mut unusable = changed.clone();
+    unusable.notifier = NotifierKind::Log;
+    unusable.sleep_duration = Duration::from_millis(70);
+    std::fs::write(&config_path, toml::to_string(&unusable).unwrap()).unwrap();
+    let unusable_error = wait_for(&notifications, |notification| match notification {
+        Notification::ConfigChanged(config) => panic!("applied {:?}", config),
+        Notification::Error(err) => Some(err),
+        _ => None,
+    });
+    let notes = bus
+        .request(AppNotes {
+            link: code.canonicalize().unwrap().to_string_lossy().to_string(),
+        })
+        .unwrap();
+    rarian.stop();
+    remove_dir_all(&config.data_path).unwrap();
+    remove_dir_all(&watched).unwrap();
+
+    assert_eq!(applied, changed);
+    assert_eq!(stripped, "let x = 1; \n");
+    assert!(
+        matches!(error, RarianError::InvalidConfig { .. }),
+        "{}",
+        error
+    );
+    assert!(notes.iter().any(|note| note.text == "rename x"));
+    assert!(
+        matches!(unusable_error, RarianError::OpenCache { .. }),
+        "{}",
+        unusable_error
+    );
+}
+
+#[test]
+fn lines_that_cant_be_read_are_reported_to_subscribers() {
+    let config = config("service_unreadable");
+    std::fs::write(
+        config.data_path.join("notes.json"),
+        "not a note\n\n{\"broken\": true}\n",
+    )
+    .unwrap();
+    let rarian = Rarian::start(&config).unwrap();
+    let notifications = rarian.bus().subscribe().unwrap();
+    let reported = loop {
+        match notifications.recv_timeout(Duration::from_secs(5)) {
+            Ok(Notification::Error(err)) => break err,
+            Ok(_) => {}
+            Err(err) => panic!("no error reported: {}", err),
+        }
+    };
+    rarian.stop();
+    remove_dir_all(&config.data_path).unwrap();
+
+    match reported {
+        RarianError::UnreadableRecords { path, count } => {
+            assert!(path.ends_with("notes.json"));
+            assert_eq!(count, 2);
+        }
+        other => panic!("expected unreadable records, got {:?}", other),
+    }
+}
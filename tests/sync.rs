@@ -0,0 +1,130 @@
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rarian::sync::{apply, GitSync, SyncConfig};
+use rarian::NoteTaker;
+
+fn device(root: &Path, name: &str, remote: &Path) -> (NoteTaker, GitSync) {
+    let data_path = root.join(name);
+    create_dir_all(&data_path).unwrap();
+    let config = SyncConfig {
+        remote: remote.canonicalize().unwrap().to_string_lossy().to_string(),
+        branch: "main".to_string(),
+        interval_minutes: None,
+    };
+    (
+        NoteTaker::new(&data_path).unwrap(),
+        GitSync::new(&data_path, config),
+    )
+}
+
+fn sync(note_taker: &mut NoteTaker, git_sync: &GitSync) -> Vec<ulid::Ulid> {
+    let notes: Vec<_> = note_taker.notes().cloned().collect();
+    let outcome = git_sync.sync(&notes).unwrap();
+    apply(note_taker, &outcome).unwrap();
+    outcome.conflicts
+}
+
+#[test]
+fn devices_sync_through_a_bare_repository_and_converge() {
+    let root = PathBuf::from("./testData/sync");
+    let _ = remove_dir_all(&root);
+    let remote = root.join("remote.git");
+    create_dir_all(&remote).unwrap();
+    let status = Command::new("git")
+        .args(["init", "--quiet", "--bare"])
+        .arg(&remote)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let (mut laptop, laptop_sync) = device(&root, "laptop", &remote);
+    let (mut desktop, desktop_sync) = device(&root, "desktop", &remote);
+
+    let id = laptop
+        .add_note("Release plan", vec!["Code".to_string()])
+        .unwrap();
+    sync(&mut laptop, &laptop_sync);
+    sync(&mut desktop, &desktop_sync);
+    assert_eq!(desktop.get_note(&id).unwrap().text, "Release plan");
+
+    // Edits of different notes, and of different parts of the same note, merge cleanly
+    let other = desktop.add_note("Standup", vec![]).unwrap();
+    desktop.archive_note(&id).unwrap();
+    laptop.boost_link(&id, "Firefox", 3).unwrap();
+    sync(&mut laptop, &laptop_sync);
+    assert!(sync(&mut desktop, &desktop_sync).is_empty());
+    sync(&mut laptop, &laptop_sync);
+    for note_taker in [&laptop, &desktop] {
+        let note = note_taker.get_note(&id).unwrap();
+        assert_eq!(note.status, rarian::NoteStatus::Archived);
+        assert!(note.link_to("Firefox").is_some());
+        assert!(note_taker.get_note(&other).is_some());
+    }
+
+    // Both devices editing the text is a conflict, with the same result on both
+    laptop.edit_note(&id, "Release plan\n- tag").unwrap();
+    desktop.edit_note(&id, "Release plan\n- wait").unwrap();
+    sync(&mut laptop, &laptop_sync);
+    let conflicts = sync(&mut desktop, &desktop_sync);
+    sync(&mut laptop, &laptop_sync);
+    let (laptop_note, desktop_note) = (
+        laptop.get_note(&id).unwrap(),
+        desktop.get_note(&id).unwrap(),
+    );
+    remove_dir_all(&root).unwrap();
+    assert_eq!(conflicts, vec![id]);
+    assert_eq!(laptop_note.text, "Release plan\n- wait");
+    assert_eq!(laptop_note.text, desktop_note.text);
+    assert_eq!(laptop_note.conflict.as_deref(), Some("Release plan\n- tag"));
+    assert_eq!(laptop_note.conflict, desktop_note.conflict);
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?}", args);
+}
+
+#[test]
+fn a_merge_that_fails_is_aborted() {
+    let root = PathBuf::from("./testData/sync_abort");
+    let _ = remove_dir_all(&root);
+    let remote = root.join("remote.git");
+    create_dir_all(&remote).unwrap();
+    git(&root, &["init", "--quiet", "--bare", "remote.git"]);
+    let (mut laptop, laptop_sync) = device(&root, "laptop", &remote);
+    laptop.add_note("Release plan", vec![]).unwrap();
+    sync(&mut laptop, &laptop_sync);
+
+    // Another device pushes a note that can't be read
+    git(
+        &root,
+        &[
+            "clone",
+            "--quiet",
+            "--branch",
+            "main",
+            "remote.git",
+            "other",
+        ],
+    );
+    let other = root.join("other");
+    std::fs::write(other.join("notes").join("broken.json"), "not a note").unwrap();
+    git(&other, &["add", "--all"]);
+    git(&other, &["commit", "--quiet", "-m", "Broken note"]);
+    git(&other, &["push", "--quiet"]);
+
+    laptop.add_note("Standup", vec![]).unwrap();
+    let notes: Vec<_> = laptop.notes().cloned().collect();
+    let failed = laptop_sync.sync(&notes);
+    let merging = root.join("laptop/sync/.git/MERGE_HEAD").exists();
+    remove_dir_all(&root).unwrap();
+
+    assert!(failed.is_err());
+    assert!(!merging);
+}